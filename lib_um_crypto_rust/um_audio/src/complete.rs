@@ -0,0 +1,89 @@
+use crate::flac;
+use crate::metadata::get_header_metadata_size;
+use crate::mp3::Mp3FrameHeader;
+use crate::AudioType;
+use byteorder::{ByteOrder, LE};
+
+/// Check if the (decrypted) audio in `data` looks complete, i.e. not truncated.
+///
+/// * FLAC: the last frame must be complete and pass its CRC check.
+/// * MP3 (best-effort): walking the frames must not run past the end of the data.
+/// * WAV: the data must be at least as long as the RIFF size declares.
+/// * Other formats are not checked, and always report `true`.
+pub fn is_complete(data: &[u8], format: AudioType) -> bool {
+    match format {
+        AudioType::FLAC => match get_header_metadata_size(data, 0) {
+            Ok(offset) => flac::ends_with_complete_frame(&data[offset..]),
+            Err(_) => false,
+        },
+        AudioType::MP3 => match get_header_metadata_size(data, 0) {
+            Ok(offset) => is_mp3_complete(&data[offset..]),
+            Err(_) => false,
+        },
+        AudioType::WAV => is_wav_complete(data),
+        _ => true,
+    }
+}
+
+fn is_mp3_complete(data: &[u8]) -> bool {
+    let mut offset = 0;
+    while offset < data.len() {
+        let frame = &data[offset..];
+        match Mp3FrameHeader::parse(frame) {
+            Some(header) => offset += header.frame_len(),
+            // Trailing tags, or something we don't understand: stop here.
+            None if frame.len() >= 4 => return true,
+            // A partial frame header.
+            None => return false,
+        }
+    }
+
+    offset == data.len()
+}
+
+fn is_wav_complete(data: &[u8]) -> bool {
+    if data.len() < 8 || !data.starts_with(b"RIFF") {
+        return false;
+    }
+
+    let riff_len = LE::read_u32(&data[4..8]) as usize;
+    data.len() >= riff_len.saturating_add(8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flac::tests::make_flac;
+    use crate::mp3::tests::make_mp3_frame;
+
+    fn make_mp3(frames: usize) -> Vec<u8> {
+        make_mp3_frame().repeat(frames)
+    }
+
+    #[test]
+    fn test_flac() {
+        let data = make_flac();
+        assert!(is_complete(&data, AudioType::FLAC));
+        assert!(!is_complete(&data[..data.len() - 1], AudioType::FLAC));
+    }
+
+    #[test]
+    fn test_mp3() {
+        let mut data = make_mp3(3);
+        assert!(is_complete(&data, AudioType::MP3));
+        assert!(!is_complete(&data[..data.len() - 100], AudioType::MP3));
+        assert!(!is_complete(&data[..417 * 2 + 2], AudioType::MP3));
+
+        data.extend_from_slice(b"TAG");
+        data.extend_from_slice(&[0u8; 125]);
+        assert!(is_complete(&data, AudioType::MP3));
+    }
+
+    #[test]
+    fn test_wav() {
+        let mut data = b"RIFF\x0c\x00\x00\x00WAVEdata\x00\x00\x00\x00".to_vec();
+        assert!(is_complete(&data, AudioType::WAV));
+        data.truncate(15);
+        assert!(!is_complete(&data, AudioType::WAV));
+    }
+}
@@ -0,0 +1,167 @@
+//! Duration estimates read from the headers of the (decrypted) audio, without
+//! decoding it.
+
+use crate::flac::MAGIC_FLAC;
+use crate::metadata::get_header_metadata_size;
+use crate::mp3::{Mp3FrameHeader, MpegVersion};
+use crate::AudioType;
+use byteorder::{ByteOrder, BE};
+use std::time::Duration;
+
+const FLAC_BLOCK_STREAMINFO: u8 = 0;
+const ID3V1_LEN: usize = 128;
+
+/// Estimate the duration of the audio in `data`, a whole decrypted file.
+///
+/// * FLAC: total samples and sample rate of the `STREAMINFO` block.
+/// * MP3: frame count of the Xing/Info or VBRI header of the first frame, or the
+///   size of the audio at the bitrate of the first frame (exact for CBR files).
+/// * Other formats are not supported, and always return `None`.
+///
+/// returns: `None` if the headers are missing or don't give the duration.
+pub fn estimate_duration(data: &[u8], format: AudioType) -> Option<Duration> {
+    match format {
+        AudioType::FLAC => estimate_flac_duration(data),
+        AudioType::MP3 => estimate_mp3_duration(data),
+        _ => None,
+    }
+}
+
+fn estimate_flac_duration(data: &[u8]) -> Option<Duration> {
+    // offset    value
+    //      0    magic("fLaC")
+    //      4    u8(block_type) u24(block_len)
+    //      8    u16(min_block) u16(max_block) u24(min_frame) u24(max_frame)
+    //     18    u20(sample_rate) u3(channels) u5(bits_per_sample) u36(total_samples)
+    if !data.starts_with(&MAGIC_FLAC) || data.get(4)? & 0x7F != FLAC_BLOCK_STREAMINFO {
+        return None;
+    }
+    let packed = BE::read_u64(data.get(18..26)?);
+    let sample_rate = (packed >> 44) as u32;
+    let total_samples = packed & ((1 << 36) - 1);
+    samples_to_duration(total_samples, sample_rate)
+}
+
+fn estimate_mp3_duration(data: &[u8]) -> Option<Duration> {
+    let start = get_header_metadata_size(data, 0).ok()?;
+    let frame = data.get(start..)?;
+    let header = Mp3FrameHeader::parse(frame)?;
+
+    if let Some(frames) = find_vbr_frame_count(frame, &header) {
+        let samples_per_frame = match (header.layer, header.version) {
+            (1, _) => 384,
+            (3, MpegVersion::V2 | MpegVersion::V2_5) => 576,
+            _ => 1152,
+        };
+        return samples_to_duration(frames as u64 * samples_per_frame, header.sample_rate);
+    }
+
+    let mut audio_len = frame.len();
+    if audio_len >= ID3V1_LEN && frame[audio_len - ID3V1_LEN..].starts_with(b"TAG") {
+        audio_len -= ID3V1_LEN;
+    }
+    let nanos = audio_len as u128 * 8 * 1_000_000 / header.bitrate as u128;
+    Some(Duration::from_nanos(u64::try_from(nanos).ok()?))
+}
+
+/// Frame count of the Xing/Info or VBRI header in the first `frame`, if any.
+fn find_vbr_frame_count(frame: &[u8], header: &Mp3FrameHeader) -> Option<u32> {
+    // The Xing/Info header follows the side information, whose size depends on the
+    // version and channel mode; the VBRI header is always 32 bytes in.
+    let is_mono = frame[3] >> 6 == 0b11;
+    let side_info_len = match (header.version, is_mono) {
+        (MpegVersion::V1, false) => 32,
+        (MpegVersion::V1, true) | (_, false) => 17,
+        (_, true) => 9,
+    };
+
+    let xing = frame.get(4 + side_info_len..)?;
+    if xing.starts_with(b"Xing") || xing.starts_with(b"Info") {
+        // u32(flags), then u32(frames) if flags & 1
+        let flags = BE::read_u32(xing.get(4..8)?);
+        return match flags & 1 {
+            0 => None,
+            _ => Some(BE::read_u32(xing.get(8..12)?)),
+        };
+    }
+
+    // magic("VBRI") u16(version) u16(delay) u16(quality) u32(bytes) u32(frames)
+    let vbri = frame.get(4 + 32..)?;
+    match vbri.starts_with(b"VBRI") {
+        true => Some(BE::read_u32(vbri.get(14..18)?)),
+        false => None,
+    }
+}
+
+fn samples_to_duration(samples: u64, sample_rate: u32) -> Option<Duration> {
+    if samples == 0 || sample_rate == 0 {
+        return None;
+    }
+    let sample_rate = sample_rate as u64;
+    let nanos = (samples % sample_rate) * 1_000_000_000 / sample_rate;
+    Some(Duration::new(samples / sample_rate, nanos as u32))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mp3::tests::make_mp3_frame;
+
+    #[test]
+    fn test_cbr_mp3_from_bitrate() {
+        let mut data = b"ID3\x04\x00\x00\x00\x00\x00\x10".to_vec();
+        data.extend_from_slice(&[0u8; 0x10]);
+        data.extend(make_mp3_frame().repeat(100));
+        data.extend_from_slice(b"TAG");
+        data.extend_from_slice(&[0u8; 125]);
+
+        // 41700 bytes at 128 kbit/s.
+        assert_eq!(
+            estimate_duration(&data, AudioType::MP3),
+            Some(Duration::from_micros(2_606_250))
+        );
+    }
+
+    #[test]
+    fn test_vbr_mp3_from_xing_header() {
+        let mut frame = make_mp3_frame();
+        frame[36..40].copy_from_slice(b"Xing");
+        frame[40..44].copy_from_slice(&1u32.to_be_bytes());
+        frame[44..48].copy_from_slice(&1000u32.to_be_bytes());
+        let mut data = frame;
+        data.extend(make_mp3_frame().repeat(3));
+
+        // 1000 frames of 1152 samples at 44.1 kHz, whatever the size of the file.
+        assert_eq!(
+            estimate_duration(&data, AudioType::MP3),
+            Some(Duration::new(26, 122_448_979))
+        );
+    }
+
+    #[test]
+    fn test_flac_from_streaminfo() {
+        let total_samples = 44100 * 3 + 22050u64;
+        let packed = 44100u64 << 44 | 1 << 41 | 15 << 36 | total_samples;
+
+        let mut data = MAGIC_FLAC.to_vec();
+        data.extend_from_slice(&[0x80, 0x00, 0x00, 0x22]);
+        data.extend_from_slice(&[0u8; 10]);
+        data.extend_from_slice(&packed.to_be_bytes());
+        data.extend_from_slice(&[0u8; 16]);
+
+        assert_eq!(
+            estimate_duration(&data, AudioType::FLAC),
+            Some(Duration::from_millis(3500))
+        );
+
+        // Unknown total samples.
+        data[22..26].fill(0);
+        assert_eq!(estimate_duration(&data, AudioType::FLAC), None);
+    }
+
+    #[test]
+    fn test_unsupported() {
+        assert_eq!(estimate_duration(b"OggS", AudioType::OGG), None);
+        assert_eq!(estimate_duration(&[0u8; 0x40], AudioType::MP3), None);
+    }
+}
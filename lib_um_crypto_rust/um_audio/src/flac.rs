@@ -0,0 +1,220 @@
+//! Minimal FLAC stream parsing.
+
+use byteorder::{ByteOrder, BE};
+use std::iter::once;
+use std::ops::Range;
+
+pub const MAGIC_FLAC: [u8; 4] = *b"fLaC";
+
+/// Number of trailing frame header candidates to try before giving up.
+const MAX_LAST_FRAME_CANDIDATES: usize = 8;
+
+/// Number of following frame header candidates to try as the end of a frame.
+const MAX_FRAME_END_CANDIDATES: usize = 8;
+
+fn crc8(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |crc, &byte| {
+        (0..8).fold(crc ^ byte, |crc, _| match crc & 0x80 {
+            0 => crc << 1,
+            _ => (crc << 1) ^ 0x07,
+        })
+    })
+}
+
+fn crc16(data: &[u8]) -> u16 {
+    data.iter().fold(0u16, |crc, &byte| {
+        (0..8).fold(crc ^ ((byte as u16) << 8), |crc, _| match crc & 0x8000 {
+            0 => crc << 1,
+            _ => (crc << 1) ^ 0x8005,
+        })
+    })
+}
+
+/// Offset of the first audio frame, i.e. right after the last metadata block.
+///
+/// `buffer` should start with the `fLaC` magic.
+pub fn find_first_frame(buffer: &[u8]) -> Option<usize> {
+    if !buffer.starts_with(&MAGIC_FLAC) {
+        return None;
+    }
+
+    let mut offset = MAGIC_FLAC.len();
+    loop {
+        let header = buffer.get(offset..offset + 4)?;
+        let is_last = header[0] & 0x80 != 0;
+        let block_len = BE::read_u24(&header[1..4]) as usize;
+        offset += 4 + block_len;
+        if offset > buffer.len() {
+            return None;
+        }
+        if is_last {
+            return Some(offset);
+        }
+    }
+}
+
+/// Length of the frame header at the start of `buffer` (including its CRC-8),
+/// if it is a valid one.
+pub fn frame_header_len(buffer: &[u8]) -> Option<usize> {
+    let header = buffer.get(..4)?;
+    if header[0] != 0xFF || header[1] & 0xFE != 0xF8 {
+        return None;
+    }
+
+    let block_size_code = header[2] >> 4;
+    let sample_rate_code = header[2] & 0x0F;
+    if block_size_code == 0 || sample_rate_code == 0x0F {
+        return None;
+    }
+
+    // Sample/frame number, coded like UTF-8.
+    let mut len = match buffer.get(4)?.leading_ones() {
+        0 => 1,
+        n @ 2..=7 => n as usize,
+        _ => return None,
+    };
+    len += 4;
+    len += match block_size_code {
+        6 => 1,
+        7 => 2,
+        _ => 0,
+    };
+    len += match sample_rate_code {
+        12 => 1,
+        13 | 14 => 2,
+        _ => 0,
+    };
+
+    let expected_crc = *buffer.get(len)?;
+    match crc8(&buffer[..len]) == expected_crc {
+        true => Some(len + 1),
+        false => None,
+    }
+}
+
+/// Check if `buffer` (starting with the `fLaC` magic) ends with a complete frame.
+///
+/// The last frame is located by scanning backwards for a valid frame header, then
+/// verified against its CRC-16 footer.
+pub fn ends_with_complete_frame(buffer: &[u8]) -> bool {
+    let first_frame = match find_first_frame(buffer) {
+        Some(offset) => offset,
+        None => return false,
+    };
+    if buffer.len() < first_frame + 2 {
+        return false;
+    }
+
+    let crc_offset = buffer.len() - 2;
+    let expected_crc = BE::read_u16(&buffer[crc_offset..]);
+    (first_frame..crc_offset)
+        .rev()
+        .filter(|&offset| frame_header_len(&buffer[offset..crc_offset]).is_some())
+        .take(MAX_LAST_FRAME_CANDIDATES)
+        .any(|offset| crc16(&buffer[offset..crc_offset]) == expected_crc)
+}
+
+/// Byte ranges of `buffer` (starting with the `fLaC` magic) that are not covered by
+/// a frame passing its CRC-16 check.
+///
+/// Each frame is expected to end right before the next valid frame header (or at the
+/// end of the buffer); when its CRC does not match, everything up to the next frame
+/// header is reported.
+pub fn find_corrupt_frames(buffer: &[u8]) -> Vec<Range<usize>> {
+    let first_frame = match find_first_frame(buffer) {
+        Some(offset) => offset,
+        None => {
+            return vec![Range {
+                start: 0,
+                end: buffer.len(),
+            }]
+        }
+    };
+
+    let is_frame_header = |offset: usize| frame_header_len(&buffer[offset..]).is_some();
+    let frame_end_candidates = |offset: usize| {
+        (offset + 1..buffer.len())
+            .filter(move |&offset| is_frame_header(offset))
+            .chain(once(buffer.len()))
+    };
+    let is_frame_end = |start: usize, end: usize| {
+        end >= start + 2 && crc16(&buffer[start..end - 2]) == BE::read_u16(&buffer[end - 2..end])
+    };
+
+    let mut corrupt = vec![];
+    let mut offset = first_frame;
+    while offset < buffer.len() {
+        let frame_end = match is_frame_header(offset) {
+            true => frame_end_candidates(offset)
+                .take(MAX_FRAME_END_CANDIDATES)
+                .find(|&end| is_frame_end(offset, end)),
+            false => None,
+        };
+
+        offset = match frame_end {
+            Some(end) => end,
+            None => {
+                let next = frame_end_candidates(offset).next().unwrap_or(buffer.len());
+                corrupt.push(offset..next);
+                next
+            }
+        };
+    }
+    corrupt
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    /// A FLAC stream with a STREAMINFO block and a single (fake) mono frame.
+    pub fn make_flac() -> Vec<u8> {
+        make_flac_frames(1, 0x40)
+    }
+
+    /// A FLAC stream with `frames` (fake) mono frames, each with `frame_data_len`
+    /// bytes of data after the header.
+    pub fn make_flac_frames(frames: usize, frame_data_len: usize) -> Vec<u8> {
+        let mut data = MAGIC_FLAC.to_vec();
+        data.extend_from_slice(&[0x80, 0x00, 0x00, 0x22]);
+        data.extend_from_slice(&[0u8; 0x22]);
+
+        for frame in 0..frames {
+            let frame_start = data.len();
+            data.extend_from_slice(&[0xFF, 0xF8, 0x19, 0x08, frame as u8 & 0x7F]);
+            data.push(crc8(&data[frame_start..]));
+            data.extend((0..frame_data_len).map(|i| i as u8));
+            let crc = crc16(&data[frame_start..]);
+            data.extend_from_slice(&crc.to_be_bytes());
+        }
+        data
+    }
+
+    #[test]
+    fn test_crc() {
+        assert_eq!(crc8(b"123456789"), 0xF4);
+        assert_eq!(crc16(b"123456789"), 0xFEE8);
+    }
+
+    #[test]
+    fn test_ends_with_complete_frame() {
+        let data = make_flac();
+        assert_eq!(find_first_frame(&data), Some(0x2a));
+        assert_eq!(frame_header_len(&data[0x2a..]), Some(6));
+        assert!(ends_with_complete_frame(&data));
+        assert!(!ends_with_complete_frame(&data[..data.len() - 5]));
+        assert!(!ends_with_complete_frame(&data[..0x2a]));
+    }
+
+    #[test]
+    fn test_find_corrupt_frames() {
+        let mut data = make_flac_frames(4, 0x40);
+        assert_eq!(find_corrupt_frames(&data), vec![]);
+
+        // Frames are 0x48 bytes each, starting at 0x2a.
+        data[0x2a + 0x48 + 0x20] ^= 0xFF;
+        assert_eq!(find_corrupt_frames(&data), vec![0x72..0xba]);
+
+        assert_eq!(find_corrupt_frames(&data[..0x10]), vec![0..0x10]);
+    }
+}
@@ -0,0 +1,137 @@
+use crate::flac;
+use crate::metadata::get_header_metadata_size;
+use crate::mp3::Mp3FrameHeader;
+use crate::AudioType;
+use std::ops::Range;
+
+/// Find the byte ranges of the (decrypted) audio in `data` that fail frame validation.
+///
+/// * FLAC: ranges not covered by a frame passing its CRC-16 check.
+/// * MP3 (best-effort): ranges where the frame sync is lost, up to where it is found again.
+/// * Other formats are not checked, and always report no ranges.
+///
+/// If the leading metadata can't be parsed, the whole of `data` is reported.
+pub fn find_corrupt_frames(data: &[u8], format: AudioType) -> Vec<Range<usize>> {
+    let find_corrupt: fn(&[u8]) -> Vec<Range<usize>> = match format {
+        AudioType::FLAC => flac::find_corrupt_frames,
+        AudioType::MP3 => find_corrupt_mp3_frames,
+        _ => return vec![],
+    };
+
+    match get_header_metadata_size(data, 0) {
+        Ok(offset) => find_corrupt(&data[offset..])
+            .into_iter()
+            .map(|range| range.start + offset..range.end + offset)
+            .collect(),
+        Err(_) => vec![Range {
+            start: 0,
+            end: data.len(),
+        }],
+    }
+}
+
+/// Count the valid frame headers found anywhere in `data`, a piece of (decrypted)
+/// audio that doesn't have to start at a frame boundary.
+///
+/// * FLAC: frame headers passing their CRC-8 check.
+/// * MP3: frame headers immediately followed by another one.
+/// * Other formats are not checked, and always count 0.
+pub fn count_frame_headers(data: &[u8], format: AudioType) -> usize {
+    let is_frame: fn(&[u8], usize) -> bool = match format {
+        AudioType::FLAC => |data, offset| flac::frame_header_len(&data[offset..]).is_some(),
+        AudioType::MP3 => |data, offset| {
+            Mp3FrameHeader::parse(&data[offset..])
+                .map(|header| offset + header.frame_len())
+                .is_some_and(|next| {
+                    Mp3FrameHeader::parse(data.get(next..).unwrap_or(&[])).is_some()
+                })
+        },
+        _ => return 0,
+    };
+    (0..data.len())
+        .filter(|&offset| is_frame(data, offset))
+        .count()
+}
+
+fn find_corrupt_mp3_frames(data: &[u8]) -> Vec<Range<usize>> {
+    let mut corrupt = vec![];
+    let mut offset = 0;
+    while offset + 4 <= data.len() {
+        let frame = &data[offset..];
+        // Trailing tags: stop here.
+        if frame.starts_with(b"TAG") || frame.starts_with(b"APETAGEX") {
+            break;
+        }
+
+        offset = match Mp3FrameHeader::parse(frame) {
+            Some(header) => offset + header.frame_len(),
+            None => {
+                let next = (offset + 1..data.len())
+                    .find(|&offset| is_mp3_synced(data, offset))
+                    .unwrap_or(data.len());
+                corrupt.push(offset..next);
+                next
+            }
+        };
+    }
+    corrupt
+}
+
+/// Check for a frame at `offset`, followed by another frame (or the end of the data).
+fn is_mp3_synced(data: &[u8], offset: usize) -> bool {
+    match Mp3FrameHeader::parse(&data[offset..]) {
+        Some(header) => {
+            let next = offset + header.frame_len();
+            next >= data.len() || Mp3FrameHeader::parse(&data[next..]).is_some()
+        }
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flac::tests::make_flac_frames;
+    use crate::mp3::tests::make_mp3_frame;
+
+    #[test]
+    fn test_mp3() {
+        let frame = make_mp3_frame();
+        let mut data = frame.repeat(5);
+        data.extend_from_slice(b"TAG");
+        data.extend_from_slice(&[0u8; 125]);
+        assert_eq!(find_corrupt_frames(&data, AudioType::MP3), vec![]);
+
+        data[417 * 2 + 1] = 0x00;
+        assert_eq!(
+            find_corrupt_frames(&data, AudioType::MP3),
+            vec![417 * 2..417 * 3]
+        );
+    }
+
+    #[test]
+    fn test_flac() {
+        let mut data = make_flac_frames(3, 0x40);
+        data[0x2a + 2] ^= 0x01;
+        assert_eq!(
+            find_corrupt_frames(&data, AudioType::FLAC),
+            vec![0x2a..0x72]
+        );
+    }
+
+    #[test]
+    fn test_count_frame_headers() {
+        let frame = make_mp3_frame();
+        let data = frame.repeat(5);
+        assert_eq!(count_frame_headers(&data[100..], AudioType::MP3), 3);
+
+        let data = make_flac_frames(3, 0x40);
+        assert_eq!(count_frame_headers(&data[0x2a + 1..], AudioType::FLAC), 2);
+        assert_eq!(count_frame_headers(&[0x55u8; 0x100], AudioType::FLAC), 0);
+    }
+
+    #[test]
+    fn test_unchecked_format() {
+        assert_eq!(find_corrupt_frames(&[0u8; 0x10], AudioType::OGG), vec![]);
+    }
+}
@@ -0,0 +1,119 @@
+use crate::flac;
+use crate::mp3::Mp3FrameHeader;
+use crate::AudioType;
+
+/// How far into the stream to search for the first frame.
+const SEARCH_WINDOW: usize = 0x10000;
+/// Number of back-to-back frames required to accept a frame based stream.
+const MIN_CONSECUTIVE_FRAMES: usize = 3;
+
+/// ADTS frame length (including its header), if `buffer` starts with an ADTS header.
+fn adts_frame_len(buffer: &[u8]) -> Option<usize> {
+    let header = buffer.get(..7)?;
+    // 12-bit sync word, then the layer (always `00`).
+    if header[0] != 0xFF || header[1] & 0xF6 != 0xF0 {
+        return None;
+    }
+
+    let len = (((header[3] & 0x03) as usize) << 11)
+        | ((header[4] as usize) << 3)
+        | ((header[5] as usize) >> 5);
+    match len {
+        0..=6 => None,
+        len => Some(len),
+    }
+}
+
+fn mp3_frame_len(buffer: &[u8]) -> Option<usize> {
+    Mp3FrameHeader::parse(buffer).map(|header| header.frame_len())
+}
+
+/// Check for `MIN_CONSECUTIVE_FRAMES` frames chained from `offset`.
+fn has_frame_chain(data: &[u8], offset: usize, frame_len: fn(&[u8]) -> Option<usize>) -> bool {
+    let mut offset = offset;
+    for _ in 0..MIN_CONSECUTIVE_FRAMES {
+        match data.get(offset..).and_then(frame_len) {
+            Some(len) => offset += len,
+            None => return false,
+        }
+    }
+    true
+}
+
+/// Check for two valid FLAC frame headers (the CRC-8 has to match).
+fn has_flac_frames(data: &[u8], offset: usize) -> bool {
+    let next = offset + 1;
+    flac::frame_header_len(&data[offset..]).is_some()
+        && (next..data.len().min(next + SEARCH_WINDOW))
+            .any(|offset| flac::frame_header_len(&data[offset..]).is_some())
+}
+
+/// Guess the format of audio data without a (recognisable) container magic,
+/// by looking for the frame structure of MP3, ADTS AAC and FLAC streams.
+///
+/// This is a fallback for when [`crate::detect_audio_type`] reports
+/// [`AudioType::Unknown`].
+pub fn guess_format_by_structure(data: &[u8]) -> Option<AudioType> {
+    let search_end = data.len().min(SEARCH_WINDOW);
+
+    for offset in 0..search_end {
+        if data[offset] != 0xFF {
+            continue;
+        }
+
+        if has_frame_chain(data, offset, adts_frame_len) {
+            return Some(AudioType::AAC);
+        }
+        if has_frame_chain(data, offset, mp3_frame_len) {
+            return Some(AudioType::MP3);
+        }
+        if has_flac_frames(data, offset) {
+            return Some(AudioType::FLAC);
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flac::tests::make_flac;
+    use crate::mp3::tests::make_mp3_frame;
+
+    #[test]
+    fn test_headerless_mp3() {
+        let frame = make_mp3_frame();
+        let mut data = vec![0x12, 0x34, 0x56];
+        data.extend(frame.repeat(4));
+
+        assert_eq!(guess_format_by_structure(&data), Some(AudioType::MP3));
+    }
+
+    #[test]
+    fn test_adts() {
+        // AAC-LC, 44.1kHz, stereo, 0x100 byte frames.
+        let mut frame = vec![0u8; 0x100];
+        frame[..7].copy_from_slice(&[0xFF, 0xF1, 0x50, 0x80, 0x20, 0x1F, 0xFC]);
+        let data = frame.repeat(4);
+
+        assert_eq!(adts_frame_len(&data), Some(0x100));
+        assert_eq!(guess_format_by_structure(&data), Some(AudioType::AAC));
+    }
+
+    #[test]
+    fn test_headerless_flac() {
+        let flac = make_flac();
+        let frame = &flac[flac::find_first_frame(&flac).unwrap()..];
+        let data = frame.repeat(2);
+
+        assert_eq!(guess_format_by_structure(&data), Some(AudioType::FLAC));
+    }
+
+    #[test]
+    fn test_unknown() {
+        let data = (0..0x1000).map(|i| (i * 7) as u8).collect::<Vec<_>>();
+        assert_eq!(guess_format_by_structure(&data), None);
+        assert_eq!(guess_format_by_structure(&[]), None);
+    }
+}
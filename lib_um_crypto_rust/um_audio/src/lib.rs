@@ -1,4 +1,21 @@
-mod metadata;
+mod complete;
+mod duration;
+mod flac;
+mod frames;
+mod guess;
+pub mod metadata;
+pub mod mp3;
+mod ogg;
+mod payload;
+mod tags;
+
+pub use complete::is_complete;
+pub use duration::estimate_duration;
+pub use frames::{count_frame_headers, find_corrupt_frames};
+pub use guess::guess_format_by_structure;
+pub use ogg::{ogg_codec, OggCodec};
+pub use payload::{wav_pcm_data, AudioPayload};
+pub use tags::AudioTags;
 
 use std::fmt::Display;
 use thiserror::Error;
@@ -12,7 +29,8 @@ pub enum AudioError {
 pub const MASK_LOSSLESS: u32 = 0x80000000;
 
 #[repr(u32)]
-#[derive(Debug, PartialEq, Eq)]
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum AudioType {
     Unknown = 0,
 
@@ -35,6 +53,11 @@ pub enum AudioType {
 
 impl AudioType {
     pub fn as_str(&self) -> &str {
+        self.extension()
+    }
+
+    /// File extension (without the leading dot) for this format.
+    pub fn extension(&self) -> &'static str {
         match self {
             AudioType::OGG => "ogg",
             AudioType::AAC => "aac",
@@ -52,6 +75,24 @@ impl AudioType {
             _ => "bin",
         }
     }
+
+    pub fn mime_type(&self) -> &'static str {
+        match self {
+            AudioType::OGG => "audio/ogg",
+            AudioType::AAC => "audio/aac",
+            AudioType::MP3 => "audio/mpeg",
+            AudioType::M4A | AudioType::M4B => "audio/mp4",
+            AudioType::MP4 => "video/mp4",
+            AudioType::WMA => "audio/x-ms-wma",
+            AudioType::FLAC => "audio/flac",
+            AudioType::DFF => "audio/x-dff",
+            AudioType::WAV => "audio/wav",
+            AudioType::APE => "audio/x-ape",
+            AudioType::MKV => "audio/x-matroska",
+
+            _ => "application/octet-stream",
+        }
+    }
 }
 
 impl Display for AudioType {
@@ -76,7 +117,7 @@ fn is_aac(magic: u32) -> bool {
     (magic & AAC_AND_MASK) == AAC_EXPECTED
 }
 
-const MAGIC_FLAC: [u8; 4] = *b"fLaC";
+const MAGIC_FLAC: [u8; 4] = flac::MAGIC_FLAC;
 const MAGIC_OGG: [u8; 4] = *b"OggS";
 const MAGIC_DFF: [u8; 4] = *b"FRM8";
 const MAGIC_WMA: [u8; 4] = [0x30, 0x26, 0xB2, 0x75];
@@ -151,4 +192,22 @@ mod tests {
         let result = detect_audio_type(mp3_data).expect("failed to parse mp3");
         assert_eq!(result, AudioType::MP3);
     }
+
+    #[test]
+    fn test_extension_and_mime_type() {
+        let cases = [
+            (AudioType::MP3, "mp3", "audio/mpeg"),
+            (AudioType::FLAC, "flac", "audio/flac"),
+            (AudioType::OGG, "ogg", "audio/ogg"),
+            (AudioType::M4A, "m4a", "audio/mp4"),
+            (AudioType::AAC, "aac", "audio/aac"),
+            (AudioType::WAV, "wav", "audio/wav"),
+            (AudioType::APE, "ape", "audio/x-ape"),
+            (AudioType::Unknown, "bin", "application/octet-stream"),
+        ];
+        for (format, ext, mime) in cases {
+            assert_eq!(format.extension(), ext);
+            assert_eq!(format.mime_type(), mime);
+        }
+    }
 }
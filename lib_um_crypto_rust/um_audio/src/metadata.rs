@@ -67,6 +67,7 @@ fn get_ape_v2_size(buffer: &[u8], offset: usize) -> Result<usize, AudioError> {
     Ok(0)
 }
 
+/// Size of the ID3v2 / APEv2 tags at `offset` of `buffer`, where the audio starts.
 pub fn get_header_metadata_size(buffer: &[u8], offset: usize) -> Result<usize, AudioError> {
     let mut offset = offset;
 
@@ -0,0 +1,142 @@
+//! MPEG audio frame header parsing.
+
+const BITRATES_V1_L1: [u32; 16] = [
+    0, 32, 64, 96, 128, 160, 192, 224, 256, 288, 320, 352, 384, 416, 448, 0,
+];
+const BITRATES_V1_L2: [u32; 16] = [
+    0, 32, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320, 384, 0,
+];
+const BITRATES_V1_L3: [u32; 16] = [
+    0, 32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320, 0,
+];
+const BITRATES_V2_L1: [u32; 16] = [
+    0, 32, 48, 56, 64, 80, 96, 112, 128, 144, 160, 176, 192, 224, 256, 0,
+];
+const BITRATES_V2_L23: [u32; 16] = [
+    0, 8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160, 0,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MpegVersion {
+    V1,
+    V2,
+    V2_5,
+}
+
+/// Parsed MPEG audio frame header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Mp3FrameHeader {
+    pub version: MpegVersion,
+    /// Layer, 1 to 3.
+    pub layer: u8,
+    /// Bitrate, in kbit/s.
+    pub bitrate: u32,
+    pub sample_rate: u32,
+    pub padding: bool,
+}
+
+impl Mp3FrameHeader {
+    /// Parse the 4 byte frame header at the start of `buffer`.
+    ///
+    /// returns: `None` if there is no valid frame header; free-format frames
+    ///          (bitrate index 0) are not supported.
+    pub fn parse(buffer: &[u8]) -> Option<Self> {
+        let header = buffer.get(..4)?;
+        if header[0] != 0xFF || header[1] & 0xE0 != 0xE0 {
+            return None;
+        }
+
+        let version = match (header[1] >> 3) & 0b11 {
+            0b00 => MpegVersion::V2_5,
+            0b10 => MpegVersion::V2,
+            0b11 => MpegVersion::V1,
+            _ => return None,
+        };
+        let layer = match (header[1] >> 1) & 0b11 {
+            0b01 => 3,
+            0b10 => 2,
+            0b11 => 1,
+            _ => return None,
+        };
+
+        let bitrate_table = match (version, layer) {
+            (MpegVersion::V1, 1) => &BITRATES_V1_L1,
+            (MpegVersion::V1, 2) => &BITRATES_V1_L2,
+            (MpegVersion::V1, _) => &BITRATES_V1_L3,
+            (_, 1) => &BITRATES_V2_L1,
+            (_, _) => &BITRATES_V2_L23,
+        };
+        let bitrate = bitrate_table[(header[2] >> 4) as usize];
+        if bitrate == 0 {
+            return None;
+        }
+
+        let sample_rate = match (header[2] >> 2) & 0b11 {
+            0 => 44100,
+            1 => 48000,
+            2 => 32000,
+            _ => return None,
+        };
+        let sample_rate = match version {
+            MpegVersion::V1 => sample_rate,
+            MpegVersion::V2 => sample_rate / 2,
+            MpegVersion::V2_5 => sample_rate / 4,
+        };
+
+        Some(Self {
+            version,
+            layer,
+            bitrate,
+            sample_rate,
+            padding: header[2] & 0b10 != 0,
+        })
+    }
+
+    /// Size of the whole frame, including the header.
+    pub fn frame_len(&self) -> usize {
+        let bitrate = (self.bitrate * 1000) as usize;
+        let sample_rate = self.sample_rate as usize;
+        let padding = self.padding as usize;
+
+        match (self.layer, self.version) {
+            (1, _) => (12 * bitrate / sample_rate + padding) * 4,
+            (3, MpegVersion::V2 | MpegVersion::V2_5) => 72 * bitrate / sample_rate + padding,
+            _ => 144 * bitrate / sample_rate + padding,
+        }
+    }
+}
+
+#[cfg(any(test, feature = "fixtures"))]
+pub mod tests {
+    use super::*;
+
+    /// 128 kbit/s, 44.1 kHz, joint stereo MPEG-1 Layer III frame.
+    pub fn make_mp3_frame() -> Vec<u8> {
+        let header = [0xFF, 0xFB, 0x90, 0x64];
+        let mut frame = vec![0u8; Mp3FrameHeader::parse(&header).unwrap().frame_len()];
+        frame[..4].copy_from_slice(&header);
+        frame
+    }
+
+    #[test]
+    fn test_parse_frame_header() {
+        let header = Mp3FrameHeader::parse(&[0xFF, 0xFB, 0x90, 0x64]).unwrap();
+        assert_eq!(header.version, MpegVersion::V1);
+        assert_eq!(header.layer, 3);
+        assert_eq!(header.bitrate, 128);
+        assert_eq!(header.sample_rate, 44100);
+        assert!(!header.padding);
+        assert_eq!(header.frame_len(), 417);
+
+        let padded = Mp3FrameHeader::parse(&[0xFF, 0xFB, 0x92, 0x64]).unwrap();
+        assert_eq!(padded.frame_len(), 418);
+    }
+
+    #[test]
+    fn test_parse_invalid_frame_header() {
+        assert_eq!(Mp3FrameHeader::parse(b"ID3\x04"), None);
+        assert_eq!(Mp3FrameHeader::parse(&[0xFF, 0xFB, 0x00, 0x64]), None); // free format
+        assert_eq!(Mp3FrameHeader::parse(&[0xFF, 0xFB, 0xF0, 0x64]), None); // bad bitrate
+        assert_eq!(Mp3FrameHeader::parse(&[0xFF, 0xFB]), None);
+    }
+}
@@ -0,0 +1,122 @@
+//! OGG container helpers.
+
+const MAGIC_OGG_PAGE: [u8; 4] = *b"OggS";
+const PAGE_HEADER_LEN: usize = 27;
+
+const VORBIS_ID_HEADER: &[u8] = b"\x01vorbis";
+const OPUS_ID_HEADER: &[u8] = b"OpusHead";
+const FLAC_ID_HEADER: &[u8] = b"\x7fFLAC";
+
+/// Codec carried in an OGG stream.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OggCodec {
+    Vorbis,
+    Opus,
+    /// FLAC frames in an OGG container (not a native `.flac` file).
+    Flac,
+}
+
+impl OggCodec {
+    /// File extension to use while the stream is still in its OGG container.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            OggCodec::Vorbis => "ogg",
+            OggCodec::Opus => "opus",
+            OggCodec::Flac => "oga",
+        }
+    }
+}
+
+/// Payload of the OGG page at the start of `data`.
+fn first_page_payload(data: &[u8]) -> Option<&[u8]> {
+    if data.len() < PAGE_HEADER_LEN || !data.starts_with(&MAGIC_OGG_PAGE) {
+        return None;
+    }
+
+    let segment_count = data[26] as usize;
+    let segment_table = data.get(PAGE_HEADER_LEN..PAGE_HEADER_LEN + segment_count)?;
+    let payload_len = segment_table.iter().map(|&len| len as usize).sum::<usize>();
+    let payload_start = PAGE_HEADER_LEN + segment_count;
+
+    // Allow a truncated page, the identification header is at the very start.
+    let payload_end = (payload_start + payload_len).min(data.len());
+    Some(&data[payload_start..payload_end])
+}
+
+/// Detect the codec of a (decrypted) OGG stream from the identification header
+/// in its first page.
+pub fn ogg_codec(data: &[u8]) -> Option<OggCodec> {
+    let payload = first_page_payload(data)?;
+
+    if payload.starts_with(VORBIS_ID_HEADER) {
+        Some(OggCodec::Vorbis)
+    } else if payload.starts_with(OPUS_ID_HEADER) {
+        Some(OggCodec::Opus)
+    } else if payload.starts_with(FLAC_ID_HEADER) {
+        Some(OggCodec::Flac)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    /// A single OGG page (beginning of stream) with `payload`.
+    pub fn make_ogg_page(payload: &[u8]) -> Vec<u8> {
+        assert!(payload.len() < 0xFF);
+
+        let mut page = MAGIC_OGG_PAGE.to_vec();
+        page.push(0); // version
+        page.push(0x02); // header type: beginning of stream
+        page.extend_from_slice(&[0u8; 8]); // granule position
+        page.extend_from_slice(&[1, 0, 0, 0]); // serial
+        page.extend_from_slice(&[0u8; 4]); // page sequence
+        page.extend_from_slice(&[0u8; 4]); // crc (not checked)
+        page.push(1);
+        page.push(payload.len() as u8);
+        page.extend_from_slice(payload);
+        page
+    }
+
+    #[test]
+    fn test_vorbis() {
+        let mut id_header = b"\x01vorbis".to_vec();
+        id_header.extend_from_slice(&[0, 0, 0, 0, 2, 0x44, 0xAC, 0, 0]);
+        let page = make_ogg_page(&id_header);
+        assert_eq!(ogg_codec(&page), Some(OggCodec::Vorbis));
+    }
+
+    #[test]
+    fn test_opus() {
+        let page = make_ogg_page(b"OpusHead\x01\x02\x38\x01\x80\xbb\x00\x00\x00\x00\x00");
+        assert_eq!(ogg_codec(&page), Some(OggCodec::Opus));
+    }
+
+    #[test]
+    fn test_flac() {
+        // Mapping header, version 1.0, 1 header packet, then the native STREAMINFO block.
+        let mut id_header = b"\x7fFLAC\x01\x00\x00\x01fLaC".to_vec();
+        id_header.extend_from_slice(&[0x80, 0x00, 0x00, 0x22]);
+        id_header.extend_from_slice(&[0u8; 0x22]);
+        let page = make_ogg_page(&id_header);
+
+        let codec = ogg_codec(&page);
+        assert_eq!(codec, Some(OggCodec::Flac));
+        assert_eq!(codec.unwrap().extension(), "oga");
+    }
+
+    #[test]
+    fn test_extension() {
+        assert_eq!(OggCodec::Vorbis.extension(), "ogg");
+        assert_eq!(OggCodec::Opus.extension(), "opus");
+    }
+
+    #[test]
+    fn test_unknown() {
+        assert_eq!(ogg_codec(&make_ogg_page(b"Speex   ")), None);
+        assert_eq!(ogg_codec(b"fLaC\x00\x00\x00\x22"), None);
+    }
+}
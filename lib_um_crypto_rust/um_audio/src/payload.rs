@@ -0,0 +1,171 @@
+use crate::{detect_audio_type, AudioType};
+use byteorder::{ByteOrder, LE};
+
+const NCM_MAGIC: &[u8; 8] = b"CTENFDAM";
+const KGM_MAGIC: [u8; 16] = [
+    0x7C, 0xD5, 0x32, 0xEB, 0x86, 0x02, 0x7F, 0x4B, 0xA8, 0xAF, 0xA6, 0x8E, 0x0F, 0xFF, 0x99, 0x14,
+];
+const VPR_MAGIC: [u8; 16] = [
+    0x05, 0x28, 0xBC, 0x96, 0xE9, 0xE4, 0x5A, 0x43, 0x91, 0xAA, 0xBD, 0xD0, 0x7A, 0xF5, 0x36, 0x31,
+];
+
+/// Helper to locate the actual audio bytes within a decrypted stream.
+pub struct AudioPayload;
+
+impl AudioPayload {
+    /// Get the audio byte range, with the container framing removed.
+    ///
+    /// * NCM, KGM / VPR: a file decrypted in place still starts with its (plain)
+    ///   container header, which is skipped.
+    /// * Everything else (e.g. QMC): the buffer is returned as-is. The audio file
+    ///   headers (ID3, `fLaC`, RIFF etc.) are part of the audio.
+    ///
+    /// The framing is only removed if what follows is detected as `format` (any
+    /// format for [`AudioType::Unknown`]); otherwise the whole buffer is returned.
+    pub fn extract(decrypted: &[u8], format: AudioType) -> &[u8] {
+        let offset = match ncm_audio_offset(decrypted).or_else(|| kgm_audio_offset(decrypted)) {
+            Some(offset) => offset,
+            None => return decrypted,
+        };
+        let payload = &decrypted[offset..];
+        match detect_audio_type(payload) {
+            Ok(detected) if format == AudioType::Unknown || detected == format => payload,
+            _ => decrypted,
+        }
+    }
+}
+
+/// Get the content of the `data` chunk of a WAV file: the PCM samples, without
+/// the RIFF header and the other chunks.
+///
+/// returns: `None` if `buffer` is not a WAV file, or has no `data` chunk.
+pub fn wav_pcm_data(buffer: &[u8]) -> Option<&[u8]> {
+    if buffer.len() < 12 || !buffer.starts_with(b"RIFF") || &buffer[8..12] != b"WAVE" {
+        return None;
+    }
+
+    let mut offset = 12;
+    while offset + 8 <= buffer.len() {
+        let chunk_id = &buffer[offset..offset + 4];
+        let chunk_len = LE::read_u32(&buffer[offset + 4..offset + 8]) as usize;
+        let start = offset + 8;
+        if chunk_id == b"data" {
+            let end = start.saturating_add(chunk_len).min(buffer.len());
+            return Some(&buffer[start..end]);
+        }
+
+        // Chunks are padded to an even size.
+        offset = start.checked_add(chunk_len)?.checked_add(chunk_len & 1)?;
+    }
+
+    None
+}
+
+/// Read the u32 (little endian) length at `offset`, and skip it and what it covers.
+fn skip_len_prefixed(buffer: &[u8], offset: usize) -> Option<usize> {
+    let len = LE::read_u32(buffer.get(offset..offset.checked_add(4)?)?) as usize;
+    offset.checked_add(4)?.checked_add(len)
+}
+
+/// Offset of the audio in a NCM file: after the key, metadata, CRC and cover frame.
+fn ncm_audio_offset(buffer: &[u8]) -> Option<usize> {
+    if !buffer.starts_with(NCM_MAGIC) {
+        return None;
+    }
+
+    // magic, format version, client version
+    let offset = skip_len_prefixed(buffer, 10)?; // content key
+    let offset = skip_len_prefixed(buffer, offset)?; // metadata
+    let offset = offset.checked_add(5)?; // crc32, cover version
+    let cover_frame_len = LE::read_u32(buffer.get(offset..offset.checked_add(4)?)?) as usize;
+    // cover frame size, image 1 size, then the images
+    let offset = offset.checked_add(8)?.checked_add(cover_frame_len)?;
+    (offset <= buffer.len()).then_some(offset)
+}
+
+/// Offset of the audio in a KGM / VPR file, as declared in its header.
+fn kgm_audio_offset(buffer: &[u8]) -> Option<usize> {
+    let magic = buffer.get(..0x10)?;
+    if magic != KGM_MAGIC && magic != VPR_MAGIC {
+        return None;
+    }
+    let offset = LE::read_u32(buffer.get(0x10..0x14)?) as usize;
+    (offset <= buffer.len()).then_some(offset)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flac::tests::make_flac;
+
+    fn make_ncm_framing(audio: &[u8]) -> Vec<u8> {
+        let mut data = NCM_MAGIC.to_vec();
+        data.extend_from_slice(&[0x01, 0x02]);
+        for field in [&b"content key"[..], b"metadata"] {
+            data.extend_from_slice(&(field.len() as u32).to_le_bytes());
+            data.extend_from_slice(field);
+        }
+        data.extend_from_slice(&[0u8; 4]); // crc32
+        data.push(1); // cover version
+        let cover = b"\xFF\xD8\xFF\xE0 cover";
+        data.extend_from_slice(&(cover.len() as u32).to_le_bytes());
+        data.extend_from_slice(&(cover.len() as u32).to_le_bytes());
+        data.extend_from_slice(cover);
+        data.extend_from_slice(audio);
+        data
+    }
+
+    #[test]
+    fn test_extract_mp3_identity() {
+        let mp3_data = include_bytes!("__fixtures__/mp3_with_id3v2.bin");
+        let payload = AudioPayload::extract(mp3_data, AudioType::MP3);
+        assert_eq!(payload, mp3_data);
+    }
+
+    #[test]
+    fn test_extract_wav_identity() {
+        let mut wav = b"RIFF\x00\x00\x00\x00WAVE".to_vec();
+        wav.extend_from_slice(b"fmt \x10\x00\x00\x00");
+        wav.extend_from_slice(&[0u8; 0x10]);
+        wav.extend_from_slice(b"data\x05\x00\x00\x00hello");
+
+        assert_eq!(AudioPayload::extract(&wav, AudioType::WAV), wav);
+    }
+
+    #[test]
+    fn test_extract_ncm_framing() {
+        let flac = make_flac();
+        let ncm = make_ncm_framing(&flac);
+        assert_eq!(AudioPayload::extract(&ncm, AudioType::FLAC), flac);
+        assert_eq!(AudioPayload::extract(&ncm, AudioType::Unknown), flac);
+
+        // Not the expected format: keep everything.
+        assert_eq!(AudioPayload::extract(&ncm, AudioType::MP3), ncm);
+        // Cover frame larger than the file.
+        let truncated = &ncm[..ncm.len() - flac.len() - 2];
+        assert_eq!(AudioPayload::extract(truncated, AudioType::FLAC), truncated);
+    }
+
+    #[test]
+    fn test_extract_kgm_framing() {
+        let flac = make_flac();
+        let mut kgm = KGM_MAGIC.to_vec();
+        kgm.extend_from_slice(&0x40u32.to_le_bytes());
+        kgm.resize(0x40, 0);
+        kgm.extend_from_slice(&flac);
+        assert_eq!(AudioPayload::extract(&kgm, AudioType::FLAC), flac);
+    }
+
+    #[test]
+    fn test_wav_pcm_data() {
+        let mut wav = b"RIFF\x00\x00\x00\x00WAVE".to_vec();
+        wav.extend_from_slice(b"fmt \x10\x00\x00\x00");
+        wav.extend_from_slice(&[0u8; 0x10]);
+        wav.extend_from_slice(b"LIST\x03\x00\x00\x00abc\x00"); // odd sized, padded
+        wav.extend_from_slice(b"data\x05\x00\x00\x00hello");
+        assert_eq!(wav_pcm_data(&wav), Some(&b"hello"[..]));
+
+        let malformed = b"RIFF\x00\x00\x00\x00WAVEfmt \xff\xff\xff\xff";
+        assert_eq!(wav_pcm_data(malformed), None);
+    }
+}
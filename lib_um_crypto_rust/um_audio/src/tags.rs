@@ -0,0 +1,210 @@
+//! Minimal metadata reader: cover art of FLAC (`PICTURE` block) and ID3v2 (`APIC`
+//! frame) files.
+
+use crate::flac::MAGIC_FLAC;
+use byteorder::{ByteOrder, BE};
+use std::borrow::Cow;
+
+const FLAC_BLOCK_PICTURE: u8 = 6;
+
+/// Tags read from a decrypted audio file.
+///
+/// The cover art borrows from the buffer it was read from, so it can be written out
+/// without a copy; see [`AudioTags::into_owned`] to keep it longer than the buffer.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AudioTags<'a> {
+    pub cover_mime: Option<String>,
+    pub cover: Option<Cow<'a, [u8]>>,
+}
+
+impl<'a> AudioTags<'a> {
+    /// Read the tags of `buffer`, the start of a decrypted FLAC or MP3 file.
+    ///
+    /// Missing or malformed tags are left empty.
+    pub fn read(buffer: &'a [u8]) -> Self {
+        let picture = match buffer.starts_with(&MAGIC_FLAC) {
+            true => find_flac_picture(buffer),
+            false => find_id3_picture(buffer),
+        };
+
+        match picture {
+            Some((mime, cover)) => Self {
+                cover_mime: Some(String::from_utf8_lossy(mime).into_owned()),
+                cover: Some(Cow::Borrowed(cover)),
+            },
+            None => Self::default(),
+        }
+    }
+
+    /// Copy the borrowed data, detaching the tags from the buffer.
+    pub fn into_owned(self) -> AudioTags<'static> {
+        AudioTags {
+            cover_mime: self.cover_mime,
+            cover: self.cover.map(|cover| Cow::Owned(cover.into_owned())),
+        }
+    }
+}
+
+/// `(mime, data)` of the first `PICTURE` metadata block.
+fn find_flac_picture(buffer: &[u8]) -> Option<(&[u8], &[u8])> {
+    let mut offset = MAGIC_FLAC.len();
+    loop {
+        let header = buffer.get(offset..offset + 4)?;
+        let is_last = header[0] & 0x80 != 0;
+        let block_len = BE::read_u24(&header[1..4]) as usize;
+        let block = buffer.get(offset + 4..offset + 4 + block_len)?;
+        if header[0] & 0x7F == FLAC_BLOCK_PICTURE {
+            return parse_flac_picture(block);
+        }
+        if is_last {
+            return None;
+        }
+        offset += 4 + block_len;
+    }
+}
+
+fn parse_flac_picture(block: &[u8]) -> Option<(&[u8], &[u8])> {
+    // offset    value
+    //      0    u32(picture_type)
+    //      4    u32(mime_len) byte[mime_len] mime
+    //     ??    u32(desc_len) byte[desc_len] description
+    //     ??    u32(width) u32(height) u32(depth) u32(colors)
+    //     ??    u32(data_len) byte[data_len] data
+    let mut offset = 4;
+    let mut read_bytes = |skip: usize| {
+        let len = BE::read_u32(block.get(offset + skip..offset + skip + 4)?) as usize;
+        let start = offset + skip + 4;
+        offset = start.checked_add(len)?;
+        block.get(start..offset)
+    };
+    let mime = read_bytes(0)?;
+    let _description = read_bytes(0)?;
+    let data = read_bytes(16)?;
+    Some((mime, data))
+}
+
+/// `(mime, data)` of the first `APIC` frame of an ID3v2.3 or v2.4 tag.
+fn find_id3_picture(buffer: &[u8]) -> Option<(&[u8], &[u8])> {
+    let header = buffer.get(..10)?;
+    let version = header[3];
+    let flags = header[5];
+    // Unsynchronisation and extended headers are not supported.
+    if !header.starts_with(b"ID3") || !(3..=4).contains(&version) || flags & 0xC0 != 0 {
+        return None;
+    }
+    let tag = buffer.get(10..10 + sync_safe_u32(&header[6..10]))?;
+
+    let mut offset = 0;
+    while let Some(frame_header) = tag.get(offset..offset + 10) {
+        let frame_len = match version {
+            4 => sync_safe_u32(&frame_header[4..8]),
+            _ => BE::read_u32(&frame_header[4..8]) as usize,
+        };
+        if frame_header[0] == 0 || frame_len == 0 {
+            return None; // padding
+        }
+        let frame = tag.get(offset + 10..offset + 10 + frame_len)?;
+        if &frame_header[..4] == b"APIC" {
+            return parse_id3_apic(frame);
+        }
+        offset += 10 + frame_len;
+    }
+    None
+}
+
+fn parse_id3_apic(frame: &[u8]) -> Option<(&[u8], &[u8])> {
+    // u8(encoding) cstr(mime) u8(picture_type) str(description) byte[*] data
+    let (&encoding, rest) = frame.split_first()?;
+    let mime_len = rest.iter().position(|&b| b == 0)?;
+    let mime = &rest[..mime_len];
+    let description = rest.get(mime_len + 2..)?;
+    let description_len = match encoding {
+        // UTF-16 variants, terminated by an aligned double zero.
+        1 | 2 => description.chunks_exact(2).position(|c| c == [0, 0])? * 2 + 2,
+        _ => description.iter().position(|&b| b == 0)? + 1,
+    };
+    Some((mime, &description[description_len..]))
+}
+
+fn sync_safe_u32(buffer: &[u8]) -> usize {
+    buffer
+        .iter()
+        .fold(0usize, |value, &b| value << 7 | (b & 0x7F) as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mp3::tests::make_mp3_frame;
+
+    const COVER: &[u8] = b"\x89PNG\r\n\x1a\n fake image data";
+
+    fn make_flac_with_cover() -> Vec<u8> {
+        let mut picture = vec![];
+        picture.extend_from_slice(&3u32.to_be_bytes()); // front cover
+        picture.extend_from_slice(&9u32.to_be_bytes());
+        picture.extend_from_slice(b"image/png");
+        picture.extend_from_slice(&0u32.to_be_bytes());
+        picture.extend_from_slice(&[0u8; 16]);
+        picture.extend_from_slice(&(COVER.len() as u32).to_be_bytes());
+        picture.extend_from_slice(COVER);
+
+        let mut flac = MAGIC_FLAC.to_vec();
+        flac.extend_from_slice(&[0x00, 0x00, 0x00, 0x22]); // STREAMINFO
+        flac.extend_from_slice(&[0u8; 0x22]);
+        flac.push(0x80 | FLAC_BLOCK_PICTURE);
+        flac.extend_from_slice(&(picture.len() as u32).to_be_bytes()[1..]);
+        flac.extend_from_slice(&picture);
+        flac.extend_from_slice(&[0xFF, 0xF8, 0x69, 0x08]);
+        flac
+    }
+
+    #[test]
+    fn test_flac_cover_is_borrowed() {
+        let flac = make_flac_with_cover();
+        let tags = AudioTags::read(&flac);
+        assert_eq!(tags.cover_mime.as_deref(), Some("image/png"));
+
+        let cover = match &tags.cover {
+            Some(Cow::Borrowed(cover)) => *cover,
+            other => panic!("expected a borrowed cover, got {other:?}"),
+        };
+        assert_eq!(cover, COVER);
+        assert!(flac.as_ptr_range().contains(&cover.as_ptr()));
+
+        let owned = tags.clone().into_owned();
+        drop(flac);
+        assert!(matches!(owned.cover, Some(Cow::Owned(_))));
+        assert_eq!(owned.cover.as_deref(), Some(COVER));
+        assert_eq!(owned.cover_mime.as_deref(), Some("image/png"));
+    }
+
+    #[test]
+    fn test_id3_apic_cover() {
+        let mut apic = vec![0x00];
+        apic.extend_from_slice(b"image/png\x00");
+        apic.push(3);
+        apic.extend_from_slice(b"cover\x00");
+        apic.extend_from_slice(COVER);
+
+        let mut frame = b"APIC".to_vec();
+        frame.extend_from_slice(&(apic.len() as u32).to_be_bytes());
+        frame.extend_from_slice(&[0, 0]);
+        frame.extend_from_slice(&apic);
+
+        let mut mp3 = b"ID3\x03\x00\x00".to_vec();
+        mp3.extend_from_slice(&[0, 0, 0, frame.len() as u8]);
+        mp3.extend_from_slice(&frame);
+        mp3.extend(make_mp3_frame());
+
+        let tags = AudioTags::read(&mp3);
+        assert_eq!(tags.cover_mime.as_deref(), Some("image/png"));
+        assert_eq!(tags.cover.as_deref(), Some(COVER));
+    }
+
+    #[test]
+    fn test_no_tags() {
+        let tags = AudioTags::read(&[0xFF, 0xFB, 0x90, 0x64]);
+        assert_eq!(tags, AudioTags::default());
+    }
+}
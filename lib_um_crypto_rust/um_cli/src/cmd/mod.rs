@@ -10,6 +10,7 @@ pub mod qtfm;
 pub mod xiami;
 pub mod xmly;
 
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Subcommand)]
 pub enum Commands {
     #[command(name = "ncm")]
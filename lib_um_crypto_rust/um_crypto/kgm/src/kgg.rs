@@ -0,0 +1,118 @@
+//! Key database of the `.kgg` format.
+//!
+//! The ekey of a `.kgg` file is not stored in the file itself, but in the
+//! `KGMusicV3.db` database of the client, using the audio hash found in the header.
+
+use crate::{decrypt_db, KugouError};
+use rusqlite::{Connection, MAIN_DB};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+const KEY_QUERY: &str =
+    "select EncryptionKeyId, EncryptionKey from ShareFileItems where EncryptionKey != ''";
+
+#[derive(Debug, Default, Clone)]
+pub struct KggKeyDb {
+    keys: HashMap<String, Vec<u8>>,
+}
+
+impl KggKeyDb {
+    /// Load the keys from the database file at `path`, encrypted or not.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, KugouError> {
+        let data = fs::read(path).map_err(KugouError::KggDbIOError)?;
+        Self::from_bytes(data)
+    }
+
+    pub fn from_bytes(mut data: Vec<u8>) -> Result<Self, KugouError> {
+        decrypt_db(&mut data)?;
+
+        let mut conn = Connection::open_in_memory().map_err(KugouError::KggDbQueryError)?;
+        conn.deserialize_read_exact(MAIN_DB, &data[..], data.len(), true)
+            .map_err(KugouError::KggDbQueryError)?;
+
+        let mut stmt = conn
+            .prepare(KEY_QUERY)
+            .map_err(KugouError::KggDbQueryError)?;
+        let keys = stmt
+            .query_map([], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+            })
+            .and_then(|rows| {
+                rows.map(|row| row.map(|(id, key)| (id, key.into_bytes())))
+                    .collect::<Result<HashMap<_, _>, _>>()
+            })
+            .map_err(KugouError::KggDbQueryError)?;
+
+        Ok(Self { keys })
+    }
+
+    /// Find the ekey of a file, by its audio hash ([`crate::header::Header::audio_hash`]).
+    pub fn lookup(&self, file_hash: &str) -> Option<Vec<u8>> {
+        self.keys.get(file_hash).cloned()
+    }
+
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Decipher;
+    use umc_qmc::QMCv2Cipher;
+
+    const AUDIO_HASH: &str = "0123456789abcdef0123456789abcdef";
+
+    fn make_key_db(path: &Path, rows: &[(&str, &str)]) {
+        let conn = Connection::open(path).unwrap();
+        conn.execute(
+            "create table ShareFileItems (EncryptionKeyId text, EncryptionKey text)",
+            [],
+        )
+        .unwrap();
+        for (id, key) in rows {
+            conn.execute(
+                "insert into ShareFileItems (EncryptionKeyId, EncryptionKey) values (?1, ?2)",
+                [id, key],
+            )
+            .unwrap();
+        }
+    }
+
+    #[test]
+    fn test_lookup_and_decrypt() {
+        let key = (1..=512).map(|i| i as u8).collect::<Vec<_>>();
+        let ekey = umc_qmc::ekey::encrypt_v1(&key).unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("KGMusicV3.db");
+        make_key_db(&path, &[(AUDIO_HASH, &ekey), ("no key", "")]);
+
+        let db = KggKeyDb::open(&path).unwrap();
+        assert_eq!(db.len(), 1);
+        assert_eq!(db.lookup("no key"), None);
+        assert_eq!(db.lookup("unknown"), None);
+
+        let found = db.lookup(AUDIO_HASH).expect("key should be found");
+        assert_eq!(found, ekey.as_bytes());
+
+        let mut expected = vec![0x55u8; 0x2000];
+        QMCv2Cipher::new(&key).unwrap().decrypt(&mut expected, 0);
+        let mut actual = vec![0x55u8; 0x2000];
+        Decipher::new_kgg(&found).unwrap().decrypt(&mut actual, 0);
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn test_open_missing_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let result = KggKeyDb::open(dir.path().join("missing.db"));
+        assert!(matches!(result, Err(KugouError::KggDbIOError(_))));
+    }
+}
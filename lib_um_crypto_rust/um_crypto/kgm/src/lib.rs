@@ -1,4 +1,6 @@
 pub mod header;
+#[cfg(feature = "kgg")]
+pub mod kgg;
 mod pc_db_decrypt;
 mod slot_keys;
 pub mod v2;
@@ -55,6 +57,14 @@ pub enum KugouError {
 
     #[error("Invalid audio hash size: {0}")]
     HeaderInvalidAudioHash(usize),
+
+    #[cfg(feature = "kgg")]
+    #[error("Failed to read KGG key database: {0}")]
+    KggDbIOError(std::io::Error),
+
+    #[cfg(feature = "kgg")]
+    #[error("Failed to query KGG key database: {0}")]
+    KggDbQueryError(rusqlite::Error),
 }
 
 pub enum Decipher {
@@ -88,6 +98,11 @@ impl Decipher {
         Ok(decipher)
     }
 
+    /// Cipher for `.kgg` files, with the ekey found in [`kgg::KggKeyDb`].
+    pub fn new_kgg<T: AsRef<[u8]>>(ekey: T) -> Result<Self, KugouError> {
+        Ok(Decipher::V5(DecipherV5::new(ekey)?))
+    }
+
     pub fn decrypt<T: AsMut<[u8]> + ?Sized>(&self, buffer: &mut T, offset: usize) {
         match self {
             Decipher::V2(decipher) => decipher.decrypt(buffer, offset),
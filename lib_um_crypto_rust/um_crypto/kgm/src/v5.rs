@@ -4,7 +4,7 @@ use umc_qmc::QMCv2Cipher;
 pub struct DecipherV5(QMCv2Cipher);
 
 impl DecipherV5 {
-    pub fn new(ekey: &str) -> Result<Self, KugouError> {
+    pub fn new<T: AsRef<[u8]>>(ekey: T) -> Result<Self, KugouError> {
         let cipher = QMCv2Cipher::new_from_ekey(ekey)
             .map_err(|e| KugouError::QMC2EKeyError(e.to_string()))?;
 
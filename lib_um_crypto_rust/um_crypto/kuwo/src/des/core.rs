@@ -83,7 +83,7 @@ impl KuwoDes {
     }
 
     pub fn transform(&self, data: &mut [u8]) -> Result<()> {
-        if data.len() % 8 != 0 {
+        if !data.len().is_multiple_of(8) {
             Err(KuwoCryptoError::InvalidDesDataSize(data.len()))?
         }
 
@@ -22,7 +22,7 @@ pub fn decrypt_ksing<T: AsRef<[u8]>>(data: T, key: &[u8; 8]) -> Result<String> {
 
 pub fn encrypt_ksing<T: AsRef<[u8]>>(data: T, key: &[u8; 8]) -> Result<String> {
     let mut data = Vec::from(data.as_ref());
-    let padded_len = ((data.len() + 7) / 8) * 8;
+    let padded_len = data.len().div_ceil(8) * 8;
     data.resize(padded_len, 0u8);
 
     let des = KuwoDes::new(key, Mode::Encrypt);
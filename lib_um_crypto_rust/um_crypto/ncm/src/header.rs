@@ -1,3 +1,4 @@
+use crate::metadata::MusicInfo;
 use crate::{content_key, metadata, NetEaseCryptoError as Error, NetEaseCryptoError};
 use byteorder::{ByteOrder, LE};
 use std::cmp::max;
@@ -17,6 +18,8 @@ pub struct NCMFile {
     pub content_key: Vec<u8>,
     /// Encrypted metadata.
     pub metadata: Vec<u8>,
+    /// Parsed metadata, `None` if absent or invalid (see [`NCMFile::get_music_info`]).
+    pub music_info: Option<MusicInfo>,
     /// Cover image 1, usually jpg.
     pub image1: Option<Vec<u8>>,
     /// Cover image 2, format unknown
@@ -48,6 +51,19 @@ fn build_audio_rc4_key_stream(enciphered_content_key: &[u8]) -> Result<[u8; 256]
     Ok(key)
 }
 
+/// Guess the image format of cover art by its magic bytes.
+///
+/// returns: `Some("jpg")` or `Some("png")` when recognized, otherwise `None`.
+pub fn image_format(image: &[u8]) -> Option<&'static str> {
+    if image.starts_with(b"\xFF\xD8\xFF") {
+        Some("jpg")
+    } else if image.starts_with(b"\x89PNG\r\n\x1A\n") {
+        Some("png")
+    } else {
+        None
+    }
+}
+
 impl NCMFile {
     pub fn new<T>(header: T) -> Result<Self, Error>
     where
@@ -131,6 +147,7 @@ impl NCMFile {
             client_version,
             content_key: Vec::from(content_key),
             metadata: Vec::from(metadata),
+            music_info: metadata::decrypt(metadata).and_then(metadata::parse).ok(),
             image1,
             image2,
             audio_rc4_key_stream: build_audio_rc4_key_stream(content_key)?,
@@ -168,10 +185,20 @@ impl NCMFile {
         Ok(ncm)
     }
 
+    /// Get the embedded cover art (image 1), if present.
+    pub fn cover_art(&self) -> Option<&[u8]> {
+        self.image1.as_deref()
+    }
+
     pub fn get_metadata(&self) -> Result<Vec<u8>, Error> {
         metadata::decrypt(&self.metadata)
     }
 
+    /// Decrypt and parse the metadata, reporting why it failed if it did.
+    pub fn get_music_info(&self) -> Result<MusicInfo, Error> {
+        metadata::parse(self.get_metadata()?)
+    }
+
     /// Decrypt audio data.
     ///
     /// # Arguments
@@ -216,6 +243,10 @@ fn test_load_ncm() -> Result<(), Error> {
     assert_eq!(ncm.image1, Some(b"img#1".to_vec()));
     assert_eq!(ncm.image2, Some(b"IMAGE#2".to_vec()));
 
+    let music_info = ncm.music_info.as_ref().expect("metadata should parse");
+    assert_eq!(music_info.artist, ["大野克夫"]);
+    assert_eq!(music_info.format, "mp3");
+
     let mut audio_data = ncm_header[ncm.audio_data_offset..].to_vec();
     ncm.decrypt(&mut audio_data, 0);
     let actual = vec![
@@ -225,3 +256,29 @@ fn test_load_ncm() -> Result<(), Error> {
 
     Ok(())
 }
+
+#[test]
+fn test_ncm_cover_art_png() -> Result<(), Error> {
+    const PNG_COVER: [u8; 16] = *b"\x89PNG\r\n\x1A\n\x00\x00\x00\x0DIHDR";
+
+    // Reuse the fixture up to (and including) the cover version byte.
+    let fixture = include_bytes!("__fixture__/ncm_test1.bin");
+    let cover_frame_offset = 0x3fd;
+    let mut ncm_data = fixture[..cover_frame_offset].to_vec();
+    ncm_data.extend_from_slice(&(PNG_COVER.len() as u32).to_le_bytes()); // cover frame len
+    ncm_data.extend_from_slice(&(PNG_COVER.len() as u32).to_le_bytes()); // image 1 len
+    ncm_data.extend_from_slice(&PNG_COVER);
+    ncm_data.extend_from_slice(b"audio");
+
+    let ncm = NCMFile::new(&ncm_data)?;
+    let cover = ncm.cover_art().expect("cover art should be present");
+    assert_eq!(cover, PNG_COVER);
+    assert_eq!(image_format(cover), Some("png"));
+    assert_eq!(ncm.image2, None);
+    assert_eq!(&ncm_data[ncm.audio_data_offset..], b"audio");
+
+    assert_eq!(image_format(b"\xFF\xD8\xFF\xE0"), Some("jpg"));
+    assert_eq!(image_format(b"img#1"), None);
+
+    Ok(())
+}
@@ -41,4 +41,6 @@ pub enum NetEaseCryptoError {
     MetadataDecodeError(base64::DecodeError),
     #[error("Metadata: Invalid prefix on final json: {0}")]
     MetadataInvalidJsonPrefix(String),
+    #[error("Metadata: Invalid json: {0}")]
+    MetadataInvalidJson(serde_json::Error),
 }
@@ -4,10 +4,51 @@ use cipher::block_padding::Pkcs7;
 use cipher::generic_array::GenericArray;
 use cipher::{BlockDecrypt, KeyInit};
 use itertools::Itertools;
+use serde::{Deserialize, Deserializer};
 use umc_utils::base64;
 
 const METADATA_KEY: [u8; 0x10] = *b"#14ljk_!\\]&0U<'(";
 
+/// Music information from the metadata json.
+#[derive(Debug, PartialEq, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MusicInfo {
+    #[serde(default)]
+    pub music_name: String,
+    /// Artist names.
+    #[serde(default, deserialize_with = "deserialize_artists")]
+    pub artist: Vec<String>,
+    #[serde(default)]
+    pub album: String,
+    /// Audio format, e.g. `mp3` or `flac`.
+    #[serde(default)]
+    pub format: String,
+}
+
+/// Artists are stored as `[["name", id], ...]`, keep the names only.
+fn deserialize_artists<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let artists = Vec::<Vec<serde_json::Value>>::deserialize(deserializer)?;
+    let names = artists
+        .into_iter()
+        .filter_map(|artist| match artist.into_iter().next() {
+            Some(serde_json::Value::String(name)) => Some(name),
+            _ => None,
+        })
+        .collect();
+    Ok(names)
+}
+
+/// Parse the metadata json, as returned by [`decrypt`].
+pub fn parse<T>(json: T) -> Result<MusicInfo, NetEaseCryptoError>
+where
+    T: AsRef<[u8]>,
+{
+    serde_json::from_slice(json.as_ref()).map_err(NetEaseCryptoError::MetadataInvalidJson)
+}
+
 /// Decrypt metadata
 ///
 /// # Arguments
@@ -109,3 +150,31 @@ fn test_decrypt_metadata() -> Result<(), NetEaseCryptoError> {
     assert_eq!(key[0], b'{');
     Ok(())
 }
+
+#[test]
+fn test_parse_encrypted_metadata() -> Result<(), NetEaseCryptoError> {
+    use aes::Aes128Enc;
+    use cipher::BlockEncrypt;
+
+    let json = r#"music:{"musicId":1,"musicName":"Song","artist":[["Alice",1],["Bob",2]],"album":"Album","format":"flac"}"#;
+    let aes = Aes128Enc::new(&GenericArray::from(METADATA_KEY));
+    let mut buffer = json.as_bytes().to_vec();
+    buffer.resize(json.len() + 0x10, 0);
+    let encrypted = aes
+        .encrypt_padded::<Pkcs7>(&mut buffer, json.len())
+        .expect("buffer should be large enough");
+    let block = format!("163 key(Don't modify):{}", base64::encode(encrypted));
+    let block = block.bytes().map(|b| b ^ 0x63).collect_vec();
+
+    let info = parse(decrypt(block)?)?;
+    assert_eq!(
+        info,
+        MusicInfo {
+            music_name: "Song".into(),
+            artist: vec!["Alice".into(), "Bob".into()],
+            album: "Album".into(),
+            format: "flac".into(),
+        }
+    );
+    Ok(())
+}
@@ -0,0 +1,47 @@
+//! `.bkcmp3`, `.bkcflac`, `.bkcm4a` etc. files.
+//!
+//! The whole file is XOR-ed with the static QMCv1 key, see [`crate::v1::decrypt`].
+
+use crate::{v1, QmcCipher};
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct BaiduCipher;
+
+impl BaiduCipher {
+    /// The key is built in, there is nothing to configure.
+    pub fn new() -> Self {
+        Self
+    }
+
+    pub fn decrypt<T>(&self, data: &mut T, offset: usize)
+    where
+        T: AsMut<[u8]> + ?Sized,
+    {
+        v1::decrypt(data.as_mut(), offset)
+    }
+}
+
+impl QmcCipher for BaiduCipher {
+    fn decrypt(&self, data: &mut [u8], offset: usize) {
+        BaiduCipher::decrypt(self, data, offset)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decrypt_header() {
+        // "ID3\x04\x00" with the static key.
+        let mut header = [0x8a, 0x0e, 0xe5, 0xce, 0x90];
+        BaiduCipher::new().decrypt(&mut header, 0);
+        assert_eq!(&header, b"ID3\x04\x00");
+
+        let mut data = *b"\xab\x2f\xba\xa6\xff\x47\x80\x3d\xaa\xcd\x02";
+        let (head, tail) = data.split_at_mut(4);
+        BaiduCipher::new().decrypt(head, 0);
+        BaiduCipher::new().decrypt(tail, 4);
+        assert_eq!(&data, b"hello world");
+    }
+}
@@ -0,0 +1,60 @@
+//! Decrypt many files in parallel, on the global rayon pool.
+
+use crate::decoder::QmcDecoder;
+use crate::{ekey, QmcCryptoError};
+use rayon::prelude::*;
+use std::fs;
+use std::path::PathBuf;
+
+/// Where the key of a [`DecryptJob`] comes from.
+#[derive(Debug, Clone, PartialEq)]
+pub enum KeySource {
+    /// Embedded in the file, or in its sidecar key file, see [`QmcDecoder::open`].
+    Embedded,
+    /// Base64 encoded EKey.
+    EKey(String),
+    /// Already decoded key.
+    Key(Vec<u8>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DecryptJob {
+    pub input: PathBuf,
+    pub key: KeySource,
+    /// Path of the decrypted file, including its extension.
+    pub output: PathBuf,
+}
+
+impl DecryptJob {
+    fn run(self) -> Result<PathBuf, QmcCryptoError> {
+        let decoder = match self.key {
+            KeySource::Embedded => QmcDecoder::open(&self.input)?,
+            KeySource::EKey(ekey) => {
+                let key = ekey::decrypt(ekey).map_err(QmcCryptoError::EKeyDecodeError)?;
+                let data = fs::read(&self.input).map_err(QmcCryptoError::IOError)?;
+                QmcDecoder::from_vec_with_key(data, key)?
+            }
+            KeySource::Key(key) => {
+                let data = fs::read(&self.input).map_err(QmcCryptoError::IOError)?;
+                QmcDecoder::from_vec_with_key(data, key)?
+            }
+        };
+
+        fs::write(&self.output, decoder.into_vec()).map_err(QmcCryptoError::IOError)?;
+        Ok(self.output)
+    }
+}
+
+/// Decrypt each of `jobs` in parallel, and call `on_done` with the index of the job
+/// and its output path as soon as it finishes.
+///
+/// `on_done` is called from the worker threads, in completion order; returns once
+/// every job is done.
+pub fn decrypt_batch<F>(jobs: Vec<DecryptJob>, on_done: F)
+where
+    F: Fn(usize, Result<PathBuf, QmcCryptoError>) + Sync,
+{
+    jobs.into_par_iter()
+        .enumerate()
+        .for_each(|(index, job)| on_done(index, job.run()));
+}
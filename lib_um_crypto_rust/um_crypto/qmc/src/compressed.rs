@@ -0,0 +1,48 @@
+//! QMC files distributed with an extra layer of gzip compression.
+
+use crate::{QMCv2Cipher, QmcCryptoError};
+use flate2::read::GzDecoder;
+use std::io::Read;
+
+/// Decompress `gz_data`, then decrypt the result with `key`.
+///
+/// The encrypted file is the decompressed stream, its first byte is at offset 0.
+pub fn decrypt_gzipped(key: &[u8], gz_data: &[u8]) -> Result<Vec<u8>, QmcCryptoError> {
+    let cipher = QMCv2Cipher::try_new(key)?;
+
+    let mut data = Vec::with_capacity(gz_data.len());
+    GzDecoder::new(gz_data)
+        .read_to_end(&mut data)
+        .map_err(QmcCryptoError::IOError)?;
+    cipher.decrypt(&mut data, 0);
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::generate_key;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+
+    #[test]
+    fn test_decrypt_gzipped() {
+        let key = generate_key(512);
+        let plain = (0..0x3000).map(|i| (i * 3) as u8).collect::<Vec<_>>();
+        let mut encrypted = plain.clone();
+        QMCv2Cipher::new(&key).unwrap().decrypt(&mut encrypted, 0);
+
+        let mut encoder = GzEncoder::new(vec![], Compression::default());
+        encoder.write_all(&encrypted).unwrap();
+        let gz_data = encoder.finish().unwrap();
+
+        assert_eq!(decrypt_gzipped(&key, &gz_data).unwrap(), plain);
+    }
+
+    #[test]
+    fn test_decrypt_gzipped_invalid() {
+        let result = decrypt_gzipped(&generate_key(512), b"not gzip data");
+        assert!(matches!(result, Err(QmcCryptoError::IOError(_))));
+    }
+}
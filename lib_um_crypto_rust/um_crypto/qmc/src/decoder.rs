@@ -0,0 +1,1007 @@
+use crate::ekey::{EKeyDecoder, TeaEKeyDecoder};
+use crate::footer::FooterScheme;
+use crate::identity::IdentityCipher;
+use crate::transform::PostTransform;
+use crate::v2_rc4::cipher::{is_known_audio, OTHER_SEGMENT_SIZE, PROBE_SIZE};
+use crate::v2_rc4::verify::segment_validity;
+use crate::{dir, footer, tag_key, QMCv2Cipher, QmcCipher, QmcCryptoError};
+use std::fmt::{self, Display};
+use std::fs;
+use std::io::Read;
+use std::ops::Range;
+use std::path::Path;
+use std::sync::Arc;
+use um_audio::mp3::Mp3FrameHeader;
+use um_audio::{AudioError, AudioType};
+
+#[cfg(feature = "hash")]
+use sha2::{Digest, Sha256};
+
+#[cfg(feature = "hash")]
+pub use sha2::digest::DynDigest;
+
+/// Cipher picked for a file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CipherKind {
+    /// The input was already plain audio.
+    Passthrough,
+    MapL,
+    RC4,
+}
+
+impl From<&QMCv2Cipher> for CipherKind {
+    fn from(cipher: &QMCv2Cipher) -> Self {
+        match cipher {
+            QMCv2Cipher::MapL(_) => CipherKind::MapL,
+            QMCv2Cipher::RC4(_) => CipherKind::RC4,
+        }
+    }
+}
+
+impl Display for CipherKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            CipherKind::Passthrough => "passthrough",
+            CipherKind::MapL => "QMCv2/Map",
+            CipherKind::RC4 => "QMCv2/RC4",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// What [`QmcDecoder`] does with a file, see [`QmcDecoder::report`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecryptReport {
+    pub cipher: CipherKind,
+    /// Length of the decoded key, `0` for passthrough.
+    pub key_len: usize,
+    /// `None` if there is no footer, or when the input was already plain audio.
+    pub footer: Option<FooterScheme>,
+    /// Format of the decrypted audio, if recognised.
+    pub output_format: Option<AudioType>,
+    /// Size of the decrypted audio, footer excluded.
+    pub audio_bytes: usize,
+    /// Ranges of the decrypted output that failed frame validation, see
+    /// [`QmcDecoder::decrypt_checked`]. Always empty from [`QmcDecoder::report`].
+    pub suspect_ranges: Vec<Range<usize>>,
+    /// The embedded key only worked in reverse byte order, as written by some tools.
+    pub key_reversed: bool,
+    /// SHA-256 of the decrypted output, see `QmcDecoder::sha256` (`hash` feature).
+    /// Always `None` otherwise.
+    pub audio_sha256: Option<[u8; 32]>,
+    /// Output of the digest given to `QmcDecoder::digest` (`hash` feature) over the
+    /// decrypted output. Always `None` otherwise.
+    pub audio_digest: Option<Vec<u8>>,
+}
+
+impl Display for DecryptReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "cipher={}, key_len={}", self.cipher, self.key_len)?;
+        match self.footer {
+            Some(footer) => write!(f, ", footer={footer}")?,
+            None => write!(f, ", footer=none")?,
+        }
+        match self.output_format {
+            Some(format) => write!(f, ", format={format}")?,
+            None => write!(f, ", format=unknown")?,
+        }
+        write!(f, ", audio_bytes={}", self.audio_bytes)?;
+        if self.key_reversed {
+            write!(f, ", key=reversed")?;
+        }
+        match self.suspect_ranges.len() {
+            0 => Ok(()),
+            n => write!(f, ", suspect_ranges={n}"),
+        }
+    }
+}
+
+/// Limits applied while reading the input, see [`QmcDecoder::open_with_options`]
+/// and [`QmcDecoder::from_reader`], and how embedded keys are decoded.
+#[derive(Clone, Default)]
+pub struct QmcDecoderOptions {
+    /// Refuse inputs larger than this many bytes, before reading them into memory.
+    pub max_input_bytes: Option<usize>,
+    /// Decoder of the embedded EKey, `None` for [`TeaEKeyDecoder`].
+    pub ekey_decoder: Option<Arc<dyn EKeyDecoder>>,
+}
+
+impl fmt::Debug for QmcDecoderOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("QmcDecoderOptions")
+            .field("max_input_bytes", &self.max_input_bytes)
+            .field("custom_ekey_decoder", &self.ekey_decoder.is_some())
+            .finish()
+    }
+}
+
+impl QmcDecoderOptions {
+    fn ekey_decoder(&self) -> &dyn EKeyDecoder {
+        match &self.ekey_decoder {
+            Some(decoder) => decoder.as_ref(),
+            None => &TeaEKeyDecoder,
+        }
+    }
+
+    fn check_input_len(&self, len: u64) -> Result<(), QmcCryptoError> {
+        match self.max_input_bytes {
+            Some(limit) if len > limit as u64 => Err(QmcCryptoError::InputTooLarge { limit }),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Decode a whole QMC file held in memory.
+///
+/// Inputs without any key that are already plain audio (e.g. the output of a previous
+/// run) are recognised and passed through untouched.
+pub struct QmcDecoder {
+    data: Vec<u8>,
+    /// Start of the encrypted audio, after a plain text tag holding the key.
+    audio_start: usize,
+    audio_len: usize,
+    /// `None` when the input is already decrypted.
+    cipher: Option<QMCv2Cipher>,
+    key_len: usize,
+    /// See [`DecryptReport::key_reversed`].
+    key_reversed: bool,
+    footer: Option<FooterScheme>,
+    lenient: bool,
+    post_transform: Option<Arc<dyn PostTransform>>,
+    #[cfg(feature = "hash")]
+    sha256: bool,
+    #[cfg(feature = "hash")]
+    digest: Option<Box<dyn DynDigest + Send + Sync>>,
+}
+
+fn new_cipher(key: &[u8]) -> Result<QMCv2Cipher, QmcCryptoError> {
+    QMCv2Cipher::try_new(key)
+}
+
+/// Cipher for `key`, or for `key` in reverse byte order if only that decrypts `head`
+/// (the start of the audio) to known audio.
+///
+/// returns: The cipher, and whether the key was reversed.
+fn new_cipher_oriented(head: &[u8], key: Vec<u8>) -> Result<(QMCv2Cipher, bool), QmcCryptoError> {
+    let decrypts_head = |cipher: &QMCv2Cipher| {
+        let mut head = head.to_vec();
+        cipher.decrypt(&mut head, 0);
+        is_known_audio(&head)
+    };
+
+    let cipher = new_cipher(&key)?;
+    if decrypts_head(&cipher) {
+        return Ok((cipher, false));
+    }
+    let mut reversed = key;
+    reversed.reverse();
+    match new_cipher(&reversed)? {
+        reversed_cipher if decrypts_head(&reversed_cipher) => {
+            trace_event!("key only works reversed");
+            Ok((reversed_cipher, true))
+        }
+        _ => Ok((cipher, false)),
+    }
+}
+
+/// `true` if `data` is plain audio for sure, not only by its magic: the MP3 and AAC
+/// sync words match about one in 2048 encrypted files.
+///
+/// * MP3: two consecutive frame headers after the ID3 tag.
+/// * FLAC: a `STREAMINFO` block right after the magic.
+/// * AAC: never, there is no cheap strict check.
+/// * Other formats: their magic, at least 4 bytes long.
+fn is_confirmed_plain_audio(data: &[u8]) -> bool {
+    let audio = &data[tag_key::id3_tag_len(data).min(data.len())..];
+    match um_audio::detect_audio_type(data) {
+        Ok(AudioType::MP3) => Mp3FrameHeader::parse(audio)
+            .and_then(|header| audio.get(header.frame_len()..))
+            .and_then(Mp3FrameHeader::parse)
+            .is_some(),
+        // offset 4: u8(last_block | block_type) u24(block_len)
+        Ok(AudioType::FLAC) => {
+            audio.starts_with(b"fLaC")
+                && audio
+                    .get(4..8)
+                    .is_some_and(|block| block[0] & 0x7F == 0 && block[1..] == [0, 0, 0x22])
+        }
+        Ok(AudioType::AAC | AudioType::Unknown) | Err(_) => false,
+        Ok(_) => true,
+    }
+}
+
+impl QmcDecoder {
+    /// Read and prepare the file at `path`.
+    ///
+    /// The key is taken from the file footer, or from its sidecar key file
+    /// (see [`dir::read_sidecar_key`]) if the footer has none.
+    /// See [`QmcDecoder::from_vec`] for keys stored in an ID3 tag.
+    pub fn open(path: &Path) -> Result<Self, QmcCryptoError> {
+        Self::open_with_options(path, &QmcDecoderOptions::default())
+    }
+
+    /// [`QmcDecoder::open`], refusing files over the limits of `options` before
+    /// reading them.
+    pub fn open_with_options(
+        path: &Path,
+        options: &QmcDecoderOptions,
+    ) -> Result<Self, QmcCryptoError> {
+        let metadata = fs::metadata(path).map_err(QmcCryptoError::IOError)?;
+        options.check_input_len(metadata.len())?;
+        let data = fs::read(path).map_err(QmcCryptoError::IOError)?;
+        Self::new(data, Some(path), options)
+    }
+
+    /// Read the whole of `reader`, then prepare it as with [`QmcDecoder::from_vec`].
+    ///
+    /// Reading stops as soon as the input is over the limits of `options`.
+    pub fn from_reader<R: Read>(
+        reader: R,
+        options: &QmcDecoderOptions,
+    ) -> Result<Self, QmcCryptoError> {
+        let max_read = options
+            .max_input_bytes
+            .map_or(u64::MAX, |limit| limit as u64 + 1);
+        let mut data = vec![];
+        reader
+            .take(max_read)
+            .read_to_end(&mut data)
+            .map_err(QmcCryptoError::IOError)?;
+        options.check_input_len(data.len() as u64)?;
+        Self::new(data, None, options)
+    }
+
+    /// Prepare `data`, using the key embedded in its footer.
+    ///
+    /// When there is no key in the footer, the key of some malformed exports is
+    /// looked for in an ID3 tag, see [`tag_key`]. A "footer" that would reach
+    /// into that tag is ignored.
+    pub fn from_vec(data: Vec<u8>) -> Result<Self, QmcCryptoError> {
+        Self::from_vec_with_options(data, &QmcDecoderOptions::default())
+    }
+
+    /// [`QmcDecoder::from_vec`], with the limits and EKey decoder of `options`.
+    pub fn from_vec_with_options(
+        data: Vec<u8>,
+        options: &QmcDecoderOptions,
+    ) -> Result<Self, QmcCryptoError> {
+        options.check_input_len(data.len() as u64)?;
+        Self::new(data, None, options)
+    }
+
+    fn new(
+        data: Vec<u8>,
+        path: Option<&Path>,
+        options: &QmcDecoderOptions,
+    ) -> Result<Self, QmcCryptoError> {
+        let tag_len = tag_key::id3_tag_len(&data);
+        let footer = footer::parse_footer(&data)
+            .filter(|footer| footer.size <= data.len().saturating_sub(tag_len));
+        let audio_len = match &footer {
+            Some(footer) => footer::expected_audio_len(footer, data.len()),
+            None => data.len(),
+        };
+        let scheme = footer.as_ref().map(|footer| footer.data.scheme());
+
+        let ekey = footer
+            .and_then(|footer| footer.ekey)
+            .filter(|ekey| !ekey.is_empty());
+        let ekey_decoder = options.ekey_decoder();
+        let mut audio_start = 0;
+        let key = match (ekey, tag_key::find_id3_priv_key(&data), path) {
+            (Some(ekey), _, _) => ekey_decoder.decode(&ekey)?,
+            (None, Some(tag_key), _) => {
+                audio_start = tag_key.tag_len;
+                ekey_decoder.decode(&tag_key.ekey)?
+            }
+            (None, None, Some(path)) if dir::sidecar_key_path(path).is_file() => {
+                ekey_decoder.decode(&dir::read_sidecar_ekey(path)?)?
+            }
+            (None, None, _) if is_confirmed_plain_audio(&data) => {
+                trace_event!("no key, input is already plain audio");
+                return Ok(Self::passthrough(data));
+            }
+            (None, None, Some(path)) => {
+                Err(QmcCryptoError::KeyNotFound(path.display().to_string()))?
+            }
+            (None, None, None) => {
+                Err(QmcCryptoError::KeyNotFound("file footer or ID3 tag".into()))?
+            }
+        };
+        let key_len = key.len();
+        let head_end = audio_len.min(audio_start + PROBE_SIZE);
+        let (cipher, key_reversed) = match data.get(audio_start..head_end) {
+            Some(head) => new_cipher_oriented(head, key)?,
+            None => (new_cipher(&key)?, false),
+        };
+
+        Ok(Self {
+            data,
+            audio_start,
+            audio_len,
+            cipher: Some(cipher),
+            key_len,
+            key_reversed,
+            footer: scheme,
+            lenient: false,
+            post_transform: None,
+            #[cfg(feature = "hash")]
+            sha256: false,
+            #[cfg(feature = "hash")]
+            digest: None,
+        })
+    }
+
+    /// Prepare `data`, using an already decoded `key`.
+    ///
+    /// The input is always decrypted, even if it looks like plain audio.
+    pub fn from_vec_with_key<T: AsRef<[u8]>>(
+        data: Vec<u8>,
+        key: T,
+    ) -> Result<Self, QmcCryptoError> {
+        let footer = Self::parse_footer(&data);
+        let audio_len = match &footer {
+            Some(footer) => footer::expected_audio_len(footer, data.len()),
+            None => data.len(),
+        };
+        let key = key.as_ref();
+        let cipher = QMCv2Cipher::try_new(key)?;
+        Ok(Self {
+            data,
+            audio_start: 0,
+            audio_len,
+            cipher: Some(cipher),
+            key_len: key.len(),
+            key_reversed: false,
+            footer: footer.map(|footer| footer.data.scheme()),
+            lenient: false,
+            post_transform: None,
+            #[cfg(feature = "hash")]
+            sha256: false,
+            #[cfg(feature = "hash")]
+            digest: None,
+        })
+    }
+
+    fn passthrough(data: Vec<u8>) -> Self {
+        Self {
+            audio_start: 0,
+            audio_len: data.len(),
+            data,
+            cipher: None,
+            key_len: 0,
+            key_reversed: false,
+            footer: None,
+            lenient: false,
+            post_transform: None,
+            #[cfg(feature = "hash")]
+            sha256: false,
+            #[cfg(feature = "hash")]
+            digest: None,
+        }
+    }
+
+    /// Keep going when [`QmcDecoder::decrypt_checked`] finds segments that don't look
+    /// like valid audio (default: `false`).
+    ///
+    /// Suspect segments are decrypted anyway, and listed in the report instead of
+    /// failing, e.g. to salvage what is left of a partially corrupt file.
+    pub fn lenient(mut self, lenient: bool) -> Self {
+        self.lenient = lenient;
+        self
+    }
+
+    /// Apply `post_transform` to the audio once decrypted, for variants that scramble
+    /// it further (default: none). Inputs that are already plain audio are left as-is.
+    pub fn post_transform<T: PostTransform + 'static>(mut self, post_transform: T) -> Self {
+        self.post_transform = Some(Arc::new(post_transform));
+        self
+    }
+
+    /// Decrypt `audio`, located at `offset` of the audio, then apply the post
+    /// transform if any.
+    fn decrypt_audio(&self, cipher: &QMCv2Cipher, audio: &mut [u8], offset: usize) {
+        cipher.decrypt(audio, offset);
+        if let Some(post_transform) = &self.post_transform {
+            post_transform.apply(audio, offset);
+        }
+    }
+
+    /// Hash the decrypted output as it is produced by [`QmcDecoder::decrypt_checked`],
+    /// and report it in [`DecryptReport::audio_sha256`] (default: `false`).
+    #[cfg(feature = "hash")]
+    pub fn sha256(mut self, sha256: bool) -> Self {
+        self.sha256 = sha256;
+        self
+    }
+
+    /// Feed the decrypted output to `digest` as it is produced by
+    /// [`QmcDecoder::decrypt_checked`], and report its output in
+    /// [`DecryptReport::audio_digest`] (default: none).
+    #[cfg(feature = "hash")]
+    pub fn digest<D: DynDigest + Send + Sync + 'static>(mut self, digest: D) -> Self {
+        self.digest = Some(Box::new(digest));
+        self
+    }
+
+    /// Parse the footer of `data`; a malformed footer is treated as no footer.
+    fn parse_footer(data: &[u8]) -> Option<footer::Metadata> {
+        let tail = &data[data.len().saturating_sub(footer::INITIAL_DETECTION_LEN)..];
+        footer::from_byte_slice(tail).unwrap_or_default()
+    }
+
+    /// `true` if the input was already plain audio, and will be returned as-is.
+    pub fn is_passthrough(&self) -> bool {
+        self.cipher.is_none()
+    }
+
+    /// Cipher of the audio, an [`IdentityCipher`] when the input was already plain
+    /// audio.
+    ///
+    /// Offsets are relative to the start of the audio, after any ID3 tag holding the
+    /// key; the post transform, if any, is not included.
+    pub fn cipher(&self) -> Box<dyn QmcCipher> {
+        match &self.cipher {
+            Some(cipher) => Box::new(cipher.clone()),
+            None => Box::new(IdentityCipher),
+        }
+    }
+
+    /// Describe how the file will be decoded.
+    ///
+    /// Only the start of the audio is decrypted, to detect its format: the first
+    /// [`PROBE_SIZE`] bytes, or up to the end of the tags at the start of the audio.
+    pub fn report(&self) -> DecryptReport {
+        let mut head_len = self.audio_len.min(self.audio_start + PROBE_SIZE);
+        let output_format = loop {
+            let mut head = self.data[..head_len].to_vec();
+            if let Some(cipher) = &self.cipher {
+                self.decrypt_audio(cipher, &mut head[self.audio_start..], 0);
+            }
+            match um_audio::detect_audio_type(&head) {
+                Ok(AudioType::Unknown) => break None,
+                Ok(format) => break Some(format),
+                // The head ends inside a tag (e.g. with a cover): decrypt past it.
+                Err(AudioError::NeedMoreHeader(len))
+                    if len > head_len && head_len < self.audio_len =>
+                {
+                    head_len = len.min(self.audio_len);
+                }
+                Err(_) => break None,
+            }
+        };
+        trace_event!(format = ?output_format, "detected output format");
+
+        DecryptReport {
+            cipher: self
+                .cipher
+                .as_ref()
+                .map_or(CipherKind::Passthrough, CipherKind::from),
+            key_len: self.key_len,
+            footer: self.footer,
+            output_format,
+            audio_bytes: self.audio_len,
+            suspect_ranges: vec![],
+            key_reversed: self.key_reversed,
+            audio_sha256: None,
+            audio_digest: None,
+        }
+    }
+
+    /// Decrypt the audio, with the footer removed.
+    pub fn into_vec(self) -> Vec<u8> {
+        self.decrypt_chunks(|_| {})
+    }
+
+    /// Decrypt the audio as in [`QmcDecoder::into_vec`], and detect its format.
+    ///
+    /// Any container framing is removed, see [`um_audio::AudioPayload::extract`].
+    ///
+    /// returns: `(format, audio)`, or [`QmcCryptoError::UnknownAudio`] if the format
+    ///          of the decrypted audio is not recognised (e.g. with a wrong key).
+    pub fn decrypt_all(self) -> Result<(AudioType, Vec<u8>), QmcCryptoError> {
+        let data = self.into_vec();
+        let format = match um_audio::detect_audio_type(&data) {
+            Ok(AudioType::Unknown) | Err(_) => Err(QmcCryptoError::UnknownAudio)?,
+            Ok(format) => format,
+        };
+        let payload = um_audio::AudioPayload::extract(&data, format);
+        let audio = match payload.len() == data.len() {
+            true => data,
+            false => payload.to_vec(),
+        };
+        Ok((format, audio))
+    }
+
+    /// Decrypt the audio as in [`QmcDecoder::into_vec`], one segment at a time, and
+    /// pass each part of the output to `on_chunk` in order, once decrypted.
+    fn decrypt_chunks(mut self, mut on_chunk: impl FnMut(&[u8])) -> Vec<u8> {
+        let mut data = std::mem::take(&mut self.data);
+        let cipher = match &self.cipher {
+            Some(cipher) => cipher,
+            None => {
+                on_chunk(&data);
+                return data;
+            }
+        };
+
+        data.truncate(self.audio_len);
+        let (tag, audio) = data.split_at_mut(self.audio_start);
+        on_chunk(tag);
+        let mut offset = 0;
+        for chunk in audio.chunks_mut(OTHER_SEGMENT_SIZE) {
+            self.decrypt_audio(cipher, chunk, offset);
+            offset += chunk.len();
+            on_chunk(chunk);
+        }
+        data
+    }
+
+    /// Decrypt the audio as in [`QmcDecoder::into_vec`], then check each segment of
+    /// [`OTHER_SEGMENT_SIZE`] bytes for valid audio frames (see
+    /// [`QMC2RC4::verify_segments`](crate::v2_rc4::cipher::QMC2RC4::verify_segments)).
+    ///
+    /// Suspect segments fail with [`QmcCryptoError::CorruptSegments`], unless the
+    /// decoder is [lenient](QmcDecoder::lenient): they are then reported in
+    /// [`DecryptReport::suspect_ranges`], as ranges of the returned data.
+    #[cfg_attr(not(feature = "hash"), allow(unused_mut))]
+    pub fn decrypt_checked(mut self) -> Result<(Vec<u8>, DecryptReport), QmcCryptoError> {
+        let mut report = self.report();
+        let (audio_start, lenient) = (self.audio_start, self.lenient);
+        #[cfg(feature = "hash")]
+        let (mut hasher, mut digest) = (self.sha256.then(Sha256::new), self.digest.take());
+        let data = self.decrypt_chunks(|_chunk| {
+            #[cfg(feature = "hash")]
+            {
+                if let Some(hasher) = &mut hasher {
+                    Digest::update(hasher, _chunk);
+                }
+                if let Some(digest) = &mut digest {
+                    DynDigest::update(digest.as_mut(), _chunk);
+                }
+            }
+        });
+        #[cfg(feature = "hash")]
+        {
+            report.audio_sha256 = hasher.map(|hasher| hasher.finalize().into());
+            report.audio_digest = digest.map(|mut digest| {
+                let mut output = vec![0u8; DynDigest::output_size(digest.as_ref())];
+                digest
+                    .finalize_into_reset(&mut output)
+                    .expect("buffer has the output size");
+                output
+            });
+        }
+
+        if report.cipher == CipherKind::Passthrough {
+            return Ok((data, report));
+        }
+        if let Some(format) = report.output_format {
+            report.suspect_ranges = suspect_ranges(&data, audio_start, format);
+        }
+        if !report.suspect_ranges.is_empty() {
+            trace_event!(ranges = ?report.suspect_ranges, "suspect segments");
+            if !lenient {
+                return Err(QmcCryptoError::CorruptSegments(report.suspect_ranges));
+            }
+        }
+        Ok((data, report))
+    }
+}
+
+/// Suspect segments of the audio in `data` (starting at `audio_start`), with adjacent
+/// segments merged.
+fn suspect_ranges(data: &[u8], audio_start: usize, format: AudioType) -> Vec<Range<usize>> {
+    let audio = &data[audio_start..];
+    let mut ranges: Vec<Range<usize>> = vec![];
+    for (i, valid) in segment_validity(audio, format, OTHER_SEGMENT_SIZE)
+        .into_iter()
+        .enumerate()
+    {
+        if valid {
+            continue;
+        }
+
+        let start = audio_start + i * OTHER_SEGMENT_SIZE;
+        let end = data.len().min(start + OTHER_SEGMENT_SIZE);
+        match ranges.last_mut() {
+            Some(last) if last.end == start => last.end = end,
+            _ => ranges.push(start..end),
+        }
+    }
+    ranges
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ekey;
+    use crate::footer::tests::append_qtag;
+    use crate::tag_key::tests::make_id3_with_priv_key;
+    use um_audio::mp3::tests::make_mp3_frame;
+
+    #[test]
+    fn test_max_input_bytes() {
+        let options = QmcDecoderOptions {
+            max_input_bytes: Some(0x100),
+            ..Default::default()
+        };
+        // No key anywhere: any attempt to decrypt would fail with `KeyNotFound`.
+        let data = vec![0x55u8; 0x101];
+        let result = QmcDecoder::from_reader(data.as_slice(), &options);
+        assert!(matches!(
+            result,
+            Err(QmcCryptoError::InputTooLarge { limit: 0x100 })
+        ));
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("large.mflac");
+        fs::write(&path, &data).unwrap();
+        let result = QmcDecoder::open_with_options(&path, &options);
+        assert!(matches!(
+            result,
+            Err(QmcCryptoError::InputTooLarge { limit: 0x100 })
+        ));
+
+        let result = QmcDecoder::from_reader(&data[..0x100], &options);
+        assert!(matches!(result, Err(QmcCryptoError::KeyNotFound(_))));
+    }
+
+    #[test]
+    fn test_plaintext_passthrough() {
+        let mut data = b"fLaC\x00\x00\x00\x22".to_vec();
+        data.extend((0..0x1000).map(|i| i as u8));
+
+        let decoder = QmcDecoder::from_vec(data.clone()).expect("should accept plain flac");
+        assert!(decoder.is_passthrough());
+
+        let mut audio = data.clone();
+        decoder.cipher().decrypt(&mut audio, 0);
+        assert_eq!(audio, data);
+        assert_eq!(decoder.into_vec(), data);
+    }
+
+    #[test]
+    fn test_report_qtag_mp3() {
+        const EKEY: &str = include_str!("v2_rc4/fixtures/ekey_rc4_512.txt");
+
+        let mut plaintext = make_mp3_frame();
+        plaintext.resize(0x2000, 0);
+        let mut data = plaintext.clone();
+        QMCv2Cipher::new_from_ekey(EKEY)
+            .unwrap()
+            .decrypt(&mut data, 0);
+
+        append_qtag(&mut data, EKEY);
+
+        let decoder = QmcDecoder::from_vec(data).unwrap();
+        let report = decoder.report();
+        assert_eq!(
+            report,
+            DecryptReport {
+                cipher: CipherKind::RC4,
+                key_len: 512,
+                footer: Some(FooterScheme::AndroidQTag),
+                output_format: Some(AudioType::MP3),
+                audio_bytes: plaintext.len(),
+                suspect_ranges: vec![],
+                key_reversed: false,
+                audio_sha256: None,
+                audio_digest: None,
+            }
+        );
+        assert_eq!(
+            report.to_string(),
+            "cipher=QMCv2/RC4, key_len=512, footer=QTag, format=mp3, audio_bytes=8192"
+        );
+        assert_eq!(decoder.into_vec(), plaintext);
+    }
+
+    #[test]
+    fn test_custom_ekey_decoder() {
+        use crate::test::generate_key;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        struct FixedKeyDecoder(AtomicUsize);
+        impl EKeyDecoder for FixedKeyDecoder {
+            fn decode(&self, ekey_b64: &str) -> Result<Vec<u8>, QmcCryptoError> {
+                assert_eq!(ekey_b64, "Y3VzdG9tIGVrZXk=");
+                self.0.fetch_add(1, Ordering::SeqCst);
+                Ok(generate_key(512))
+            }
+        }
+
+        let mut plaintext = b"fLaC\x00\x00\x00\x22".to_vec();
+        plaintext.resize(0x2000, 0);
+        let mut data = plaintext.clone();
+        QMCv2Cipher::new(generate_key(512))
+            .unwrap()
+            .decrypt(&mut data, 0);
+        append_qtag(&mut data, "Y3VzdG9tIGVrZXk=");
+
+        let decoder = Arc::new(FixedKeyDecoder(AtomicUsize::new(0)));
+        let options = QmcDecoderOptions {
+            ekey_decoder: Some(decoder.clone()),
+            ..Default::default()
+        };
+        let qmc = QmcDecoder::from_vec_with_options(data, &options).unwrap();
+        assert_eq!(decoder.0.load(Ordering::SeqCst), 1);
+        assert_eq!(qmc.into_vec(), plaintext);
+    }
+
+    #[test]
+    fn test_post_transform_runs_after_decrypt() {
+        use crate::test::generate_key;
+
+        struct Invert;
+        impl PostTransform for Invert {
+            fn apply(&self, data: &mut [u8], _offset: usize) {
+                data.iter_mut().for_each(|b| *b = !*b);
+            }
+        }
+
+        let mut plaintext = b"fLaC\x00\x00\x00\x22".to_vec();
+        plaintext.extend((0..0x3000).map(|i| i as u8));
+        let mut data = plaintext.iter().map(|b| !b).collect::<Vec<_>>();
+        QMCv2Cipher::new(generate_key(512))
+            .unwrap()
+            .decrypt(&mut data, 0);
+
+        let decoder = QmcDecoder::from_vec_with_key(data, generate_key(512))
+            .unwrap()
+            .post_transform(Invert);
+        assert_eq!(decoder.report().output_format, Some(AudioType::FLAC));
+        assert_eq!(decoder.into_vec(), plaintext);
+    }
+
+    #[test]
+    fn test_reversed_footer_key() {
+        const EKEY: &str = include_str!("v2_rc4/fixtures/ekey_rc4_512.txt");
+        let mut reversed_key = ekey::decrypt(EKEY).unwrap();
+        reversed_key.reverse();
+
+        let mut plaintext = b"fLaC\x00\x00\x00\x22".to_vec();
+        plaintext.resize(0x2000, 0);
+        let mut data = plaintext.clone();
+        QMCv2Cipher::new(&reversed_key)
+            .unwrap()
+            .decrypt(&mut data, 0);
+        append_qtag(&mut data, EKEY);
+
+        let decoder = QmcDecoder::from_vec(data).unwrap();
+        let report = decoder.report();
+        assert!(report.key_reversed);
+        assert_eq!(report.output_format, Some(AudioType::FLAC));
+        assert!(report.to_string().ends_with(", key=reversed"));
+        assert_eq!(decoder.into_vec(), plaintext);
+    }
+
+    #[test]
+    fn test_reversed_footer_key_large_id3() {
+        const EKEY: &str = include_str!("v2_rc4/fixtures/ekey_rc4_512.txt");
+        let mut reversed_key = ekey::decrypt(EKEY).unwrap();
+        reversed_key.reverse();
+
+        // The ID3 tag (e.g. with a cover) goes past the probed head.
+        let mut plaintext = b"ID3\x04\x00\x00\x00\x00\x10\x00".to_vec();
+        plaintext.extend_from_slice(&[0u8; 0x800]);
+        plaintext.extend_from_slice(b"fLaC\x00\x00\x00\x22");
+        plaintext.resize(0x2000, 0);
+        let mut data = plaintext.clone();
+        QMCv2Cipher::new(&reversed_key)
+            .unwrap()
+            .decrypt(&mut data, 0);
+        append_qtag(&mut data, EKEY);
+
+        let decoder = QmcDecoder::from_vec(data).unwrap();
+        assert!(decoder.report().key_reversed);
+        assert_eq!(decoder.into_vec(), plaintext);
+    }
+
+    #[test]
+    fn test_footer_wins_over_audio_sniff() {
+        const EKEY: &str = include_str!("v2_rc4/fixtures/ekey_rc4_512.txt");
+        let cipher = QMCv2Cipher::new_from_ekey(EKEY).unwrap();
+
+        // Starting with an MP3 sync word or an `ftyp` box doesn't make the input plain
+        // audio when its footer holds a key.
+        for head in [&[0xFF, 0xFB, 0x90, 0x64][..], b"\x00\x00\x00\x20ftypisom"] {
+            let mut encrypted = head.to_vec();
+            encrypted.extend((0..0x2000).map(|i| (i * 7) as u8));
+            let mut plaintext = encrypted.clone();
+            cipher.decrypt(&mut plaintext, 0);
+
+            let mut data = encrypted;
+            append_qtag(&mut data, EKEY);
+
+            let decoder = QmcDecoder::from_vec(data).unwrap();
+            assert!(!decoder.is_passthrough());
+            assert_eq!(decoder.into_vec(), plaintext);
+        }
+    }
+
+    #[test]
+    fn test_passthrough_needs_strict_match() {
+        let frame = make_mp3_frame();
+
+        // A lone sync word is not enough.
+        let mut data = frame.clone();
+        data.extend_from_slice(&[0x55; 0x100]);
+        assert!(matches!(
+            QmcDecoder::from_vec(data),
+            Err(QmcCryptoError::KeyNotFound(_))
+        ));
+
+        let data = frame.repeat(3);
+        let decoder = QmcDecoder::from_vec(data.clone()).unwrap();
+        assert!(decoder.is_passthrough());
+        assert_eq!(decoder.into_vec(), data);
+
+        // FLAC without a STREAMINFO block.
+        let mut data = b"fLaC\x84\x00\x00\x10".to_vec();
+        data.resize(0x100, 0x55);
+        assert!(matches!(
+            QmcDecoder::from_vec(data),
+            Err(QmcCryptoError::KeyNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn test_report_mp3_large_id3() {
+        const EKEY: &str = include_str!("v2_rc4/fixtures/ekey_rc4_512.txt");
+
+        // The ID3 tag (e.g. with a cover) goes past the probed head.
+        let mut plaintext = b"ID3\x04\x00\x00\x00\x00\x10\x00".to_vec();
+        plaintext.extend_from_slice(&[0u8; 0x800]);
+        plaintext.extend(make_mp3_frame());
+        plaintext.resize(0x2000, 0);
+        let mut data = plaintext.clone();
+        QMCv2Cipher::new_from_ekey(EKEY)
+            .unwrap()
+            .decrypt(&mut data, 0);
+
+        append_qtag(&mut data, EKEY);
+
+        let report = QmcDecoder::from_vec(data).unwrap().report();
+        assert_eq!(report.output_format, Some(AudioType::MP3));
+    }
+
+    #[test]
+    fn test_report_passthrough() {
+        let mut data = b"fLaC\x00\x00\x00\x22".to_vec();
+        data.extend((0..0x1000).map(|i| i as u8));
+
+        let report = QmcDecoder::from_vec(data.clone()).unwrap().report();
+        assert_eq!(report.cipher, CipherKind::Passthrough);
+        assert_eq!(report.key_len, 0);
+        assert_eq!(report.footer, None);
+        assert_eq!(report.output_format, Some(AudioType::FLAC));
+        assert_eq!(report.audio_bytes, data.len());
+    }
+
+    #[test]
+    fn test_key_in_id3_priv_frame() {
+        const EKEY: &str = include_str!("v2_rc4/fixtures/ekey_rc4_512.txt");
+
+        let tag = make_id3_with_priv_key(EKEY);
+        let mut audio = make_mp3_frame();
+        audio.resize(0x2000, 0);
+
+        let mut data = tag.clone();
+        let mut encrypted = audio.clone();
+        QMCv2Cipher::new_from_ekey(EKEY)
+            .unwrap()
+            .decrypt(&mut encrypted, 0);
+        data.extend_from_slice(&encrypted);
+
+        let decoder = QmcDecoder::from_vec(data).expect("should find key in ID3 tag");
+        let report = decoder.report();
+        assert_eq!(report.footer, None);
+        assert_eq!(report.output_format, Some(AudioType::MP3));
+
+        let mut expected = tag;
+        expected.extend_from_slice(&audio);
+        assert_eq!(decoder.into_vec(), expected);
+    }
+
+    #[test]
+    fn test_missing_key_with_id3_tag() {
+        let mut data = make_id3_with_priv_key("");
+        data.extend_from_slice(&[0x55u8; 0x1000]);
+        let result = QmcDecoder::from_vec(data);
+        assert!(matches!(result, Err(QmcCryptoError::KeyNotFound(_))));
+    }
+
+    #[test]
+    fn test_missing_key() {
+        let data = vec![0u8; 0x1000];
+        let result = QmcDecoder::from_vec(data);
+        assert!(matches!(result, Err(QmcCryptoError::KeyNotFound(_))));
+    }
+
+    #[test]
+    fn test_lenient_reports_corrupt_segment() {
+        const EKEY: &str = include_str!("v2_rc4/fixtures/ekey_rc4_512.txt");
+
+        let frame = make_mp3_frame();
+        let mut plaintext = frame.repeat(OTHER_SEGMENT_SIZE * 3 / frame.len() + 1);
+        plaintext.truncate(OTHER_SEGMENT_SIZE * 3);
+
+        let mut data = plaintext.clone();
+        QMCv2Cipher::new_from_ekey(EKEY)
+            .unwrap()
+            .decrypt(&mut data, 0);
+        let corrupt = OTHER_SEGMENT_SIZE..OTHER_SEGMENT_SIZE * 2;
+        data[corrupt.clone()].iter_mut().for_each(|b| *b ^= 0xFF);
+
+        append_qtag(&mut data, EKEY);
+
+        let result = QmcDecoder::from_vec(data.clone())
+            .unwrap()
+            .decrypt_checked();
+        assert!(
+            matches!(result, Err(QmcCryptoError::CorruptSegments(ranges)) if ranges == std::slice::from_ref(&corrupt))
+        );
+
+        let (decrypted, report) = QmcDecoder::from_vec(data)
+            .unwrap()
+            .lenient(true)
+            .decrypt_checked()
+            .expect("lenient decoding should complete");
+        assert_eq!(report.suspect_ranges, std::slice::from_ref(&corrupt));
+        assert_eq!(decrypted.len(), plaintext.len());
+        assert_eq!(decrypted[..corrupt.start], plaintext[..corrupt.start]);
+        assert_eq!(decrypted[corrupt.end..], plaintext[corrupt.end..]);
+    }
+
+    #[cfg(feature = "hash")]
+    #[test]
+    fn test_audio_sha256() {
+        const EKEY: &str = include_str!("v2_rc4/fixtures/ekey_rc4_512.txt");
+
+        let mut plaintext = make_mp3_frame();
+        plaintext.resize(0x5000, 0);
+        let mut data = plaintext.clone();
+        QMCv2Cipher::new_from_ekey(EKEY)
+            .unwrap()
+            .decrypt(&mut data, 0);
+        append_qtag(&mut data, EKEY);
+
+        let (decrypted, report) = QmcDecoder::from_vec(data)
+            .unwrap()
+            .lenient(true)
+            .sha256(true)
+            .decrypt_checked()
+            .unwrap();
+        let expected: [u8; 32] = Sha256::digest(&decrypted).into();
+        assert_eq!(report.audio_sha256, Some(expected));
+        assert_eq!(decrypted, plaintext);
+    }
+
+    #[cfg(feature = "hash")]
+    #[test]
+    fn test_audio_digest() {
+        use sha2::Sha512;
+        const EKEY: &str = include_str!("v2_rc4/fixtures/ekey_rc4_512.txt");
+
+        let mut plaintext = make_mp3_frame();
+        plaintext.resize(0x5000, 0);
+        let mut data = plaintext.clone();
+        QMCv2Cipher::new_from_ekey(EKEY)
+            .unwrap()
+            .decrypt(&mut data, 0);
+
+        let (decrypted, report) = QmcDecoder::from_vec_with_key(data, ekey::decrypt(EKEY).unwrap())
+            .unwrap()
+            .lenient(true)
+            .digest(Sha512::new())
+            .decrypt_checked()
+            .unwrap();
+        assert_eq!(decrypted, plaintext);
+        assert_eq!(report.audio_sha256, None);
+        assert_eq!(
+            report.audio_digest,
+            Some(Sha512::digest(&decrypted).to_vec())
+        );
+    }
+}
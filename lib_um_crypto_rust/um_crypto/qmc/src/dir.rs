@@ -0,0 +1,261 @@
+use crate::v2_rc4::cipher::OTHER_SEGMENT_SIZE;
+use crate::{ekey, footer, QMCv2Cipher, QmcCryptoError};
+use std::collections::HashMap;
+use std::ffi::{OsStr, OsString};
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use um_audio::AudioType;
+
+const SIDECAR_KEY_EXT: &str = "key";
+
+/// Chunk size of [`decrypt_file_to_file`], a multiple of the RC4 segment size.
+const STREAM_CHUNK_SIZE: usize = OTHER_SEGMENT_SIZE * 256;
+
+/// Path of the sidecar key file for `audio_path`, e.g. `song.mflac` -> `song.mflac.key`.
+pub(crate) fn sidecar_key_path(audio_path: &Path) -> PathBuf {
+    let mut path = OsString::from(audio_path.as_os_str());
+    path.push(".");
+    path.push(SIDECAR_KEY_EXT);
+    PathBuf::from(path)
+}
+
+/// Read the base64 encoded EKey stored next to `audio_path` (e.g. `song.mflac.key`)
+/// and decode it.
+///
+/// returns: The decoded key, ready to be passed to [`QMCv2Cipher::new`].
+pub fn read_sidecar_key(audio_path: &Path) -> Result<Vec<u8>, QmcCryptoError> {
+    let ekey_str = read_sidecar_ekey(audio_path)?;
+    ekey::decrypt(ekey_str).map_err(QmcCryptoError::EKeyDecodeError)
+}
+
+/// Base64 encoded EKey stored next to `audio_path`, not decoded.
+pub(crate) fn read_sidecar_ekey(audio_path: &Path) -> Result<String, QmcCryptoError> {
+    let ekey_str =
+        fs::read_to_string(sidecar_key_path(audio_path)).map_err(QmcCryptoError::IOError)?;
+    Ok(ekey_str.trim().to_string())
+}
+
+/// Size of the footer to trim off the end of `data`, `0` when not found.
+fn get_footer_size(data: &[u8]) -> usize {
+    let tail = &data[data.len().saturating_sub(footer::INITIAL_DETECTION_LEN)..];
+    match footer::from_byte_slice(tail) {
+        Ok(Some(metadata)) => metadata.size,
+        _ => 0,
+    }
+}
+
+/// Output buffers reused across files, to avoid an allocation per file.
+///
+/// Buffers are handed out by [`DecryptPool::take`] (or filled by
+/// [`DecryptPool::decrypt_file`]), and their allocation is reused once given
+/// back with [`DecryptPool::put`].
+#[derive(Debug, Default)]
+pub struct DecryptPool {
+    free: Vec<Vec<u8>>,
+}
+
+impl DecryptPool {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// An empty buffer, reusing the allocation of a returned one if possible.
+    pub fn take(&mut self) -> Vec<u8> {
+        let mut buffer = self.free.pop().unwrap_or_default();
+        buffer.clear();
+        buffer
+    }
+
+    /// Give `buffer` back to the pool.
+    pub fn put(&mut self, buffer: Vec<u8>) {
+        self.free.push(buffer);
+    }
+
+    /// Number of buffers waiting to be reused.
+    pub fn available(&self) -> usize {
+        self.free.len()
+    }
+
+    /// Read the file at `path` into a pooled buffer, and decrypt its audio in place.
+    ///
+    /// The footer is trimmed off. Give the buffer back with [`DecryptPool::put`]
+    /// once done with it.
+    pub fn decrypt_file(
+        &mut self,
+        path: &Path,
+        cipher: &QMCv2Cipher,
+    ) -> Result<Vec<u8>, QmcCryptoError> {
+        let mut data = self.take();
+        let read = File::open(path).and_then(|mut file| file.read_to_end(&mut data));
+        if let Err(err) = read {
+            self.put(data);
+            return Err(QmcCryptoError::IOError(err));
+        }
+
+        let audio_len = data.len() - get_footer_size(&data);
+        data.truncate(audio_len);
+        cipher.decrypt(&mut data, 0);
+        Ok(data)
+    }
+}
+
+/// Decrypt the file at `src` to `dst` with `key`, without reading it all in memory.
+///
+/// The file is streamed through a single reused buffer, in chunks aligned to the
+/// cipher segments. The footer is detected from the end of `src` and left out.
+///
+/// returns: Number of bytes written to `dst`.
+pub fn decrypt_file_to_file(src: &Path, dst: &Path, key: &[u8]) -> io::Result<u64> {
+    let cipher = QMCv2Cipher::try_new(key)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+
+    let mut input = File::open(src)?;
+    let file_len = input.metadata()?.len();
+    let tail_len = file_len.min(footer::INITIAL_DETECTION_LEN as u64);
+    let mut tail = vec![0u8; tail_len as usize];
+    input.seek(SeekFrom::Start(file_len - tail_len))?;
+    input.read_exact(&mut tail)?;
+    input.seek(SeekFrom::Start(0))?;
+    let audio_len = file_len - get_footer_size(&tail) as u64;
+
+    let mut input = input.take(audio_len);
+    let mut output = File::create(dst)?;
+    let mut buffer = vec![0u8; STREAM_CHUNK_SIZE];
+    let mut offset = 0usize;
+    loop {
+        let n = read_chunk(&mut input, &mut buffer)?;
+        if n == 0 {
+            break;
+        }
+        cipher.decrypt(&mut buffer[..n], offset);
+        output.write_all(&buffer[..n])?;
+        offset += n;
+    }
+    output.flush()?;
+
+    Ok(offset as u64)
+}
+
+/// Fill `buffer` as much as possible, so chunks stay aligned until the end of `r`.
+fn read_chunk(r: &mut impl Read, buffer: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match r.read(&mut buffer[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(filled)
+}
+
+/// Decrypt a single file with the given cipher, and write it to `out_dir`.
+///
+/// The output file name is the input file stem, with the detected audio extension
+/// (for OGG, the one of its codec, e.g. `.oga` for FLAC-in-OGG).
+fn decrypt_file_with_cipher(
+    path: &Path,
+    cipher: &QMCv2Cipher,
+    out_dir: &Path,
+    pool: &mut DecryptPool,
+) -> Result<PathBuf, QmcCryptoError> {
+    let stem = path.file_stem().unwrap_or(path.as_os_str());
+    decrypt_file_as(path, cipher, out_dir, stem, pool)
+}
+
+/// Same as [`decrypt_file_with_cipher`], naming the output file `stem` instead.
+pub(crate) fn decrypt_file_as(
+    path: &Path,
+    cipher: &QMCv2Cipher,
+    out_dir: &Path,
+    stem: &OsStr,
+    pool: &mut DecryptPool,
+) -> Result<PathBuf, QmcCryptoError> {
+    let data = pool.decrypt_file(path, cipher)?;
+
+    let ext = match um_audio::detect_audio_type(&data) {
+        Ok(AudioType::OGG) => {
+            um_audio::ogg_codec(&data).map_or(AudioType::OGG.extension(), |codec| codec.extension())
+        }
+        Ok(format) => format.extension(),
+        Err(_) => AudioType::Unknown.extension(),
+    };
+    // Not `with_extension`, which would cut stems with a dot in them ("Mr. Brightside").
+    let mut file_name = stem.to_os_string();
+    file_name.push(".");
+    file_name.push(ext);
+    let out_path = out_dir.join(file_name);
+    let written = fs::write(&out_path, &data).map_err(QmcCryptoError::IOError);
+    pool.put(data);
+    written?;
+
+    Ok(out_path)
+}
+
+/// List regular files in `dir` (except sidecar key files), sorted by path.
+fn list_files(dir: &Path) -> Result<Vec<PathBuf>, QmcCryptoError> {
+    let mut files = vec![];
+    for entry in fs::read_dir(dir).map_err(QmcCryptoError::IOError)? {
+        let entry = entry.map_err(QmcCryptoError::IOError)?;
+        let path = entry.path();
+        let is_sidecar = path.extension().is_some_and(|ext| ext == SIDECAR_KEY_EXT);
+        if path.is_file() && !is_sidecar {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Decrypt every file in `dir` (non-recursive), looking up its EKey by file name.
+///
+/// # Arguments
+///
+/// * `dir`: Directory containing encrypted files.
+/// * `keymap`: File name (e.g. `song.mflac`) to base64 encoded EKey.
+/// * `out`: Directory to write the decrypted files to.
+///
+/// returns: `(input, output)` for each file. Files without an entry in `keymap`
+///          fall back to their sidecar key file (see [`read_sidecar_key`]), and
+///          report [`QmcCryptoError::KeyNotFound`] if there is none.
+pub fn decrypt_dir_with_keymap(
+    dir: &Path,
+    keymap: &HashMap<String, String>,
+    out: &Path,
+) -> Vec<(PathBuf, Result<PathBuf, QmcCryptoError>)> {
+    decrypt_dir_with_keymap_in(dir, keymap, out, &mut DecryptPool::new())
+}
+
+/// Same as [`decrypt_dir_with_keymap`], using the buffers of `pool`.
+pub fn decrypt_dir_with_keymap_in(
+    dir: &Path,
+    keymap: &HashMap<String, String>,
+    out: &Path,
+    pool: &mut DecryptPool,
+) -> Vec<(PathBuf, Result<PathBuf, QmcCryptoError>)> {
+    let files = match list_files(dir) {
+        Ok(files) => files,
+        Err(err) => return vec![(dir.to_path_buf(), Err(err))],
+    };
+
+    files
+        .into_iter()
+        .map(|path| {
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            let cipher = match keymap.get(name.as_ref()) {
+                Some(ekey) => ekey::decrypt(ekey.as_str())
+                    .map_err(QmcCryptoError::EKeyDecodeError)
+                    .and_then(|key| QMCv2Cipher::try_new(&key)),
+                None if sidecar_key_path(&path).is_file() => {
+                    read_sidecar_key(&path).and_then(|key| QMCv2Cipher::try_new(&key))
+                }
+                None => Err(QmcCryptoError::KeyNotFound(name.to_string())),
+            };
+            let result =
+                cipher.and_then(|cipher| decrypt_file_with_cipher(&path, &cipher, out, pool));
+            (path, result)
+        })
+        .collect()
+}
@@ -1,13 +1,16 @@
+use crate::QmcCryptoError;
 use anyhow::Result;
 use itertools::Itertools;
 use lazy_static::lazy_static;
+use std::borrow::Cow;
 use std::ops::Mul;
 use tc_tea::TcTeaError;
 use thiserror::Error;
 use umc_utils::base64;
 
 /// Base64 encoded prefix: "QQMusic EncV2,Key:"
-const EKEY_V2_PREFIX: &[u8; 24] = b"UVFNdXNpYyBFbmNWMixLZXk6";
+pub(crate) const EKEY_V2_PREFIX: &[u8; 24] = b"UVFNdXNpYyBFbmNWMixLZXk6";
+const UTF8_BOM: &[u8; 3] = b"\xEF\xBB\xBF";
 const EKEY_V2_KEY1: [u8; 16] = [
     0x33, 0x38, 0x36, 0x5A, 0x4A, 0x59, 0x21, 0x40, 0x23, 0x2A, 0x24, 0x25, 0x5E, 0x26, 0x29, 0x28,
 ];
@@ -61,6 +64,27 @@ pub fn decrypt_v1(ekey: &[u8]) -> Result<Vec<u8>> {
     Ok([header, &plaintext].concat())
 }
 
+/// Encrypt `key` as a (v1) EKey, the reverse of [`decrypt_v1`].
+///
+/// A fixed salt is used, so the output is deterministic. Fixtures only.
+#[cfg(feature = "fixtures")]
+pub fn encrypt_v1(key: &[u8]) -> Result<String> {
+    if key.len() < 8 {
+        Err(EKeyDecryptError::EKeyTooShort)?;
+    }
+
+    let (header, plaintext) = key.split_at(8);
+    let tea_key = EKEY_SIMPLE_KEY
+        .iter()
+        .zip(header)
+        .flat_map(|(&simple_key_part, &header_part)| [simple_key_part, header_part])
+        .collect_vec();
+
+    let cipher = tc_tea::encrypt_with_salt(plaintext, tea_key, &[0u8; 10])
+        .map_err(EKeyDecryptError::FailDecryptV1)?;
+    Ok(base64::encode([header, &cipher].concat()))
+}
+
 pub fn decrypt_v2(ekey: &[u8]) -> Result<Vec<u8>> {
     let ekey = base64::decode(ekey)?;
     let ekey = tc_tea::decrypt(ekey, EKEY_V2_KEY1).map_err(EKeyDecryptError::FailDecryptV2)?;
@@ -70,10 +94,84 @@ pub fn decrypt_v2(ekey: &[u8]) -> Result<Vec<u8>> {
     decrypt_v1(&ekey)
 }
 
+/// Strip what copy-pasting tends to add around an EKey: a UTF-8 BOM, surrounding
+/// whitespace and quotes. The URL-safe base64 alphabet is mapped to the standard one.
+fn normalize_ekey(ekey: &[u8]) -> Cow<'_, [u8]> {
+    let ekey = ekey.strip_prefix(UTF8_BOM).unwrap_or(ekey).trim_ascii();
+    let ekey = match ekey {
+        [b'"', inner @ .., b'"'] | [b'\'', inner @ .., b'\''] => inner.trim_ascii(),
+        _ => ekey,
+    };
+
+    match ekey.iter().any(|&b| b == b'-' || b == b'_') {
+        true => Cow::Owned(
+            ekey.iter()
+                .map(|&b| match b {
+                    b'-' => b'+',
+                    b'_' => b'/',
+                    b => b,
+                })
+                .collect(),
+        ),
+        false => Cow::Borrowed(ekey),
+    }
+}
+
+/// Decrypt a base64 encoded EKey, v1 or v2.
+///
+/// A leading UTF-8 BOM, surrounding quotes and whitespace are ignored, and both the
+/// standard and URL-safe base64 alphabets are accepted.
 pub fn decrypt<T: AsRef<[u8]>>(ekey: T) -> Result<Vec<u8>> {
-    let ekey = ekey.as_ref();
+    let ekey = normalize_ekey(ekey.as_ref());
     match ekey.strip_prefix(EKEY_V2_PREFIX) {
         Some(v2_ekey) => decrypt_v2(v2_ekey),
-        None => decrypt_v1(ekey),
+        None => decrypt_v1(&ekey),
+    }
+}
+
+/// Decodes a base64 EKey into the key of a file, see [`crate::decoder::QmcDecoderOptions`].
+///
+/// The default, [`TeaEKeyDecoder`], handles the current v1 / v2 schemes; implement
+/// this to plug in a newer scheme.
+pub trait EKeyDecoder: Send + Sync {
+    fn decode(&self, ekey_b64: &str) -> Result<Vec<u8>, QmcCryptoError>;
+}
+
+/// The TEA based EKey schemes, see [`decrypt`].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TeaEKeyDecoder;
+
+impl EKeyDecoder for TeaEKeyDecoder {
+    fn decode(&self, ekey_b64: &str) -> Result<Vec<u8>, QmcCryptoError> {
+        decrypt(ekey_b64).map_err(QmcCryptoError::EKeyDecodeError)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EKEY: &str = include_str!("v2_rc4/fixtures/ekey_rc4_512.txt");
+
+    #[test]
+    fn test_decrypt_tolerates_wrappers() {
+        let expected = decrypt(EKEY).unwrap();
+        for wrapped in [
+            format!("\u{FEFF}{EKEY}"),
+            format!("\"{EKEY}\""),
+            format!(" '{EKEY}' "),
+            format!("{EKEY}\r\n"),
+            format!("\u{FEFF} \"{EKEY}\"\n"),
+        ] {
+            assert_eq!(decrypt(&wrapped).unwrap(), expected, "{wrapped:?}");
+        }
+    }
+
+    #[test]
+    fn test_decrypt_url_safe_alphabet() {
+        let expected = decrypt(EKEY).unwrap();
+        let url_safe = EKEY.trim().replace('+', "-").replace('/', "_");
+        assert_ne!(url_safe, EKEY.trim());
+        assert_eq!(decrypt(url_safe).unwrap(), expected);
     }
 }
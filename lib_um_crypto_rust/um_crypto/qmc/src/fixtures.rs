@@ -0,0 +1,93 @@
+//! Helpers to generate encrypted test files.
+
+use crate::{ekey, QMCv2Cipher};
+use byteorder::{ByteOrder, BE, LE};
+
+/// Footer layout to write, see [`crate::footer`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum FooterScheme {
+    /// EKey followed by its length (u32, little endian).
+    PCv1Legacy,
+    /// Android `QTag`: `ekey,resource_id,2`, its length (u32, big endian), `QTag`.
+    AndroidQTag { resource_id: u64 },
+}
+
+/// Build a footer holding `ekey`.
+pub fn write_footer(ekey: &str, scheme: &FooterScheme) -> Vec<u8> {
+    let mut footer = vec![];
+    let mut len = [0u8; 4];
+
+    match scheme {
+        FooterScheme::PCv1Legacy => {
+            footer.extend_from_slice(ekey.as_bytes());
+            LE::write_u32(&mut len, ekey.len() as u32);
+            footer.extend_from_slice(&len);
+        }
+        FooterScheme::AndroidQTag { resource_id } => {
+            let payload = format!("{ekey},{resource_id},2");
+            footer.extend_from_slice(payload.as_bytes());
+            BE::write_u32(&mut len, payload.len() as u32);
+            footer.extend_from_slice(&len);
+            footer.extend_from_slice(b"QTag");
+        }
+    }
+
+    footer
+}
+
+/// Encrypt `plaintext` with `key`, and append a footer with the matching EKey.
+///
+/// # Panics
+///
+/// If `key` is shorter than 8 bytes.
+pub fn encrypt_file(plaintext: &[u8], key: &[u8], scheme: FooterScheme) -> Vec<u8> {
+    let cipher = QMCv2Cipher::new(key).expect("invalid key");
+    let ekey = ekey::encrypt_v1(key).expect("failed to encrypt key");
+
+    // The cipher is symmetric.
+    let mut data = plaintext.to_vec();
+    cipher.decrypt(&mut data, 0);
+    data.extend_from_slice(&write_footer(&ekey, &scheme));
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decoder::QmcDecoder;
+    use crate::footer;
+    use crate::test::generate_key;
+
+    #[test]
+    fn test_encrypt_v1_round_trip() {
+        let key = generate_key(512);
+        let ekey = ekey::encrypt_v1(&key).unwrap();
+        assert_eq!(ekey::decrypt(ekey).unwrap(), key);
+    }
+
+    #[test]
+    fn test_encrypt_file_round_trip() {
+        let mut plaintext = b"fLaC\x00\x00\x00\x22".to_vec();
+        plaintext.extend((0..0x3000).map(|i| i as u8));
+
+        let schemes = [
+            FooterScheme::PCv1Legacy,
+            FooterScheme::AndroidQTag {
+                resource_id: 123456,
+            },
+        ];
+        for key_len in [128, 512] {
+            let key = generate_key(key_len);
+            for scheme in schemes.iter().cloned() {
+                let file = encrypt_file(&plaintext, &key, scheme);
+
+                let tail = &file[file.len() - footer::INITIAL_DETECTION_LEN..];
+                let metadata = footer::from_byte_slice(tail).unwrap().unwrap();
+                assert_eq!(metadata.size, file.len() - plaintext.len());
+
+                let decoder = QmcDecoder::from_vec(file).unwrap();
+                assert_eq!(decoder.into_vec(), plaintext);
+            }
+        }
+    }
+}
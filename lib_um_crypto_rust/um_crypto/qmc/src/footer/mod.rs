@@ -1,18 +1,26 @@
 pub mod android_qtag;
 pub mod android_stag;
 mod musicex_v1;
+mod partial;
 pub mod pc_v1_legacy;
 pub mod pc_v2_musicex;
 mod utils;
 
+pub use crate::footer::partial::parse_footer_partial;
 use crate::footer::{
     android_qtag::QTagMetadata, android_stag::STagMetadata, pc_v1_legacy::PcV1Legacy,
     pc_v2_musicex::PcV2MusicEx,
 };
+use crate::v2_rc4::cipher::{is_known_audio, PROBE_SIZE};
+use crate::{ekey, QMCv2Cipher, QmcCipher, QmcCryptoError};
+use std::fmt::Display;
 use thiserror::Error;
 
 pub const INITIAL_DETECTION_LEN: usize = 1024;
 
+/// Maximum number of stacked footers to look at.
+const MAX_STACKED_FOOTERS: usize = 4;
+
 #[derive(Error, Debug)]
 pub enum FooterParseError {
     #[error("Footer: Buffer too small, require at least {0} bytes")]
@@ -61,6 +69,80 @@ pub enum Data {
     AndroidSTag(android_stag::STagMetadata),
 }
 
+impl Data {
+    pub fn scheme(&self) -> FooterScheme {
+        match self {
+            Data::PCv1Legacy(_) => FooterScheme::PCv1Legacy,
+            Data::PCv2MusicEx(_) => FooterScheme::PCv2MusicEx,
+            Data::AndroidQTag(_) => FooterScheme::AndroidQTag,
+            Data::AndroidSTag(_) => FooterScheme::AndroidSTag,
+        }
+    }
+}
+
+/// Footer type, without its data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FooterScheme {
+    PCv1Legacy,
+    PCv2MusicEx,
+    AndroidQTag,
+    AndroidSTag,
+}
+
+impl Display for FooterScheme {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            FooterScheme::PCv1Legacy => "PCv1",
+            FooterScheme::PCv2MusicEx => "MusicEx",
+            FooterScheme::AndroidQTag => "QTag",
+            FooterScheme::AndroidSTag => "STag",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// Marker identifying a footer, see [`find_footer_markers`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FooterMarkerKind {
+    /// `STag`, Android metadata only footer.
+    STag,
+    /// `QTag`, Android footer with ekey.
+    QTag,
+    /// `musicex\0`, PC "MusicEx" footer.
+    MusicEx,
+}
+
+impl FooterMarkerKind {
+    const ALL: [FooterMarkerKind; 3] = [
+        FooterMarkerKind::STag,
+        FooterMarkerKind::QTag,
+        FooterMarkerKind::MusicEx,
+    ];
+
+    pub fn marker(&self) -> &'static [u8] {
+        match self {
+            FooterMarkerKind::STag => b"STag",
+            FooterMarkerKind::QTag => b"QTag",
+            FooterMarkerKind::MusicEx => b"musicex\x00",
+        }
+    }
+}
+
+/// Find every known footer marker in `data`, with its offset, in file order.
+///
+/// Unlike [`parse_footer`], markers are looked for anywhere in `data` and are not
+/// validated, e.g. to inspect files with misplaced or stacked footers.
+pub fn find_footer_markers(data: &[u8]) -> Vec<(usize, FooterMarkerKind)> {
+    (0..data.len())
+        .flat_map(|offset| {
+            FooterMarkerKind::ALL
+                .into_iter()
+                .filter(move |kind| data[offset..].starts_with(kind.marker()))
+                .map(move |kind| (offset, kind))
+        })
+        .collect()
+}
+
 /// File Footer metadata
 #[derive(Debug, Clone, PartialEq)]
 pub struct Metadata {
@@ -74,10 +156,31 @@ pub struct Metadata {
     pub data: Data,
 }
 
+impl Metadata {
+    /// Decode the embedded EKey, and build the cipher matching its key length.
+    pub fn into_cipher(self) -> Result<Box<dyn QmcCipher>, QmcCryptoError> {
+        let ekey = self
+            .ekey
+            .ok_or_else(|| QmcCryptoError::KeyNotFound("file footer".into()))?;
+        let key = ekey::decrypt(ekey).map_err(QmcCryptoError::EKeyDecodeError)?;
+        let cipher = QMCv2Cipher::try_new(&key)?;
+
+        Ok(match cipher {
+            QMCv2Cipher::MapL(cipher) => Box::new(cipher),
+            QMCv2Cipher::RC4(cipher) => Box::new(cipher),
+        })
+    }
+}
+
 pub trait MetadataParser {
     fn from_byte_slice(buffer: &[u8]) -> Result<Option<Metadata>, FooterParseError>;
 }
 
+/// Length of the audio data in a file of `file_len` bytes, once `footer` is trimmed.
+pub fn expected_audio_len(footer: &Metadata, file_len: usize) -> usize {
+    file_len.saturating_sub(footer.size)
+}
+
 pub fn from_byte_slice(buffer: &[u8]) -> Result<Option<Metadata>, FooterParseError> {
     if let Some(metadata) = STagMetadata::from_byte_slice(buffer)? {
         return Ok(Some(metadata));
@@ -95,12 +198,88 @@ pub fn from_byte_slice(buffer: &[u8]) -> Result<Option<Metadata>, FooterParseErr
     Ok(None)
 }
 
+/// Collect the footers stacked at the end of `data`, outermost first.
+///
+/// Files edited by several tools can end up with more than one footer. The `size`
+/// of each candidate is counted from the end of `data`, so it includes the size of
+/// every footer after it.
+pub fn candidates(data: &[u8]) -> Vec<Metadata> {
+    let mut result = vec![];
+    let mut trimmed = 0;
+
+    while result.len() < MAX_STACKED_FOOTERS {
+        let remaining = &data[..data.len() - trimmed];
+        let tail = &remaining[remaining.len().saturating_sub(INITIAL_DETECTION_LEN)..];
+        let mut metadata = match from_byte_slice(tail) {
+            Ok(Some(metadata)) if metadata.size > 0 => metadata,
+            _ => break,
+        };
+
+        trimmed += metadata.size;
+        metadata.size = trimmed;
+        result.push(metadata);
+    }
+
+    result
+}
+
+/// Check if the key in `footer` decrypts the start of `data` to known audio.
+fn probe_footer_key(data: &[u8], footer: &Metadata) -> bool {
+    let cipher = match footer.ekey.as_ref().map(QMCv2Cipher::new_from_ekey) {
+        Some(Ok(cipher)) => cipher,
+        _ => return false,
+    };
+
+    let mut head = data[..data.len().min(PROBE_SIZE)].to_vec();
+    cipher.decrypt(&mut head, 0);
+    is_known_audio(&head)
+}
+
+/// Find the footer of the whole file `data`.
+///
+/// When there are several footers (see [`candidates`]), the first one whose key
+/// decrypts the start of the file to known audio is picked. If none of them do,
+/// the footer at the very end of the file is returned, as if there was only one.
+pub fn parse_footer(data: &[u8]) -> Option<Metadata> {
+    let mut candidates = candidates(data);
+    match candidates
+        .iter()
+        .position(|footer| probe_footer_key(data, footer))
+    {
+        Some(index) => {
+            trace_event!(
+                candidates = candidates.len(),
+                index,
+                "footer key matches the audio"
+            );
+            Some(candidates.swap_remove(index))
+        }
+        None => {
+            trace_event!(
+                candidates = candidates.len(),
+                "no footer key matches, using the outermost footer"
+            );
+            candidates.into_iter().next()
+        }
+    }
+}
+
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use super::*;
+    use crate::fixtures::{write_footer, FooterScheme};
     use crate::footer::android_qtag::QTagMetadata;
     use crate::footer::android_stag::STagMetadata;
     use crate::footer::pc_v1_legacy::PcV1Legacy;
+    use crate::self_test::TEST_QMC2_HEADER;
+
+    /// Append an Android `QTag` footer holding `ekey` to `data`.
+    pub fn append_qtag(data: &mut Vec<u8>, ekey: &str) {
+        data.extend_from_slice(&write_footer(
+            ekey,
+            &FooterScheme::AndroidQTag { resource_id: 1 },
+        ));
+    }
 
     #[test]
     fn test_qtag() {
@@ -151,6 +330,107 @@ mod tests {
         assert_eq!(payload.data, Data::PCv1Legacy(PcV1Legacy))
     }
 
+    #[test]
+    fn test_expected_audio_len() {
+        let payload = include_bytes!("fixtures/ekey_android_qtag.bin");
+        let footer = from_byte_slice(payload).unwrap().unwrap();
+
+        assert_eq!(expected_audio_len(&footer, 0x1000), 0x1000 - 0x23);
+        assert_eq!(expected_audio_len(&footer, 0x10), 0);
+    }
+
+    #[test]
+    fn test_parse_footer_with_decoy() {
+        const EKEY: &str = include_str!("../v2_rc4/fixtures/ekey_rc4_512.txt");
+
+        // A valid EKey, but for another file.
+        let decoy = from_byte_slice(include_bytes!("fixtures/ekey_pc_enc_v1.bin"))
+            .unwrap()
+            .unwrap()
+            .ekey
+            .unwrap();
+
+        let mut plaintext = b"fLaC\x00\x00\x00\x22".to_vec();
+        plaintext.extend_from_slice(&[0u8; 0x800]);
+        let mut data = plaintext.clone();
+        QMCv2Cipher::new_from_ekey(EKEY)
+            .unwrap()
+            .decrypt(&mut data, 0);
+
+        // audio, real footer (QTag), decoy footer (PCv1).
+        append_qtag(&mut data, EKEY);
+        data.extend_from_slice(decoy.as_bytes());
+        data.extend_from_slice(&(decoy.len() as u32).to_le_bytes());
+
+        let all = candidates(&data);
+        assert_eq!(all.len(), 2);
+        assert_eq!(all[0].ekey.as_ref(), Some(&decoy));
+        assert_eq!(all[1].ekey.as_deref(), Some(EKEY));
+
+        let footer = parse_footer(&data).expect("should find a footer");
+        assert_eq!(footer.ekey.as_deref(), Some(EKEY));
+        assert_eq!(footer.size, data.len() - plaintext.len());
+    }
+
+    #[test]
+    fn test_parse_footer_with_decoy_large_id3() {
+        const EKEY: &str = include_str!("../v2_rc4/fixtures/ekey_rc4_512.txt");
+        let decoy = from_byte_slice(include_bytes!("fixtures/ekey_pc_enc_v1.bin"))
+            .unwrap()
+            .unwrap()
+            .ekey
+            .unwrap();
+
+        // The ID3 tag (e.g. with a cover) goes past the probed head.
+        let mut plaintext = b"ID3\x04\x00\x00\x00\x00\x10\x00".to_vec();
+        plaintext.extend_from_slice(&[0u8; 0x800]);
+        plaintext.extend_from_slice(b"fLaC\x00\x00\x00\x22");
+        plaintext.extend_from_slice(&[0u8; 0x800]);
+        assert!(plaintext.len() > PROBE_SIZE);
+        let mut data = plaintext.clone();
+        QMCv2Cipher::new_from_ekey(EKEY)
+            .unwrap()
+            .decrypt(&mut data, 0);
+
+        // audio, real footer (QTag), decoy footer (PCv1).
+        append_qtag(&mut data, EKEY);
+        data.extend_from_slice(decoy.as_bytes());
+        data.extend_from_slice(&(decoy.len() as u32).to_le_bytes());
+
+        let footer = parse_footer(&data).expect("should find a footer");
+        assert_eq!(footer.ekey.as_deref(), Some(EKEY));
+        assert_eq!(footer.size, data.len() - plaintext.len());
+    }
+
+    #[test]
+    fn test_into_cipher() {
+        const EKEY: &str = include_str!("../v2_rc4/fixtures/ekey_rc4_512.txt");
+
+        let mut data = vec![];
+        append_qtag(&mut data, EKEY);
+
+        let cipher = from_byte_slice(&data)
+            .unwrap()
+            .expect("should parse to qtag")
+            .into_cipher()
+            .expect("should build cipher");
+        assert!(cipher.supports_random_access());
+
+        let mut header = TEST_QMC2_HEADER;
+        cipher.decrypt(&mut header, 0);
+        assert_eq!(header, [0u8; 256]);
+    }
+
+    #[test]
+    fn test_into_cipher_without_ekey() {
+        let payload = include_bytes!("fixtures/ekey_android_stag.bin");
+        let footer = from_byte_slice(payload).unwrap().unwrap();
+        assert!(matches!(
+            footer.into_cipher(),
+            Err(QmcCryptoError::KeyNotFound(_))
+        ));
+    }
+
     #[test]
     fn test_pc_enc_v2() {
         let payload = include_bytes!("fixtures/ekey_pc_enc_v2.bin");
@@ -168,4 +448,22 @@ mod tests {
             })
         )
     }
+
+    #[test]
+    fn test_find_footer_markers() {
+        let mut data = vec![0u8; 0x80];
+        data[0x10..0x14].copy_from_slice(b"QTag");
+        data[0x40..0x48].copy_from_slice(b"musicex\x00");
+        data.extend_from_slice(b"STag");
+
+        assert_eq!(
+            find_footer_markers(&data),
+            vec![
+                (0x10, FooterMarkerKind::QTag),
+                (0x40, FooterMarkerKind::MusicEx),
+                (0x80, FooterMarkerKind::STag),
+            ]
+        );
+        assert_eq!(find_footer_markers(b"musicex"), vec![]);
+    }
 }
@@ -40,21 +40,29 @@ impl MusicExV1 {
         assert_eq!(buffer.len(), 0xC0 - 0x10);
 
         let mut cursor = Cursor::new(&buffer);
-        let mut result = MusicExV1::default();
-        result.unknown_0 = cursor.read_u32::<LE>()?;
-        result.unknown_1 = cursor.read_u32::<LE>()?;
-        result.unknown_2 = cursor.read_u32::<LE>()?;
-        cursor.read(&mut result.mid)?;
-        cursor.read(&mut result.media_filename)?;
-        result.unknown_3 = cursor.read_u32::<LE>()?;
+        let unknown_0 = cursor.read_u32::<LE>()?;
+        let unknown_1 = cursor.read_u32::<LE>()?;
+        let unknown_2 = cursor.read_u32::<LE>()?;
+        let mut mid = [0u8; 30 * 2];
+        cursor.read_exact(&mut mid)?;
+        let mut media_filename = [0u8; 50 * 2];
+        cursor.read_exact(&mut media_filename)?;
+        let unknown_3 = cursor.read_u32::<LE>()?;
 
-        Ok(result)
+        Ok(MusicExV1 {
+            unknown_0,
+            unknown_1,
+            unknown_2,
+            mid,
+            media_filename,
+            unknown_3,
+        })
     }
 }
 
 pub fn parse_v1(footer: &[u8]) -> Result<Option<Metadata>, FooterParseError> {
     let (payload, payload_len) = footer.split_at(footer.len() - 4);
-    let payload_len = LE::read_u32(&payload_len) as usize;
+    let payload_len = LE::read_u32(payload_len) as usize;
     if payload_len != 0xC0 {
         Err(FooterParseError::PCv2MusicExUnsupportedPayloadSize(
             payload_len,
@@ -0,0 +1,238 @@
+//! Best-effort recovery of the key of a damaged footer.
+
+use crate::ekey::EKEY_V2_PREFIX;
+use crate::footer::android_qtag::QTagMetadata;
+use crate::footer::pc_v1_legacy::PcV1Legacy;
+use crate::footer::pc_v1_legacy::MAX_ALLOWED_EKEY_LEN;
+use crate::footer::utils::is_base64;
+use crate::footer::{from_byte_slice, Data, FooterParseError, Metadata};
+use crate::warning::QmcWarning;
+use crate::QmcCryptoError;
+use byteorder::{ByteOrder, BE, LE};
+
+/// Shortest run of key bytes accepted at the end of a truncated file; shorter runs
+/// are too likely to be audio data.
+const MIN_TRUNCATED_EKEY_LEN: usize = 64;
+
+/// Payload of the Android `QTag` (`ekey,resource_id,version` CSV) or PC v1 footer at
+/// the end of `tail`, with the length it declares.
+fn declared_payload(tail: &[u8]) -> Option<(&[u8], usize)> {
+    if let Some(footer) = tail.strip_suffix(b"QTag") {
+        let (payload, len) = footer.split_at_checked(footer.len().checked_sub(4)?)?;
+        return Some((payload, BE::read_u32(len) as usize));
+    }
+
+    let (payload, len) = tail.split_at_checked(tail.len().checked_sub(4)?)?;
+    let len = LE::read_u32(len) as usize;
+    (len <= MAX_ALLOWED_EKEY_LEN).then_some((payload, len))
+}
+
+/// `QTag` footer data, `resource_id` being `0` when it is cut off or unreadable.
+fn qtag_data(resource_id: Option<&[u8]>) -> Data {
+    let resource_id = resource_id
+        .and_then(|id| std::str::from_utf8(id).ok())
+        .and_then(|id| id.parse().ok())
+        .unwrap_or_default();
+    Data::AndroidQTag(QTagMetadata { resource_id })
+}
+
+/// Key bytes left in `payload`, the end of a footer payload that lost its start.
+fn partial_ekey<'a>(tail: &[u8], payload: &'a [u8]) -> Option<(&'a [u8], Data)> {
+    let (ekey, data) = match tail.ends_with(b"QTag") {
+        // Drop the resource id and version, the start of the key is gone.
+        true => {
+            let mut fields = payload.rsplitn(3, |&b| b == b',').skip(1);
+            let resource_id = fields.next();
+            (fields.next()?, qtag_data(resource_id))
+        }
+        false => (
+            payload.split(|&b| b == 0).next()?,
+            Data::PCv1Legacy(PcV1Legacy),
+        ),
+    };
+    (!ekey.is_empty() && is_base64(ekey)).then_some((ekey, data))
+}
+
+/// Key bytes at the end of `tail`, a file cut off inside its footer payload: the
+/// length and magic of the footer are gone, along with the end of the key.
+///
+/// The key is the first field of the trailing run of key and CSV bytes; other fields
+/// make it a `QTag` footer. Its start is guessed: a V2 key starts at its prefix,
+/// other keys may start with a few stray audio bytes.
+///
+/// returns: The key, where it starts in `tail`, and the footer data.
+fn truncated_ekey(tail: &[u8]) -> Option<(&[u8], usize, Data)> {
+    let mut start = tail
+        .iter()
+        .rposition(|&b| !is_base64(&[b]) && b != b',')
+        .map_or(0, |pos| pos + 1);
+    let mut fields = tail[start..].split(|&b| b == b',');
+    let mut ekey = fields.next()?;
+    if let Some(pos) = ekey
+        .windows(EKEY_V2_PREFIX.len())
+        .position(|w| w == EKEY_V2_PREFIX)
+    {
+        ekey = &ekey[pos..];
+        start += pos;
+    }
+
+    let data = match (fields.next(), fields.next()) {
+        (None, _) => Data::PCv1Legacy(PcV1Legacy),
+        // The resource id is only complete if followed by the version.
+        (resource_id, Some(_)) => qtag_data(resource_id),
+        (Some(_), None) => qtag_data(None),
+    };
+    (ekey.len() >= MIN_TRUNCATED_EKEY_LEN).then_some((ekey, start, data))
+}
+
+/// Find the footer with an EKey at the end of `tail`, like
+/// [`crate::footer::from_byte_slice`].
+///
+/// When the footer declares a payload larger than what is left in `tail` (e.g. a
+/// damaged file, or a tail read too short), the footer is returned with the key
+/// bytes that are left and a [`QmcWarning::PartialFooterKey`], instead of failing
+/// outright. When the file is cut off inside the footer payload, so the footer has
+/// no length or magic left, the key bytes at the end of `tail` are returned with a
+/// [`QmcWarning::TruncatedFooterKey`]. The `size` of a damaged footer is what is
+/// left of it.
+/// Such a key usually can't be decoded; it is meant as a starting point for recovery.
+pub fn parse_footer_partial(tail: &[u8]) -> Result<(Metadata, Option<QmcWarning>), QmcCryptoError> {
+    let err = match from_byte_slice(tail) {
+        Ok(Some(metadata)) if metadata.ekey.is_some() => return Ok((metadata, None)),
+        Ok(_) => Err(QmcCryptoError::KeyNotFound("file footer".into()))?,
+        Err(err @ FooterParseError::BufferTooSmall(_)) => err,
+        // Key bytes read as a length: maybe a file cut off before the footer ends.
+        Err(err @ FooterParseError::PCv1EKeyTooLarge(_)) => {
+            let (ekey, start, data) =
+                truncated_ekey(tail).ok_or(QmcCryptoError::FooterInvalid(err))?;
+            trace_event!(available = ekey.len(), "truncated footer key");
+            let metadata = Metadata {
+                size: tail.len() - start,
+                ekey: Some(String::from_utf8_lossy(ekey).into()),
+                data,
+            };
+            let warning = QmcWarning::TruncatedFooterKey {
+                available: ekey.len(),
+            };
+            return Ok((metadata, Some(warning)));
+        }
+        Err(err) => Err(QmcCryptoError::FooterInvalid(err))?,
+    };
+
+    let (payload, declared) = match declared_payload(tail) {
+        Some((payload, declared)) if declared > payload.len() => (payload, declared),
+        _ => return Err(QmcCryptoError::FooterInvalid(err)),
+    };
+    let (ekey, data) = partial_ekey(tail, payload).ok_or(QmcCryptoError::FooterInvalid(err))?;
+
+    trace_event!(declared, available = payload.len(), "partial footer key");
+    let metadata = Metadata {
+        size: tail.len(),
+        ekey: Some(String::from_utf8_lossy(ekey).into()),
+        data,
+    };
+    let warning = QmcWarning::PartialFooterKey {
+        declared,
+        available: payload.len(),
+    };
+    Ok((metadata, Some(warning)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::footer::tests::append_qtag;
+
+    fn make_qtag_footer(ekey: &str) -> Vec<u8> {
+        let mut footer = vec![];
+        append_qtag(&mut footer, ekey);
+        footer
+    }
+
+    fn qtag(size: usize, ekey: &str, resource_id: u64) -> Metadata {
+        Metadata {
+            size,
+            ekey: Some(ekey.into()),
+            data: Data::AndroidQTag(QTagMetadata { resource_id }),
+        }
+    }
+
+    #[test]
+    fn test_complete_footer() {
+        let footer = make_qtag_footer("00112233aBcD+/=");
+        assert_eq!(
+            parse_footer_partial(&footer).unwrap(),
+            (qtag(footer.len(), "00112233aBcD+/=", 1), None)
+        );
+    }
+
+    #[test]
+    fn test_missing_key_bytes() {
+        // The first 6 bytes of the key are gone.
+        let footer = make_qtag_footer("00112233aBcD+/=");
+        assert_eq!(
+            parse_footer_partial(&footer[6..]).unwrap(),
+            (
+                qtag(footer.len() - 6, "33aBcD+/=", 1),
+                Some(QmcWarning::PartialFooterKey {
+                    declared: 19,
+                    available: 13,
+                })
+            )
+        );
+    }
+
+    #[test]
+    fn test_truncated_footer() {
+        // The file is cut off inside the key: no length or magic left.
+        let ekey = format!("{}{}", "A1b2+/".repeat(20), "=");
+        let mut file = vec![0xff, 0xfb, 0x90, 0x00];
+        file.extend_from_slice(&make_qtag_footer(&ekey));
+        let file = &file[..4 + 100];
+        let expected = Metadata {
+            size: 100,
+            ekey: Some(ekey[..100].into()),
+            data: Data::PCv1Legacy(PcV1Legacy),
+        };
+        assert_eq!(
+            parse_footer_partial(file).unwrap(),
+            (
+                expected,
+                Some(QmcWarning::TruncatedFooterKey { available: 100 })
+            )
+        );
+    }
+
+    #[test]
+    fn test_truncated_footer_after_key() {
+        // Cut off in the resource id, the key is complete; the V2 prefix marks its start.
+        let ekey = format!("{}{}", "UVFNdXNpYyBFbmNWMixLZXk6", "x".repeat(64));
+        let mut file = b"\x00Zz".to_vec();
+        file.extend_from_slice(&make_qtag_footer(&ekey));
+        let file = &file[..3 + ekey.len() + 2];
+        assert_eq!(
+            parse_footer_partial(file).unwrap(),
+            (
+                qtag(ekey.len() + 2, &ekey, 0),
+                Some(QmcWarning::TruncatedFooterKey {
+                    available: ekey.len()
+                })
+            )
+        );
+    }
+
+    #[test]
+    fn test_truncated_footer_too_short() {
+        let mut file = vec![0xff; 16];
+        file.extend_from_slice(&[b'A'; MIN_TRUNCATED_EKEY_LEN - 1]);
+        let result = parse_footer_partial(&file);
+        assert!(matches!(result, Err(QmcCryptoError::FooterInvalid(_))));
+    }
+
+    #[test]
+    fn test_no_key_left() {
+        let footer = make_qtag_footer("00112233aBcD+/=");
+        let result = parse_footer_partial(&footer[footer.len() - 12..]);
+        assert!(matches!(result, Err(QmcCryptoError::FooterInvalid(_))));
+    }
+}
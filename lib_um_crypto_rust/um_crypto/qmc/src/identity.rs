@@ -0,0 +1,36 @@
+//! Cipher of inputs that are already plain audio, so they can go through the same
+//! [`QmcCipher`] code path as encrypted ones.
+
+use crate::QmcCipher;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct IdentityCipher;
+
+impl IdentityCipher {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl QmcCipher for IdentityCipher {
+    /// Leave `data` as-is.
+    fn decrypt(&self, _data: &mut [u8], _offset: usize) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_identity_is_noop() {
+        let cipher: Box<dyn QmcCipher> = Box::new(IdentityCipher::new());
+        let plain = (0..0x100).map(|i| i as u8).collect::<Vec<_>>();
+        let mut data = plain.clone();
+        cipher.decrypt(&mut data, 0);
+        cipher.decrypt(&mut data[0x10..], 0x1234);
+        assert_eq!(data, plain);
+
+        assert!(!cipher.requires_full_buffer());
+        assert!(cipher.supports_random_access());
+    }
+}
@@ -0,0 +1,62 @@
+//! Keys exported from the keychain of the iOS app.
+//!
+//! The export is a property list (XML or binary) whose root dictionary maps the
+//! file name of each encrypted file to its EKey:
+//!
+//! ```xml
+//! <plist version="1.0"><dict>
+//!   <key>song.mflac</key><string>...</string>
+//! </dict></plist>
+//! ```
+
+use crate::{ekey, QmcCryptoError};
+use std::collections::HashMap;
+
+/// Parse a keychain export, and decode each EKey.
+///
+/// returns: File name to decoded key, ready to be passed to [`crate::QMCv2Cipher::new`].
+pub fn parse_qqmusic_ios_keys(
+    plist_bytes: &[u8],
+) -> Result<HashMap<String, Vec<u8>>, QmcCryptoError> {
+    let ekeys: HashMap<String, String> =
+        plist::from_bytes(plist_bytes).map_err(QmcCryptoError::PlistInvalid)?;
+
+    ekeys
+        .into_iter()
+        .map(|(filename, ekey)| {
+            let key = ekey::decrypt(ekey.trim()).map_err(QmcCryptoError::EKeyDecodeError)?;
+            Ok((filename, key))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EKEY: &str = include_str!("v2_rc4/fixtures/ekey_rc4_512.txt");
+
+    #[test]
+    fn test_parse_qqmusic_ios_keys() {
+        let plist = format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+    <key>song.mflac</key>
+    <string>{EKEY}</string>
+</dict>
+</plist>"#
+        );
+
+        let keys = parse_qqmusic_ios_keys(plist.as_bytes()).unwrap();
+        assert_eq!(keys.len(), 1);
+        assert_eq!(keys["song.mflac"], ekey::decrypt(EKEY).unwrap());
+    }
+
+    #[test]
+    fn test_invalid_plist() {
+        let result = parse_qqmusic_ios_keys(b"<plist><array/></plist>");
+        assert!(matches!(result, Err(QmcCryptoError::PlistInvalid(_))));
+    }
+}
@@ -3,16 +3,133 @@ use crate::v2_rc4::cipher::QMC2RC4;
 use anyhow::Result;
 use thiserror::Error;
 
+/// `tracing::trace!` with the `tracing` feature, nothing otherwise.
+///
+/// Never pass key material, only its fingerprint.
+macro_rules! trace_event {
+    ($($arg:tt)*) => {
+        #[cfg(feature = "tracing")]
+        tracing::trace!($($arg)*);
+    };
+}
+
+pub mod baidu;
+#[cfg(feature = "rayon")]
+pub mod batch;
+#[cfg(feature = "compressed")]
+pub mod compressed;
+pub mod decoder;
+pub mod dir;
 pub mod ekey;
+#[cfg(any(test, feature = "fixtures"))]
+pub mod fixtures;
 pub mod footer;
+pub mod identity;
+#[cfg(feature = "plist")]
+pub mod ios_keys;
+#[cfg(feature = "manifest")]
+pub mod manifest;
+pub mod mp4_wrapper;
+#[cfg(feature = "probe")]
+pub mod probe;
+#[cfg(feature = "remux")]
+pub mod remux;
+pub mod self_test;
+pub mod sink;
+#[cfg(feature = "stream")]
+pub mod stream;
+pub mod tag_key;
+#[cfg(feature = "test_vectors")]
+pub mod test_vectors;
+pub mod tm;
+pub mod transform;
 pub mod v1;
 pub mod v2_map;
 pub mod v2_rc4;
+pub mod validate;
+pub mod version;
+pub mod warning;
 
 #[derive(Error, Debug)]
 pub enum QmcCryptoError {
     #[error("QMC V2/Map Cipher: Key is empty")]
     QMCV2MapKeyEmpty,
+
+    #[error("QMC V2/RC4 Cipher: Key is empty")]
+    QMCV2RC4KeyEmpty,
+
+    #[error("QMC: I/O Error: {0}")]
+    IOError(std::io::Error),
+
+    #[error("QMC: Offset overflow: {len} bytes at offset {offset:#x}")]
+    OffsetOverflow { offset: usize, len: usize },
+
+    #[error("QMC: No key found for {0}")]
+    KeyNotFound(String),
+    #[error("QMC: Failed to decode EKey: {0}")]
+    EKeyDecodeError(anyhow::Error),
+    #[error("QMC: Invalid key: {0}")]
+    InvalidKey(anyhow::Error),
+    #[error("QMC: Decrypted data is not a known audio format")]
+    UnknownAudio,
+    #[error("QMC: Invalid footer: {0}")]
+    FooterInvalid(footer::FooterParseError),
+    #[error("QMC: Decrypted audio looks corrupt at {0:x?}")]
+    CorruptSegments(Vec<std::ops::Range<usize>>),
+    #[error("QMC: Invalid key stream cache: {0}")]
+    KeyStreamCacheInvalid(&'static str),
+    #[error("QMC: Self test failed: {0}")]
+    SelfTestFailed(&'static str),
+    #[error("QMC: Input is larger than {limit} bytes")]
+    InputTooLarge { limit: usize },
+    #[error("QMC: Invalid MP3 frame header: {0}")]
+    Mp3FrameInvalid(&'static str),
+    #[error("QMC: Invalid WAV structure: {0}")]
+    WavInvalid(&'static str),
+
+    #[cfg(feature = "manifest")]
+    #[error("QMC: Invalid manifest: {0}")]
+    ManifestInvalidJson(serde_json::Error),
+
+    #[cfg(feature = "plist")]
+    #[error("QMC: Invalid keychain plist: {0}")]
+    PlistInvalid(plist::Error),
+
+    #[cfg(feature = "probe")]
+    #[error("QMC: Failed to probe audio: {0}")]
+    ProbeError(symphonia::core::errors::Error),
+
+    #[cfg(feature = "remux")]
+    #[error("QMC: Invalid OGG FLAC stream: {0}")]
+    OggFlacInvalid(&'static str),
+}
+
+/// Hex encoded fingerprint of `key`, for logging: first 8 bytes of its MD5 digest.
+fn key_fingerprint_hex(key: &[u8]) -> String {
+    umc_utils::md5(key)[..8]
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+/// Common interface of the QMC ciphers, usable as a trait object.
+///
+/// Decrypting only reads the cipher: the key stream of any range is derived from
+/// its offset, so threads may decrypt overlapping ranges of a file into their own
+/// buffers at the same time.
+pub trait QmcCipher: Send + Sync {
+    /// Decrypt `data` in place, located at `offset` of the file.
+    fn decrypt(&self, data: &mut [u8], offset: usize);
+
+    /// `true` if any range of the file can be decrypted on its own, given its offset.
+    fn supports_random_access(&self) -> bool {
+        true
+    }
+
+    /// `true` if the file has to be decrypted in one go, from the start.
+    fn requires_full_buffer(&self) -> bool {
+        false
+    }
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -35,6 +152,11 @@ impl QMCv2Cipher {
         Ok(cipher)
     }
 
+    /// Same as [`QMCv2Cipher::new`], but with the crate error type.
+    pub fn try_new(key: &[u8]) -> std::result::Result<Self, QmcCryptoError> {
+        Self::new(key).map_err(|err| err.downcast().unwrap_or_else(QmcCryptoError::InvalidKey))
+    }
+
     pub fn new_from_ekey<T: AsRef<[u8]>>(ekey_str: T) -> Result<Self> {
         let key = ekey::decrypt(ekey_str)?;
         Self::new(key)
@@ -51,6 +173,12 @@ impl QMCv2Cipher {
     }
 }
 
+impl QmcCipher for QMCv2Cipher {
+    fn decrypt(&self, data: &mut [u8], offset: usize) {
+        QMCv2Cipher::decrypt(self, data, offset)
+    }
+}
+
 #[cfg(test)]
 mod test {
     pub fn generate_key(len: usize) -> Vec<u8> {
@@ -63,4 +191,52 @@ mod test {
             .try_into()
             .expect("failed to make test key")
     }
+
+    use super::*;
+
+    /// Stand-in for a block cipher in CBC mode.
+    struct CbcLikeCipher;
+
+    impl QmcCipher for CbcLikeCipher {
+        fn decrypt(&self, _data: &mut [u8], _offset: usize) {}
+
+        fn supports_random_access(&self) -> bool {
+            false
+        }
+
+        fn requires_full_buffer(&self) -> bool {
+            true
+        }
+    }
+
+    fn capabilities(cipher: &dyn QmcCipher) -> (bool, bool) {
+        (
+            cipher.supports_random_access(),
+            cipher.requires_full_buffer(),
+        )
+    }
+
+    #[test]
+    fn test_cipher_capabilities() {
+        let rc4 = QMC2RC4::new(&generate_key(512));
+        let map = QMC2Map::new(generate_key(128)).unwrap();
+        let v2 = QMCv2Cipher::new(generate_key(512)).unwrap();
+
+        assert_eq!(capabilities(&rc4), (true, false));
+        assert_eq!(capabilities(&map), (true, false));
+        assert_eq!(capabilities(&v2), (true, false));
+        assert_eq!(capabilities(&CbcLikeCipher), (false, true));
+    }
+
+    #[test]
+    fn test_cipher_trait_decrypt() {
+        let key = generate_key(512);
+        let mut expected = vec![0u8; 0x2000];
+        QMC2RC4::new(&key).decrypt(&mut expected, 0x10);
+
+        let cipher: &dyn QmcCipher = &QMCv2Cipher::new(&key).unwrap();
+        let mut actual = vec![0u8; 0x2000];
+        cipher.decrypt(&mut actual, 0x10);
+        assert_eq!(actual, expected);
+    }
 }
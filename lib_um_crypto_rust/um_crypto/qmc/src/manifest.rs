@@ -0,0 +1,111 @@
+//! Playlist manifests, as exported by some backup tools.
+//!
+//! A manifest is a JSON array of entries, each giving the file name of an
+//! encrypted file, its EKey, and optionally the track title:
+//!
+//! ```json
+//! [{ "filename": "song.mflac", "ekey": "...", "title": "Song" }]
+//! ```
+
+use crate::dir::{decrypt_file_as, DecryptPool};
+use crate::{QMCv2Cipher, QmcCryptoError};
+use serde::Deserialize;
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct ManifestEntry {
+    /// Path of the encrypted file, relative to the manifest base directory.
+    pub filename: String,
+    /// Base64 encoded EKey.
+    pub ekey: String,
+    #[serde(default)]
+    pub title: Option<String>,
+}
+
+pub fn parse(manifest_json: &str) -> Result<Vec<ManifestEntry>, QmcCryptoError> {
+    serde_json::from_str(manifest_json).map_err(QmcCryptoError::ManifestInvalidJson)
+}
+
+/// Make `title` usable as a file name, `None` if nothing is left of it.
+fn sanitize_title(title: &str) -> Option<String> {
+    let name = title
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect::<String>();
+    let name = name.trim().trim_matches('.');
+    (!name.is_empty()).then(|| name.to_string())
+}
+
+/// Decrypt every file listed in `manifest_json`, and write them to `out_dir`.
+///
+/// Output files are named after the entry title (or the input file stem when
+/// there is none), with the detected audio extension.
+///
+/// returns: `(filename, output)` for each entry. An invalid manifest is reported
+///          as a single entry with an empty file name.
+pub fn decrypt_manifest(
+    manifest_json: &str,
+    base_dir: &Path,
+    out_dir: &Path,
+) -> Vec<(String, Result<PathBuf, QmcCryptoError>)> {
+    let entries = match parse(manifest_json) {
+        Ok(entries) => entries,
+        Err(err) => return vec![(String::new(), Err(err))],
+    };
+
+    let mut pool = DecryptPool::new();
+    entries
+        .into_iter()
+        .map(|entry| {
+            let path = base_dir.join(&entry.filename);
+            let stem = match entry.title.as_deref().and_then(sanitize_title) {
+                Some(title) => title.into(),
+                None => path
+                    .file_stem()
+                    .unwrap_or(OsStr::new(&entry.filename))
+                    .to_os_string(),
+            };
+
+            let result = QMCv2Cipher::new_from_ekey(&entry.ekey)
+                .map_err(QmcCryptoError::EKeyDecodeError)
+                .and_then(|cipher| decrypt_file_as(&path, &cipher, out_dir, &stem, &mut pool));
+            (entry.filename, result)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let entries = parse(r#"[{"filename": "a.mflac", "ekey": "k"}]"#).unwrap();
+        assert_eq!(
+            entries,
+            [ManifestEntry {
+                filename: "a.mflac".into(),
+                ekey: "k".into(),
+                title: None,
+            }]
+        );
+
+        assert!(matches!(
+            parse(r#"{"filename": "a.mflac"}"#),
+            Err(QmcCryptoError::ManifestInvalidJson(_))
+        ));
+    }
+
+    #[test]
+    fn test_sanitize_title() {
+        assert_eq!(sanitize_title("AC/DC: Back?"), Some("AC_DC_ Back_".into()));
+        assert_eq!(sanitize_title("  Song  "), Some("Song".into()));
+        assert_eq!(sanitize_title(".."), None);
+        assert_eq!(sanitize_title(""), None);
+    }
+}
@@ -0,0 +1,143 @@
+//! Payload wrapped in an MP4-like container (`.mmp4` and some `.mp4` exports).
+//!
+//! The container boxes are left in plain text; the payload of the `mdat` box is
+//! encrypted as if it was a file of its own (i.e. starting at offset 0).
+
+use crate::QmcCipher;
+use byteorder::{ByteOrder, BE};
+
+const BOX_HEADER_LEN: usize = 8;
+const LARGE_BOX_HEADER_LEN: usize = 16;
+
+/// Offset of the encrypted payload, i.e. the content of the first top-level `mdat`
+/// box, if `data` starts with an `ftyp` box.
+///
+/// Only the box headers up to `mdat` have to be present in `data`.
+pub fn find_payload_offset(data: &[u8]) -> Option<usize> {
+    if data.get(4..8)? != b"ftyp" {
+        return None;
+    }
+
+    let mut offset = 0usize;
+    loop {
+        let header = data.get(offset..offset.checked_add(BOX_HEADER_LEN)?)?;
+        let box_type = &header[4..8];
+        if !box_type.iter().all(u8::is_ascii_graphic) {
+            return None;
+        }
+        let is_mdat = box_type == b"mdat";
+
+        let (header_len, box_len) = match BE::read_u32(&header[..4]) {
+            // 64-bit size, right after the type.
+            1 => {
+                let large_size =
+                    data.get(offset + BOX_HEADER_LEN..offset.checked_add(LARGE_BOX_HEADER_LEN)?)?;
+                let large_size = BE::read_u64(large_size);
+                (LARGE_BOX_HEADER_LEN, usize::try_from(large_size).ok())
+            }
+            // The box extends to the end of the file: only allowed for `mdat` here.
+            0 if is_mdat => (BOX_HEADER_LEN, None),
+            0 => return None,
+            size => (BOX_HEADER_LEN, Some(size as usize)),
+        };
+
+        if is_mdat {
+            return Some(offset + header_len);
+        }
+        match box_len {
+            Some(box_len) if box_len >= header_len => offset = offset.checked_add(box_len)?,
+            _ => return None,
+        }
+    }
+}
+
+/// Decrypt the payload of the wrapped file `data` in place, starting at `payload_offset`
+/// (see [`find_payload_offset`]).
+///
+/// The cipher is given offsets relative to the payload, since that is its logical
+/// position in the encrypted stream; the container before it is left untouched.
+/// When decrypting a wrapped file in chunks, pass `file_offset - payload_offset` to
+/// [`QmcCipher::decrypt`] instead.
+pub fn decrypt_wrapped(cipher: &dyn QmcCipher, data: &mut [u8], payload_offset: usize) {
+    if let Some(payload) = data.get_mut(payload_offset..) {
+        cipher.decrypt(payload, 0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::generate_key;
+    use crate::v2_rc4::cipher::QMC2RC4;
+
+    fn make_box(box_type: &[u8; 4], content: &[u8]) -> Vec<u8> {
+        let mut data = ((content.len() + BOX_HEADER_LEN) as u32)
+            .to_be_bytes()
+            .to_vec();
+        data.extend_from_slice(box_type);
+        data.extend_from_slice(content);
+        data
+    }
+
+    #[test]
+    fn test_decrypt_wrapped() {
+        let cipher = QMC2RC4::new(&generate_key(512));
+        let plain = (0..0x3000).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        let mut payload = plain.clone();
+        cipher.decrypt(&mut payload, 0);
+
+        let mut data = make_box(b"ftyp", b"mmp4\x00\x00\x00\x00isom");
+        data.extend(make_box(b"free", &[0u8; 4]));
+        let container_len = data.len();
+        data.extend(make_box(b"mdat", &payload));
+
+        let payload_offset = find_payload_offset(&data).unwrap();
+        assert_eq!(payload_offset, container_len + BOX_HEADER_LEN);
+        assert_eq!(payload_offset, 0x28);
+
+        let container = data[..payload_offset].to_vec();
+        decrypt_wrapped(&cipher, &mut data, payload_offset);
+        assert_eq!(&data[..payload_offset], container);
+        assert_eq!(&data[payload_offset..], plain);
+    }
+
+    #[test]
+    fn test_find_payload_offset_large_box() {
+        let mut data = make_box(b"ftyp", b"isom");
+        data.extend_from_slice(&1u32.to_be_bytes());
+        data.extend_from_slice(b"mdat");
+        data.extend_from_slice(&0x20u64.to_be_bytes());
+        assert_eq!(find_payload_offset(&data), Some(0x0c + 0x10));
+    }
+
+    #[test]
+    fn test_find_payload_offset_bad_box_size() {
+        // Size 0 (up to the end of the file) is only valid for `mdat`.
+        let mut data = 0u32.to_be_bytes().to_vec();
+        data.extend_from_slice(b"ftypisom");
+        data.extend(make_box(b"mdat", &[0u8; 4]));
+        assert_eq!(find_payload_offset(&data), None);
+
+        let mut data = make_box(b"ftyp", b"isom");
+        data.extend_from_slice(&0u32.to_be_bytes());
+        data.extend_from_slice(b"free");
+        data.extend(make_box(b"mdat", &[0u8; 4]));
+        assert_eq!(find_payload_offset(&data), None);
+
+        // 64-bit sizes that would overflow the offset.
+        for large_size in [u64::MAX, u64::MAX - 0x0c, 1 << 40] {
+            let mut data = make_box(b"ftyp", b"isom");
+            data.extend_from_slice(&1u32.to_be_bytes());
+            data.extend_from_slice(b"free");
+            data.extend_from_slice(&large_size.to_be_bytes());
+            data.extend(make_box(b"mdat", &[0u8; 4]));
+            assert_eq!(find_payload_offset(&data), None, "size {large_size:#x}");
+        }
+    }
+
+    #[test]
+    fn test_find_payload_offset_not_wrapped() {
+        assert_eq!(find_payload_offset(b"fLaC\x00\x00\x00\x22"), None);
+        assert_eq!(find_payload_offset(&make_box(b"ftyp", b"isom")), None);
+    }
+}
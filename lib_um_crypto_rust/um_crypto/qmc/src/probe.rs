@@ -0,0 +1,121 @@
+//! Decrypt and read the basic stream parameters, using `symphonia`.
+
+use crate::{QMCv2Cipher, QmcCryptoError};
+use std::io::Cursor;
+use std::time::Duration;
+use symphonia::core::errors::Error as SymphoniaError;
+use symphonia::core::formats::FormatOptions;
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+use um_audio::AudioType;
+
+/// Stream parameters of the default track, as reported by the format reader.
+#[derive(Debug, PartialEq)]
+pub struct AudioProbe {
+    pub format: AudioType,
+    pub sample_rate: Option<u32>,
+    pub channels: Option<usize>,
+    pub duration: Option<Duration>,
+}
+
+/// Decrypt `data` (from the start of the file) in place, then probe the result.
+///
+/// `key` is the decoded QMCv2 key. Only the container is parsed, no audio is decoded.
+pub fn decrypt_and_probe(key: &[u8], data: &mut [u8]) -> Result<AudioProbe, QmcCryptoError> {
+    let cipher = QMCv2Cipher::try_new(key)?;
+    cipher.decrypt(data, 0);
+    probe(data)
+}
+
+/// Probe already decrypted audio.
+pub fn probe(data: &[u8]) -> Result<AudioProbe, QmcCryptoError> {
+    let format = um_audio::detect_audio_type(data).unwrap_or(AudioType::Unknown);
+    let mut hint = Hint::new();
+    if format != AudioType::Unknown {
+        hint.with_extension(format.extension());
+    }
+
+    let source = Cursor::new(data.to_vec());
+    let stream = MediaSourceStream::new(Box::new(source), Default::default());
+    let probed = symphonia::default::get_probe()
+        .format(
+            &hint,
+            stream,
+            &FormatOptions::default(),
+            &MetadataOptions::default(),
+        )
+        .map_err(QmcCryptoError::ProbeError)?;
+
+    let track = probed
+        .format
+        .default_track()
+        .ok_or(QmcCryptoError::ProbeError(SymphoniaError::Unsupported(
+            "no audio track",
+        )))?;
+    let params = &track.codec_params;
+    let duration = params
+        .time_base
+        .zip(params.n_frames)
+        .map(|(time_base, n_frames)| {
+            let time = time_base.calc_time(n_frames);
+            Duration::from_secs(time.seconds) + Duration::from_secs_f64(time.frac)
+        });
+
+    Ok(AudioProbe {
+        format,
+        sample_rate: params.sample_rate,
+        channels: params.channels.map(|channels| channels.count()),
+        duration,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::generate_key;
+
+    /// 192 samples of 44.1kHz 16-bit stereo silence, as two constant subframes.
+    const SILENT_FRAME: [u8; 14] = [
+        0xFF, 0xF8, 0x19, 0x18, 0x00, 0xED, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x9B, 0xBA,
+    ];
+
+    /// `fLaC`, a lone STREAMINFO block, then a single audio frame.
+    fn make_flac_header(sample_rate: u32, channels: u8, total_samples: u64) -> Vec<u8> {
+        let mut data = b"fLaC".to_vec();
+        data.extend_from_slice(&[0x80, 0x00, 0x00, 0x22]);
+        data.extend_from_slice(&4096u16.to_be_bytes()); // min block size
+        data.extend_from_slice(&4096u16.to_be_bytes()); // max block size
+        data.extend_from_slice(&[0u8; 6]); // min/max frame size, unknown
+        let bits_per_sample = 16u64;
+        let packed = (sample_rate as u64) << 44
+            | ((channels - 1) as u64) << 41
+            | (bits_per_sample - 1) << 36
+            | total_samples;
+        data.extend_from_slice(&packed.to_be_bytes());
+        data.extend_from_slice(&[0u8; 16]); // md5
+        data.extend_from_slice(&SILENT_FRAME);
+        data
+    }
+
+    #[test]
+    fn test_decrypt_and_probe_flac() {
+        let key = generate_key(512);
+        let plain = make_flac_header(44100, 2, 44100 * 3);
+        let mut data = plain.clone();
+        QMCv2Cipher::new(&key).unwrap().decrypt(&mut data, 0);
+
+        let probe = decrypt_and_probe(&key, &mut data).unwrap();
+        assert_eq!(data, plain);
+        assert_eq!(probe.format, AudioType::FLAC);
+        assert_eq!(probe.channels, Some(2));
+        assert_eq!(probe.sample_rate, Some(44100));
+        assert_eq!(probe.duration, Some(Duration::from_secs(3)));
+    }
+
+    #[test]
+    fn test_probe_garbage() {
+        let result = probe(&[0x55u8; 0x100]);
+        assert!(matches!(result, Err(QmcCryptoError::ProbeError(_))));
+    }
+}
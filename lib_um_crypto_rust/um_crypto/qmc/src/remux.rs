@@ -0,0 +1,222 @@
+//! Rewrap FLAC decrypted from an OGG container (e.g. `.mgg`) as a native `.flac` stream.
+//!
+//! Only the OGG FLAC mapping is supported: the first packet holds the `fLaC`
+//! magic and STREAMINFO, followed by the other metadata blocks, then one FLAC
+//! frame per packet. Page checksums are not verified.
+
+use crate::QmcCryptoError;
+use byteorder::{ByteOrder, BE, LE};
+use std::mem::take;
+
+const MAGIC_OGG_PAGE: &[u8] = b"OggS";
+const PAGE_HEADER_LEN: usize = 27;
+const MAX_LACING_VALUE: usize = 0xFF;
+
+/// `0x7F "FLAC"`, mapping version (2 bytes), number of header packets (2 bytes).
+const OGG_FLAC_HEADER: &[u8] = b"\x7fFLAC";
+const OGG_FLAC_HEADER_LEN: usize = 9;
+
+const MAGIC_FLAC: &[u8] = b"fLaC";
+const METADATA_HEADER_LEN: usize = 4;
+const STREAMINFO_LEN: usize = 34;
+const LAST_METADATA_BLOCK: u8 = 0x80;
+const MAX_FRAME_SIZE: usize = 0xFF_FFFF;
+const TOTAL_SAMPLES_MASK: u64 = 0xF_FFFF_FFFF;
+
+fn invalid(reason: &'static str) -> QmcCryptoError {
+    QmcCryptoError::OggFlacInvalid(reason)
+}
+
+/// Packets of the first logical stream in `data`, and its last known granule position.
+fn read_packets(data: &[u8]) -> Result<(Vec<Vec<u8>>, Option<u64>), QmcCryptoError> {
+    let mut packets = vec![];
+    let mut packet = vec![];
+    let mut serial = None;
+    let mut granule = None;
+
+    let mut offset = 0;
+    while offset < data.len() {
+        let header = data
+            .get(offset..offset + PAGE_HEADER_LEN)
+            .filter(|header| header.starts_with(MAGIC_OGG_PAGE))
+            .ok_or(invalid("bad page header"))?;
+        let segment_count = header[26] as usize;
+        let segment_table_start = offset + PAGE_HEADER_LEN;
+        let segment_table = data
+            .get(segment_table_start..segment_table_start + segment_count)
+            .ok_or(invalid("truncated page"))?;
+
+        let page_serial = LE::read_u32(&header[14..18]);
+        let is_first_stream = *serial.get_or_insert(page_serial) == page_serial;
+
+        offset = segment_table_start + segment_count;
+        for &len in segment_table {
+            let len = len as usize;
+            let segment = data
+                .get(offset..offset + len)
+                .ok_or(invalid("truncated page"))?;
+            offset += len;
+
+            if is_first_stream {
+                packet.extend_from_slice(segment);
+                if len < MAX_LACING_VALUE {
+                    packets.push(take(&mut packet));
+                }
+            }
+        }
+
+        // A granule position of -1 means no packet ends in the page.
+        let page_granule = LE::read_u64(&header[6..14]);
+        if is_first_stream && page_granule != u64::MAX {
+            granule = Some(page_granule);
+        }
+    }
+
+    Ok((packets, granule))
+}
+
+fn is_flac_frame(packet: &[u8]) -> bool {
+    packet.len() >= 2 && packet[0] == 0xFF && packet[1] & 0xFE == 0xF8
+}
+
+/// Fill in the STREAMINFO fields left unknown (zero) by the encoder: the total
+/// number of samples from the last granule position, and the frame sizes.
+fn reconstruct_streaminfo(streaminfo: &mut [u8], frames: &[Vec<u8>], granule: Option<u64>) {
+    let min_frame = BE::read_u24(&streaminfo[4..7]);
+    let max_frame = BE::read_u24(&streaminfo[7..10]);
+    if min_frame == 0 && max_frame == 0 {
+        let frame_lens = frames.iter().map(|frame| frame.len().min(MAX_FRAME_SIZE));
+        if let (Some(min), Some(max)) = (frame_lens.clone().min(), frame_lens.max()) {
+            BE::write_u24(&mut streaminfo[4..7], min as u32);
+            BE::write_u24(&mut streaminfo[7..10], max as u32);
+        }
+    }
+
+    let packed = BE::read_u64(&streaminfo[10..18]);
+    if let (0, Some(granule)) = (packed & TOTAL_SAMPLES_MASK, granule) {
+        let packed = packed | (granule & TOTAL_SAMPLES_MASK);
+        BE::write_u64(&mut streaminfo[10..18], packed);
+    }
+}
+
+/// Extract the FLAC frames of the (decrypted) OGG FLAC stream in `data`, and emit
+/// them as a native FLAC stream, with the STREAMINFO fields the encoder left
+/// unknown filled in.
+pub fn ogg_flac_to_native(data: &[u8]) -> Result<Vec<u8>, QmcCryptoError> {
+    let (packets, granule) = read_packets(data)?;
+    let mut packets = packets.into_iter();
+
+    let first = packets.next().ok_or(invalid("no packets"))?;
+    let streaminfo_start = OGG_FLAC_HEADER_LEN + MAGIC_FLAC.len();
+    let streaminfo_end = streaminfo_start + METADATA_HEADER_LEN + STREAMINFO_LEN;
+    if !first.starts_with(OGG_FLAC_HEADER)
+        || first.get(OGG_FLAC_HEADER_LEN..streaminfo_start) != Some(MAGIC_FLAC)
+    {
+        Err(invalid("missing OGG FLAC mapping header"))?;
+    }
+    let streaminfo = first
+        .get(streaminfo_start..streaminfo_end)
+        .filter(|block| block[0] & !LAST_METADATA_BLOCK == 0)
+        .ok_or(invalid("missing STREAMINFO"))?;
+
+    let mut metadata = vec![streaminfo.to_vec()];
+    let mut frames = vec![];
+    for packet in packets.filter(|packet| !packet.is_empty()) {
+        match (is_flac_frame(&packet), frames.is_empty()) {
+            (true, _) => frames.push(packet),
+            (false, true) if packet.len() >= METADATA_HEADER_LEN => metadata.push(packet),
+            (false, _) => Err(invalid("unexpected packet"))?,
+        }
+    }
+
+    reconstruct_streaminfo(&mut metadata[0][METADATA_HEADER_LEN..], &frames, granule);
+
+    let mut result = MAGIC_FLAC.to_vec();
+    let last = metadata.len() - 1;
+    for (i, mut block) in metadata.into_iter().enumerate() {
+        block[0] &= !LAST_METADATA_BLOCK;
+        if i == last {
+            block[0] |= LAST_METADATA_BLOCK;
+        }
+        result.extend_from_slice(&block);
+    }
+    frames
+        .iter()
+        .for_each(|frame| result.extend_from_slice(frame));
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use um_audio::AudioType;
+
+    /// An OGG page holding `packets`, each of them complete.
+    fn make_ogg_page(sequence: u32, granule: u64, packets: &[&[u8]]) -> Vec<u8> {
+        let mut segment_table = vec![];
+        for packet in packets {
+            segment_table.extend(std::iter::repeat_n(0xFF, packet.len() / MAX_LACING_VALUE));
+            segment_table.push((packet.len() % MAX_LACING_VALUE) as u8);
+        }
+
+        let mut page = MAGIC_OGG_PAGE.to_vec();
+        page.push(0); // version
+        page.push(if sequence == 0 { 0x02 } else { 0x00 });
+        page.extend_from_slice(&granule.to_le_bytes());
+        page.extend_from_slice(&1u32.to_le_bytes()); // serial
+        page.extend_from_slice(&sequence.to_le_bytes());
+        page.extend_from_slice(&[0u8; 4]); // crc (not checked)
+        page.push(segment_table.len() as u8);
+        page.extend_from_slice(&segment_table);
+        packets
+            .iter()
+            .for_each(|packet| page.extend_from_slice(packet));
+        page
+    }
+
+    #[test]
+    fn test_ogg_flac_to_native() {
+        let mut streaminfo = vec![0x00, 0x00, 0x00, 0x22];
+        streaminfo.extend_from_slice(&[0x10, 0x00, 0x10, 0x00]); // block sizes
+        streaminfo.extend_from_slice(&[0u8; 6]); // frame sizes, unknown
+        streaminfo.extend_from_slice(&[0x0A, 0xC4, 0x40, 0xF0, 0, 0, 0, 0]); // 44100Hz, 1ch, 16bit
+        streaminfo.extend_from_slice(&[0u8; 16]); // md5
+
+        let mut first = b"\x7fFLAC\x01\x00\x00\x01fLaC".to_vec();
+        first.extend_from_slice(&streaminfo);
+        let comment = b"\x04\x00\x00\x04\x00\x00\x00\x00";
+        let mut frame = vec![0xFF, 0xF8, 0x19, 0x08, 0x00];
+        frame.resize(300, 0x55);
+
+        let mut data = make_ogg_page(0, 0, &[&first]);
+        data.extend(make_ogg_page(1, 0, &[comment]));
+        data.extend(make_ogg_page(2, 4096, &[&frame]));
+
+        let native = ogg_flac_to_native(&data).unwrap();
+        assert_eq!(
+            um_audio::detect_audio_type(&native).unwrap(),
+            AudioType::FLAC
+        );
+
+        let mut expected = MAGIC_FLAC.to_vec();
+        expected.extend_from_slice(&streaminfo);
+        expected[8 + 4..8 + 10].copy_from_slice(&[0, 1, 44, 0, 1, 44]); // frame sizes: 300
+        expected[8 + 16] = 0x10; // 4096 samples
+        expected.extend_from_slice(b"\x84\x00\x00\x04\x00\x00\x00\x00");
+        expected.extend_from_slice(&frame);
+        assert_eq!(native, expected);
+    }
+
+    #[test]
+    fn test_not_ogg_flac() {
+        let data = make_ogg_page(0, 0, &[b"\x01vorbis"]);
+        assert!(matches!(
+            ogg_flac_to_native(&data),
+            Err(QmcCryptoError::OggFlacInvalid(_))
+        ));
+        assert!(matches!(
+            ogg_flac_to_native(b"fLaC\x00\x00\x00\x22"),
+            Err(QmcCryptoError::OggFlacInvalid(_))
+        ));
+    }
+}
@@ -0,0 +1,167 @@
+//! Decrypt a stream, and hand its encoded audio frames to a player.
+//!
+//! Only framing and decryption happen here; decoding and playback are up to the
+//! [`FrameSink`].
+
+use crate::tag_key::id3_tag_len;
+use crate::v2_rc4::cipher::OTHER_SEGMENT_SIZE;
+use crate::{QmcCipher, QmcCryptoError};
+use std::io::{self, Read};
+use um_audio::mp3::Mp3FrameHeader;
+use um_audio::{AudioError, AudioType};
+
+/// Receives the decrypted audio from [`play`].
+pub trait FrameSink {
+    /// Called once with the detected format, before any frame.
+    fn start(&mut self, _format: AudioType) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Called with each encoded frame, in order.
+    fn push_frame(&mut self, frame: &[u8]) -> io::Result<()>;
+}
+
+/// Read `reader` from the start of the file, decrypt it with `cipher`, and push it
+/// to `sink`.
+///
+/// MP3 is pushed one whole frame at a time, leading ID3v2 tags and anything between
+/// frames skipped. Other formats aren't split, and are pushed as decrypted chunks.
+///
+/// returns: The detected format.
+pub fn play<R: Read>(
+    cipher: &dyn QmcCipher,
+    mut reader: R,
+    sink: &mut dyn FrameSink,
+) -> Result<AudioType, QmcCryptoError> {
+    let mut pending = vec![];
+    let mut offset = 0;
+    let mut eof = false;
+    let mut read_more = |pending: &mut Vec<u8>| -> Result<bool, QmcCryptoError> {
+        let start = pending.len();
+        pending.resize(start + OTHER_SEGMENT_SIZE, 0);
+        let n = read_full(&mut reader, &mut pending[start..]).map_err(QmcCryptoError::IOError)?;
+        pending.truncate(start + n);
+        cipher.decrypt(&mut pending[start..], offset);
+        offset += n;
+        Ok(n > 0)
+    };
+
+    let format = loop {
+        match um_audio::detect_audio_type(&pending) {
+            Ok(AudioType::Unknown) => Err(QmcCryptoError::UnknownAudio)?,
+            Ok(format) => break format,
+            Err(AudioError::NeedMoreHeader(_)) if !eof => eof = !read_more(&mut pending)?,
+            Err(_) => Err(QmcCryptoError::UnknownAudio)?,
+        }
+    };
+    sink.start(format).map_err(QmcCryptoError::IOError)?;
+
+    if format != AudioType::MP3 {
+        while !pending.is_empty() {
+            sink.push_frame(&pending).map_err(QmcCryptoError::IOError)?;
+            pending.clear();
+            read_more(&mut pending)?;
+        }
+        return Ok(format);
+    }
+
+    let mut pos = id3_tag_len(&pending);
+    loop {
+        // Keep at least a whole frame, or a frame header, buffered.
+        let header = Mp3FrameHeader::parse(pending.get(pos..).unwrap_or_default());
+        let end = pos + header.map_or(4, |header| header.frame_len());
+        if end > pending.len() && !eof {
+            let consumed = pos.min(pending.len());
+            pending.drain(..consumed);
+            pos -= consumed;
+            eof = !read_more(&mut pending)?;
+            continue;
+        }
+
+        match header {
+            Some(_) if end <= pending.len() => {
+                sink.push_frame(&pending[pos..end])
+                    .map_err(QmcCryptoError::IOError)?;
+                pos = end;
+            }
+            // Not a frame: resync on the next byte.
+            None if end <= pending.len() => pos += 1,
+            // A partial frame (or header) at the end.
+            _ => return Ok(format),
+        }
+    }
+}
+
+/// Fill `buf` as much as possible; short only at the end of the stream.
+fn read_full<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(filled)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tag_key::tests::make_id3_with_priv_key;
+    use crate::test::generate_key;
+    use crate::v2_rc4::cipher::QMC2RC4;
+    use std::io::Cursor;
+    use um_audio::mp3::tests::make_mp3_frame;
+
+    #[derive(Default)]
+    struct CollectSink {
+        format: Option<AudioType>,
+        frames: Vec<Vec<u8>>,
+    }
+
+    impl FrameSink for CollectSink {
+        fn start(&mut self, format: AudioType) -> io::Result<()> {
+            self.format = Some(format);
+            Ok(())
+        }
+
+        fn push_frame(&mut self, frame: &[u8]) -> io::Result<()> {
+            self.frames.push(frame.to_vec());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_play_mp3() {
+        let frames = (0..40u8)
+            .map(|i| {
+                let mut frame = make_mp3_frame();
+                frame[4..].fill(i);
+                frame
+            })
+            .collect::<Vec<_>>();
+        let mut data = make_id3_with_priv_key("");
+        data.extend(frames.concat());
+        data.extend_from_slice(&[0xFF, 0xFB, 0x90]); // partial frame
+
+        let cipher = QMC2RC4::new(&generate_key(512));
+        cipher.decrypt(&mut data, 0);
+
+        let mut sink = CollectSink::default();
+        let format = play(&cipher, Cursor::new(data), &mut sink).unwrap();
+        assert_eq!(format, AudioType::MP3);
+        assert_eq!(sink.format, Some(AudioType::MP3));
+        assert_eq!(sink.frames, frames);
+    }
+
+    #[test]
+    fn test_play_unknown() {
+        let cipher = QMC2RC4::new(&generate_key(512));
+        let mut sink = CollectSink::default();
+        let result = play(&cipher, Cursor::new(vec![0u8; 0x100]), &mut sink);
+        assert!(matches!(result, Err(QmcCryptoError::UnknownAudio)));
+        assert_eq!(sink.format, None);
+    }
+}
@@ -0,0 +1,99 @@
+use crate::QMCv2Cipher;
+use bytes::{Bytes, BytesMut};
+use futures_core::Stream;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+/// Decrypts a stream of encrypted chunks, e.g. an HTTP response body.
+///
+/// Chunks can be of any size; the running offset is tracked across chunks, so
+/// they do not need to line up with segment boundaries.
+pub struct DecryptStream<S> {
+    inner: S,
+    cipher: QMCv2Cipher,
+    offset: usize,
+}
+
+impl<S> DecryptStream<S> {
+    pub fn new(inner: S, cipher: QMCv2Cipher) -> Self {
+        Self::new_at(inner, cipher, 0)
+    }
+
+    /// Same as [`DecryptStream::new`], for a stream that starts at `offset` of the file.
+    pub fn new_at(inner: S, cipher: QMCv2Cipher, offset: usize) -> Self {
+        Self {
+            inner,
+            cipher,
+            offset,
+        }
+    }
+
+    /// File offset of the next byte to be decrypted.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<S, E> Stream for DecryptStream<S>
+where
+    S: Stream<Item = Result<Bytes, E>> + Unpin,
+{
+    type Item = Result<Bytes, E>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        let chunk = match Pin::new(&mut this.inner).poll_next(cx) {
+            Poll::Ready(Some(Ok(chunk))) => chunk,
+            other => return other,
+        };
+
+        let mut data = BytesMut::from(chunk);
+        this.cipher.decrypt(&mut data[..], this.offset);
+        this.offset += data.len();
+        Poll::Ready(Some(Ok(data.freeze())))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::generate_key;
+    use futures::executor::block_on;
+    use futures::{stream, StreamExt};
+    use std::convert::Infallible;
+
+    #[test]
+    fn test_decrypt_stream_with_unaligned_chunks() {
+        let cipher = QMCv2Cipher::new(generate_key(512)).unwrap();
+        let encrypted = (0..0x5000).map(|i| (i * 3) as u8).collect::<Vec<_>>();
+
+        let mut expected = encrypted.clone();
+        cipher.decrypt(&mut expected, 0);
+
+        let mut chunks = vec![];
+        let mut rest = &encrypted[..];
+        for size in [1, 0x7f, 0x81, 0x13ff, 0x1401, 7, 0x2000].iter().cycle() {
+            if rest.is_empty() {
+                break;
+            }
+            let (chunk, tail) = rest.split_at((*size).min(rest.len()));
+            chunks.push(Ok::<_, Infallible>(Bytes::copy_from_slice(chunk)));
+            rest = tail;
+        }
+
+        let decrypted = block_on(
+            DecryptStream::new(stream::iter(chunks), cipher)
+                .map(|chunk| chunk.unwrap().to_vec())
+                .concat(),
+        );
+        assert_eq!(decrypted, expected);
+    }
+}
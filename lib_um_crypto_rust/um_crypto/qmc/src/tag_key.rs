@@ -0,0 +1,289 @@
+//! EKey embedded in a leading ID3v2 tag, as written by some malformed exports
+//! instead of a footer.
+//!
+//! The tag itself is left in plain text; the audio after it is encrypted as if
+//! it was a file of its own (i.e. starting at offset 0).
+//!
+//! Some other tools store the EKey in an APEv2 tag item instead, see
+//! [`parse_apev2_key`].
+
+use crate::ekey;
+use byteorder::{ByteOrder, BE, LE};
+
+const ID3_HEADER_LEN: usize = 10;
+const ID3_FRAME_HEADER_LEN: usize = 10;
+
+/// Owner identifier of the ID3 `PRIV` frame holding the EKey.
+pub const ID3_PRIV_OWNER: &[u8] = b"QMC_EKEY";
+
+/// Key of the APEv2 item holding the EKey; APE item keys are case-insensitive.
+pub const APE_KEY_ITEM: &str = "UM_KEY";
+
+const APE_MAGIC: &[u8; 8] = b"APETAGEX";
+const APE_HEADER_LEN: usize = 32;
+const ID3V1_TAG_LEN: usize = 128;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TagKey {
+    /// Embedded key (not decrypted).
+    pub ekey: String,
+    /// Size of the tag; the encrypted audio starts right after it.
+    pub tag_len: usize,
+}
+
+fn read_sync_safe_u32(buffer: &[u8]) -> Option<usize> {
+    let value = BE::read_u32(buffer);
+    if value & 0x80808080 != 0 {
+        return None;
+    }
+    let value = (value & 0x7F000000) >> 3
+        | (value & 0x007F0000) >> 2
+        | (value & 0x00007F00) >> 1
+        | (value & 0x0000007F);
+    Some(value as usize)
+}
+
+/// Size of the ID3v2 tag at the start of `data`, `0` if there is none.
+pub fn id3_tag_len(data: &[u8]) -> usize {
+    if data.len() < ID3_HEADER_LEN || !data.starts_with(b"ID3") {
+        return 0;
+    }
+    read_sync_safe_u32(&data[6..10]).map_or(0, |size| ID3_HEADER_LEN + size)
+}
+
+/// Find the EKey stored in a `PRIV` frame (owner [`ID3_PRIV_OWNER`]) of the ID3v2
+/// tag at the start of `data`.
+///
+/// Only ID3v2.3 and v2.4 tags without unsynchronisation are supported.
+pub fn find_id3_priv_key(data: &[u8]) -> Option<TagKey> {
+    let tag_len = id3_tag_len(data);
+    if tag_len == 0 || tag_len > data.len() {
+        return None;
+    }
+
+    let version = data[3];
+    let flags = data[5];
+    if !matches!(version, 3 | 4) || flags & 0x80 != 0 {
+        return None;
+    }
+
+    let mut pos = ID3_HEADER_LEN;
+    if flags & 0x40 != 0 {
+        // Extended header: v2.3 size excludes itself, v2.4 size includes itself.
+        let size = data.get(pos..pos + 4)?;
+        pos += match version {
+            3 => 4 + BE::read_u32(size) as usize,
+            _ => read_sync_safe_u32(size)?,
+        };
+    }
+
+    while pos + ID3_FRAME_HEADER_LEN <= tag_len {
+        let header = &data[pos..pos + ID3_FRAME_HEADER_LEN];
+        if header[0] == 0 {
+            break; // padding
+        }
+        let size = match version {
+            3 => BE::read_u32(&header[4..8]) as usize,
+            _ => read_sync_safe_u32(&header[4..8])?,
+        };
+        let body = data.get(pos + ID3_FRAME_HEADER_LEN..pos + ID3_FRAME_HEADER_LEN + size)?;
+        pos += ID3_FRAME_HEADER_LEN + size;
+
+        if &header[..4] != b"PRIV" {
+            continue;
+        }
+        let (owner, ekey) = match body.iter().position(|&b| b == 0) {
+            Some(i) => (&body[..i], &body[i + 1..]),
+            None => continue,
+        };
+        if owner != ID3_PRIV_OWNER {
+            continue;
+        }
+        let ekey = std::str::from_utf8(ekey).ok()?.trim();
+        if !ekey.is_empty() {
+            return Some(TagKey {
+                ekey: ekey.to_string(),
+                tag_len,
+            });
+        }
+    }
+
+    None
+}
+
+/// Items of the APEv2 tag of `data`: its footer at the end of the file (possibly
+/// followed by an ID3v1 tag), or its header at the start of the file.
+fn find_apev2_items(data: &[u8]) -> Option<(&[u8], usize)> {
+    let has_id3v1 =
+        data.len() >= ID3V1_TAG_LEN && data[data.len() - ID3V1_TAG_LEN..].starts_with(b"TAG");
+    let id3v1_len = match has_id3v1 {
+        true => ID3V1_TAG_LEN,
+        false => 0,
+    };
+    let footer_start = data.len().checked_sub(id3v1_len + APE_HEADER_LEN)?;
+
+    //  offset    value
+    //       0    magic("APETAGEX") u32(version)
+    //      12    u32(tag_len: items + footer) u32(item_count) u32(flags)
+    //      24    byte[8] reserved
+    for (start, is_header) in [(footer_start, false), (0, true)] {
+        let header = &data[start..start + APE_HEADER_LEN];
+        if !header.starts_with(APE_MAGIC) {
+            continue;
+        }
+        let items_len = (LE::read_u32(&header[12..16]) as usize).checked_sub(APE_HEADER_LEN)?;
+        let item_count = LE::read_u32(&header[16..20]) as usize;
+        let items = match is_header {
+            true => data.get(APE_HEADER_LEN..APE_HEADER_LEN.checked_add(items_len)?)?,
+            false => data.get(start.checked_sub(items_len)?..start)?,
+        };
+        return Some((items, item_count));
+    }
+    None
+}
+
+/// Find the EKey stored in the [`APE_KEY_ITEM`] item of the APEv2 tag of `data`.
+pub fn find_apev2_ekey(data: &[u8]) -> Option<String> {
+    let (mut items, item_count) = find_apev2_items(data)?;
+
+    //  offset    value
+    //       0    u32(value_len) u32(flags)
+    //       8    cstr(key) byte[value_len] value
+    for _ in 0..item_count {
+        let value_len = LE::read_u32(items.get(..4)?) as usize;
+        let key_len = items.get(8..)?.iter().position(|&b| b == 0)?;
+        let key = &items[8..8 + key_len];
+        let value_start = 8 + key_len + 1;
+        let value = items.get(value_start..value_start.checked_add(value_len)?)?;
+        items = &items[value_start + value_len..];
+
+        if key.eq_ignore_ascii_case(APE_KEY_ITEM.as_bytes()) {
+            let ekey = std::str::from_utf8(value).ok()?.trim();
+            return (!ekey.is_empty()).then(|| ekey.to_string());
+        }
+    }
+    None
+}
+
+/// Find and decode the EKey stored in the APEv2 tag of `data`, see
+/// [`find_apev2_ekey`].
+///
+/// returns: The decoded key, ready to be passed to [`crate::QMCv2Cipher::new`].
+pub fn parse_apev2_key(data: &[u8]) -> Option<Vec<u8>> {
+    ekey::decrypt(find_apev2_ekey(data)?).ok()
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// ID3v2.3 tag with a `TIT2` frame, then a `PRIV` frame holding `ekey`.
+    pub fn make_id3_with_priv_key(ekey: &str) -> Vec<u8> {
+        let mut frames = vec![];
+        let title = b"\x00title";
+        frames.extend_from_slice(b"TIT2");
+        frames.extend_from_slice(&(title.len() as u32).to_be_bytes());
+        frames.extend_from_slice(&[0, 0]);
+        frames.extend_from_slice(title);
+
+        let mut body = ID3_PRIV_OWNER.to_vec();
+        body.push(0);
+        body.extend_from_slice(ekey.as_bytes());
+        frames.extend_from_slice(b"PRIV");
+        frames.extend_from_slice(&(body.len() as u32).to_be_bytes());
+        frames.extend_from_slice(&[0, 0]);
+        frames.extend_from_slice(&body);
+        frames.extend_from_slice(&[0u8; 0x20]); // padding
+
+        let size = frames.len() as u32;
+        let sync_safe = (size & 0x7F)
+            | ((size << 1) & 0x7F00)
+            | ((size << 2) & 0x7F0000)
+            | ((size << 3) & 0x7F000000);
+        let mut tag = b"ID3\x03\x00\x00".to_vec();
+        tag.extend_from_slice(&sync_safe.to_be_bytes());
+        tag.extend_from_slice(&frames);
+        tag
+    }
+
+    #[test]
+    fn test_find_id3_priv_key() {
+        let tag = make_id3_with_priv_key("ekey123");
+        let mut data = tag.clone();
+        data.extend_from_slice(&[0x55u8; 0x100]);
+
+        assert_eq!(id3_tag_len(&data), tag.len());
+        assert_eq!(
+            find_id3_priv_key(&data),
+            Some(TagKey {
+                ekey: "ekey123".into(),
+                tag_len: tag.len(),
+            })
+        );
+    }
+
+    #[test]
+    fn test_find_id3_priv_key_missing() {
+        assert_eq!(find_id3_priv_key(&[0x55u8; 0x100]), None);
+        assert_eq!(id3_tag_len(&[0x55u8; 0x100]), 0);
+
+        // Truncated tag.
+        let tag = make_id3_with_priv_key("ekey123");
+        assert_eq!(find_id3_priv_key(&tag[..tag.len() - 1]), None);
+    }
+
+    /// APEv2 tag (header, items, footer) with a `TITLE` item, then `item_key`.
+    fn make_apev2(item_key: &str, ekey: &str) -> Vec<u8> {
+        let mut items = vec![];
+        for (key, value) in [("Title", "title"), (item_key, ekey)] {
+            items.extend_from_slice(&(value.len() as u32).to_le_bytes());
+            items.extend_from_slice(&0u32.to_le_bytes());
+            items.extend_from_slice(key.as_bytes());
+            items.push(0);
+            items.extend_from_slice(value.as_bytes());
+        }
+
+        let header = |flags: u32| {
+            let mut header = APE_MAGIC.to_vec();
+            header.extend_from_slice(&2000u32.to_le_bytes());
+            header.extend_from_slice(&((items.len() + APE_HEADER_LEN) as u32).to_le_bytes());
+            header.extend_from_slice(&2u32.to_le_bytes());
+            header.extend_from_slice(&flags.to_le_bytes());
+            header.extend_from_slice(&[0u8; 8]);
+            header
+        };
+        // Flags: has a header, is the header.
+        [header(1 << 31 | 1 << 29), items.clone(), header(1 << 31)].concat()
+    }
+
+    #[test]
+    fn test_parse_apev2_key() {
+        const EKEY: &str = include_str!("v2_rc4/fixtures/ekey_rc4_512.txt");
+        let expected = ekey::decrypt(EKEY).unwrap();
+
+        // Tag at the end of the file.
+        let mut data = vec![0x55u8; 0x100];
+        data.extend_from_slice(&make_apev2("um_key", EKEY));
+        assert_eq!(parse_apev2_key(&data), Some(expected.clone()));
+
+        // Followed by an ID3v1 tag.
+        let mut tag_v1 = b"TAG".to_vec();
+        tag_v1.resize(ID3V1_TAG_LEN, 0);
+        data.extend_from_slice(&tag_v1);
+        assert_eq!(parse_apev2_key(&data), Some(expected.clone()));
+
+        // Tag at the start of the file.
+        let mut data = make_apev2("UM_KEY", EKEY);
+        data.extend_from_slice(&[0x55u8; 0x100]);
+        assert_eq!(parse_apev2_key(&data), Some(expected));
+    }
+
+    #[test]
+    fn test_parse_apev2_key_missing() {
+        let mut data = vec![0x55u8; 0x100];
+        assert_eq!(parse_apev2_key(&data), None);
+
+        data.extend_from_slice(&make_apev2("Artist", "artist"));
+        assert_eq!(find_apev2_ekey(&data), None);
+    }
+}
@@ -0,0 +1,119 @@
+//! Reference vectors, to check other implementations of the QMCv2 ciphers against.
+//!
+//! Vectors only depend on the seed, and serialize to JSON with the byte fields as
+//! lowercase hex strings.
+
+use crate::QMCv2Cipher;
+use serde::{Serialize, Serializer};
+
+/// Key lengths to pick from, covering both the map (<= 300) and RC4 ciphers.
+const KEY_LENGTHS: [usize; 5] = [128, 256, 300, 512, 1024];
+const MAX_OFFSET: u64 = 0x10000;
+const MAX_DATA_LEN: u64 = 0x3000;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TestVector {
+    /// Decoded key; the cipher is picked from its length.
+    #[serde(serialize_with = "to_hex")]
+    pub key: Vec<u8>,
+    /// File offset of `plaintext`.
+    pub offset: usize,
+    #[serde(serialize_with = "to_hex")]
+    pub plaintext: Vec<u8>,
+    #[serde(serialize_with = "to_hex")]
+    pub ciphertext: Vec<u8>,
+}
+
+fn to_hex<S: Serializer>(data: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+    let hex = data.iter().map(|b| format!("{b:02x}")).collect::<String>();
+    serializer.serialize_str(&hex)
+}
+
+/// SplitMix64, simple and easy to port.
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E3779B97F4A7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        z ^ (z >> 31)
+    }
+
+    fn bytes(&mut self, len: usize) -> Vec<u8> {
+        (0..len).map(|_| self.next() as u8).collect()
+    }
+}
+
+/// Generate `count` vectors from `seed`.
+///
+/// Each vector uses a random key (of one of a few lengths), offset and plaintext.
+pub fn generate_vectors(seed: u64, count: usize) -> Vec<TestVector> {
+    let mut rng = SplitMix64(seed);
+
+    (0..count)
+        .map(|_| {
+            let key_len = KEY_LENGTHS[(rng.next() % KEY_LENGTHS.len() as u64) as usize];
+            // Zero key bytes are legal, but keep them non-zero so that the hash
+            // covers the whole key.
+            let key = rng.bytes(key_len).into_iter().map(|b| b | 1).collect();
+            let offset = (rng.next() % MAX_OFFSET) as usize;
+            let data_len = (rng.next() % MAX_DATA_LEN + 1) as usize;
+            let plaintext = rng.bytes(data_len);
+
+            let mut ciphertext = plaintext.clone();
+            QMCv2Cipher::new(&key)
+                .expect("key is not empty")
+                .decrypt(&mut ciphertext, offset);
+
+            TestVector {
+                key,
+                offset,
+                plaintext,
+                ciphertext,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generate_vectors_deterministic() {
+        let vectors = generate_vectors(42, 16);
+        assert_eq!(vectors.len(), 16);
+        assert_eq!(generate_vectors(42, 16), vectors);
+        assert_ne!(generate_vectors(43, 16), vectors);
+
+        // A longer run starts with the same vectors.
+        assert_eq!(&generate_vectors(42, 32)[..16], &vectors[..]);
+    }
+
+    #[test]
+    fn test_vectors_decrypt() {
+        for vector in generate_vectors(1, 16) {
+            let mut data = vector.ciphertext.clone();
+            QMCv2Cipher::new(&vector.key)
+                .unwrap()
+                .decrypt(&mut data, vector.offset);
+            assert_eq!(data, vector.plaintext);
+        }
+    }
+
+    #[test]
+    fn test_vectors_to_json() {
+        let vector = TestVector {
+            key: vec![0x01, 0xAB],
+            offset: 3,
+            plaintext: vec![0x00],
+            ciphertext: vec![0xFF],
+        };
+        assert_eq!(
+            serde_json::to_string(&vector).unwrap(),
+            r#"{"key":"01ab","offset":3,"plaintext":"00","ciphertext":"ff"}"#
+        );
+    }
+}
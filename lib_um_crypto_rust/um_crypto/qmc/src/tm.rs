@@ -0,0 +1,56 @@
+//! Legacy QQ Music `.tm*` files.
+//!
+//! * `.tm0` / `.tm3`: plain MP3, nothing to do.
+//! * `.tm2` / `.tm6`: M4A, with the first 8 bytes (size + `ftyp` box type)
+//!   replaced by a fake header starting with `QQMU`.
+
+const TM_MAGIC: [u8; 4] = *b"QQMU";
+const TM_HEADER_LEN: usize = 8;
+
+/// Original M4A header: a `0x20` byte `ftyp` box.
+const M4A_HEADER: [u8; TM_HEADER_LEN] = [0x00, 0x00, 0x00, 0x20, b'f', b't', b'y', b'p'];
+
+/// Check if `data` starts with the fake `.tm2` / `.tm6` header.
+pub fn is_tm_header(data: &[u8]) -> bool {
+    data.len() >= TM_HEADER_LEN && data.starts_with(&TM_MAGIC)
+}
+
+/// Restore the original M4A header of a `.tm2` / `.tm6` file.
+///
+/// returns: `true` if the fake header was found and replaced. Other files,
+///          including `.tm0` / `.tm3` (already plain MP3), are left untouched.
+pub fn fix_tm_header(data: &mut [u8]) -> bool {
+    if !is_tm_header(data) {
+        return false;
+    }
+
+    data[..TM_HEADER_LEN].copy_from_slice(&M4A_HEADER);
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use um_audio::mp3::tests::make_mp3_frame;
+    use um_audio::{detect_audio_type, AudioType};
+
+    #[test]
+    fn test_fix_tm2_header() {
+        let mut data = [0u8; 0x20];
+        data[..8].copy_from_slice(b"QQMU\x00\x00\x00\x00");
+        data[8..12].copy_from_slice(b"M4A ");
+
+        assert!(fix_tm_header(&mut data));
+        assert_eq!(&data[..8], b"\x00\x00\x00\x20ftyp");
+        assert_eq!(detect_audio_type(&data).unwrap(), AudioType::M4A);
+    }
+
+    #[test]
+    fn test_fix_tm_header_ignores_plain_files() {
+        let mut data = make_mp3_frame();
+        let expected = data.clone();
+
+        assert!(!fix_tm_header(&mut data));
+        assert_eq!(data, expected);
+    }
+}
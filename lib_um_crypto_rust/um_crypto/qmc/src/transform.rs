@@ -0,0 +1,54 @@
+//! Post-decrypt transforms, for variants that scramble the audio further after the
+//! QMC cipher.
+
+/// Transform applied to the decrypted audio, see [`QmcDecoder::post_transform`].
+///
+/// [`QmcDecoder::post_transform`]: crate::decoder::QmcDecoder::post_transform
+pub trait PostTransform: Send + Sync {
+    /// Transform `data` in place, located at `offset` of the (decrypted) audio.
+    fn apply(&self, data: &mut [u8], offset: usize);
+}
+
+/// Leaves the data as-is.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoopTransform;
+
+impl PostTransform for NoopTransform {
+    fn apply(&self, _data: &mut [u8], _offset: usize) {}
+}
+
+/// Replace each byte `b` with `table[b]`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BytePermute {
+    table: [u8; 256],
+}
+
+impl BytePermute {
+    pub fn new(table: [u8; 256]) -> Self {
+        Self { table }
+    }
+}
+
+impl PostTransform for BytePermute {
+    fn apply(&self, data: &mut [u8], _offset: usize) {
+        for datum in data.iter_mut() {
+            *datum = self.table[*datum as usize];
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_byte_permute() {
+        let permute = BytePermute::new(std::array::from_fn(|i| (i as u8).rotate_left(1)));
+        let mut data = [0x01, 0x80, 0x55];
+        permute.apply(&mut data, 0);
+        assert_eq!(data, [0x02, 0x01, 0xAA]);
+
+        NoopTransform.apply(&mut data, 0);
+        assert_eq!(data, [0x02, 0x01, 0xAA]);
+    }
+}
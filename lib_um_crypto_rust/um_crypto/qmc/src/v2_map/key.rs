@@ -3,6 +3,19 @@ use crate::QmcCryptoError;
 
 const INDEX_OFFSET: usize = 71214;
 
+/// Index of the long key byte used for byte `i` of the compressed key.
+fn key_index(i: usize, n: usize) -> usize {
+    (i * i + INDEX_OFFSET) % n
+}
+
+/// Number of distinct bytes of a long key of length `n` used in the compressed key.
+pub(crate) fn used_key_bytes(n: usize) -> usize {
+    (0..V1_KEY_SIZE)
+        .map(|i| key_index(i, n))
+        .collect::<std::collections::HashSet<_>>()
+        .len()
+}
+
 pub fn key_compress<T: AsRef<[u8]>>(long_key: T) -> anyhow::Result<[u8; V1_KEY_SIZE]> {
     let long_key = long_key.as_ref();
     if long_key.is_empty() {
@@ -13,7 +26,7 @@ pub fn key_compress<T: AsRef<[u8]>>(long_key: T) -> anyhow::Result<[u8; V1_KEY_S
     let mut result = [0u8; V1_KEY_SIZE];
 
     let key_stream = (0..V1_KEY_SIZE).map(|i| {
-        let i = (i * i + INDEX_OFFSET) % n;
+        let i = key_index(i, n);
         let key = long_key[i];
         let shift = ((i + 4) % 8) as u32;
         key.wrapping_shl(shift) | key.wrapping_shr(shift)
@@ -1,7 +1,10 @@
 mod key;
 
+pub(crate) use key::used_key_bytes;
+
 use crate::v1::cipher::{qmc1_transform, V1_KEY_SIZE};
 use crate::v2_map::key::key_compress;
+use crate::QmcCipher;
 use anyhow::Result;
 
 #[derive(Debug, PartialEq, Clone)]
@@ -11,6 +14,11 @@ pub struct QMC2Map {
 
 impl QMC2Map {
     pub fn new<T: AsRef<[u8]>>(key: T) -> Result<Self> {
+        trace_event!(
+            key_len = key.as_ref().len(),
+            fingerprint = %crate::key_fingerprint_hex(key.as_ref()),
+            "created QMC2Map cipher"
+        );
         let key = key_compress(key)?;
         Ok(Self { key })
     }
@@ -25,6 +33,12 @@ impl QMC2Map {
     }
 }
 
+impl QmcCipher for QMC2Map {
+    fn decrypt(&self, data: &mut [u8], offset: usize) {
+        QMC2Map::decrypt(self, data, offset)
+    }
+}
+
 #[test]
 fn test_decrypt() {
     let key = (b'a'..=b'z')
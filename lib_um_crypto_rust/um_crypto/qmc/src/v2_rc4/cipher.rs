@@ -1,77 +1,515 @@
+use crate::ekey;
 use crate::v2_rc4::hash::hash;
+use crate::v2_rc4::layout::{segment_layout_with_size, SegmentInfo, SegmentKind};
 use crate::v2_rc4::rc4::RC4;
-use crate::v2_rc4::segment_key::get_segment_key;
+use crate::v2_rc4::segment_key::{DefaultSegmentKey, SegmentKeyFn};
+use crate::{QmcCipher, QmcCryptoError};
 use std::cmp::min;
+use std::fmt;
+use std::io::{IoSliceMut, Read};
+use std::ops::Range;
+use std::sync::Arc;
+use um_audio::{AudioError, AudioType};
 
-const FIRST_SEGMENT_SIZE: usize = 0x0080;
-const OTHER_SEGMENT_SIZE: usize = 0x1400;
-const RC4_STREAM_CACHE_SIZE: usize = OTHER_SEGMENT_SIZE + 512;
+/// Size of the first segment, where each byte has its own key.
+pub const FIRST_SEGMENT_SIZE: usize = 0x0080;
 
-#[derive(Debug, PartialEq, Clone)]
+/// Size of the other segments, each decrypted with a slice of the RC4 key stream.
+///
+/// Segments are aligned to this size in the file (the first segment is part of
+/// segment 0). Reads aligned to it touch as few segments as possible:
+///
+/// ```
+/// use umc_qmc::v2_rc4::cipher::{QMC2RC4, OTHER_SEGMENT_SIZE};
+///
+/// let cipher = QMC2RC4::new(&[0x55u8; 512]);
+///
+/// // Start reading from the segment holding `position`.
+/// let position = 0x3210;
+/// let start = position / OTHER_SEGMENT_SIZE * OTHER_SEGMENT_SIZE;
+/// assert_eq!(start, 2 * OTHER_SEGMENT_SIZE);
+///
+/// let mut buffer = vec![0u8; 4 * OTHER_SEGMENT_SIZE];
+/// for (i, segment) in buffer.chunks_mut(OTHER_SEGMENT_SIZE).enumerate() {
+///     cipher.decrypt(segment, start + i * OTHER_SEGMENT_SIZE);
+/// }
+/// ```
+///
+/// Ciphers built with a custom segment size report it in [`QMC2RC4::segment_size`].
+pub const OTHER_SEGMENT_SIZE: usize = 0x1400;
+pub(super) const RC4_STREAM_CACHE_SIZE: usize = OTHER_SEGMENT_SIZE + 512;
+
+/// Largest key stream skip of an other segment, see [`QMC2RC4::segment_skip`].
+const MAX_SEGMENT_SKIP: usize = 0x1FF;
+
+// Segment sizes are capped at `OTHER_SEGMENT_SIZE` (see `QMC2RC4::with_segment_size`),
+// so the cached key stream always covers a whole segment, whatever its skip.
+const _: () = assert!(MAX_SEGMENT_SKIP + OTHER_SEGMENT_SIZE <= RC4_STREAM_CACHE_SIZE);
+
+/// Number of bytes decrypted by [`QMC2RC4::probe_key`].
+pub const PROBE_SIZE: usize = 0x400;
+
+/// How [`QMC2RC4`] computes the key of the first segment bytes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DecryptMode {
+    /// Precompute the first segment key into a table when the cipher is built;
+    /// cheapest when whole files are decrypted.
+    #[default]
+    WholeFile,
+    /// Compute the key of each first segment byte as it is decrypted, e.g. when only
+    /// a few header bytes are ever decrypted, to skip building the table.
+    HeaderOnly,
+}
+
+#[derive(Clone)]
 pub struct QMC2RC4 {
     hash: f64,
     key: Box<[u8]>,
-    key_stream: Box<[u8; RC4_STREAM_CACHE_SIZE]>,
+    /// Shared between clones; it only depends on the key.
+    key_stream: Arc<[u8; RC4_STREAM_CACHE_SIZE]>,
+    first_segment_size: usize,
+    segment_size: usize,
+    /// Custom segment key function, `None` for [`DefaultSegmentKey`].
+    segment_key_fn: Option<Arc<dyn SegmentKeyFn>>,
+    /// Key of each first segment byte, only with [`DecryptMode::WholeFile`].
+    first_segment_table: Option<Arc<[u8]>>,
+}
+
+impl fmt::Debug for QMC2RC4 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("QMC2RC4")
+            .field("hash", &self.hash)
+            .field("key_len", &self.key.len())
+            .field("key_fingerprint", &crate::key_fingerprint_hex(&self.key))
+            .field("first_segment_size", &self.first_segment_size)
+            .field("segment_size", &self.segment_size)
+            .field("custom_segment_key_fn", &self.segment_key_fn.is_some())
+            .field("decrypt_mode", &self.decrypt_mode())
+            .finish_non_exhaustive()
+    }
+}
+
+impl PartialEq for QMC2RC4 {
+    fn eq(&self, other: &Self) -> bool {
+        self.equivalent_to(other)
+    }
+}
+
+impl TryFrom<&[u8]> for QMC2RC4 {
+    type Error = QmcCryptoError;
+
+    /// See [`QMC2RC4::try_new`].
+    fn try_from(key: &[u8]) -> Result<Self, Self::Error> {
+        Self::try_new(key)
+    }
+}
+
+/// Builder for a [`QMC2RC4`] with non-standard parameters.
+pub struct QMC2RC4Builder {
+    key: Box<[u8]>,
+    first_segment_size: usize,
+    segment_size: usize,
+    segment_key_fn: Option<Arc<dyn SegmentKeyFn>>,
+    decrypt_mode: DecryptMode,
+}
+
+impl QMC2RC4Builder {
+    pub fn new(key: &[u8]) -> Self {
+        Self {
+            key: key.into(),
+            first_segment_size: FIRST_SEGMENT_SIZE,
+            segment_size: OTHER_SEGMENT_SIZE,
+            segment_key_fn: None,
+            decrypt_mode: DecryptMode::default(),
+        }
+    }
+
+    /// Size of the first segment, where each byte has its own key (default: 0x80).
+    ///
+    /// Only some early variants use another size.
+    pub fn first_segment_size(mut self, first_segment_size: usize) -> Self {
+        self.first_segment_size = first_segment_size;
+        self
+    }
+
+    /// See [`QMC2RC4::with_segment_size`].
+    pub fn segment_size(mut self, segment_size: usize) -> Self {
+        self.segment_size = segment_size;
+        self
+    }
+
+    /// Replace the segment key function (default: [`DefaultSegmentKey`]).
+    ///
+    /// The default is called directly; a custom function is called through a
+    /// trait object, which is slower.
+    pub fn segment_key_fn<F: SegmentKeyFn + 'static>(mut self, segment_key_fn: F) -> Self {
+        self.segment_key_fn = Some(Arc::new(segment_key_fn));
+        self
+    }
+
+    /// Hint on how the cipher will be used (default: [`DecryptMode::WholeFile`]).
+    ///
+    /// Both modes decrypt identically.
+    pub fn decrypt_mode(mut self, decrypt_mode: DecryptMode) -> Self {
+        self.decrypt_mode = decrypt_mode;
+        self
+    }
+
+    /// # Panics
+    ///
+    /// If the segment size is not in the range `0x80..=0x1400`, or is smaller
+    /// than the first segment size.
+    pub fn build(self) -> QMC2RC4 {
+        let mut cipher = QMC2RC4::new_header_only(&self.key);
+        cipher.first_segment_size = self.first_segment_size;
+        let mut cipher = cipher.with_segment_size(self.segment_size);
+        cipher.segment_key_fn = self.segment_key_fn;
+        cipher.with_decrypt_mode(self.decrypt_mode)
+    }
 }
 
 impl QMC2RC4 {
     pub fn new(key: &[u8]) -> Self {
+        Self::new_header_only(key).with_decrypt_mode(DecryptMode::WholeFile)
+    }
+
+    fn new_header_only(key: &[u8]) -> Self {
         let mut rc4 = RC4::new(key);
         let mut key_stream = Box::new([0u8; RC4_STREAM_CACHE_SIZE]);
         rc4.derive(&mut key_stream[..]);
+        trace_event!(
+            key_len = key.len(),
+            fingerprint = %crate::key_fingerprint_hex(key),
+            "created QMC2RC4 cipher"
+        );
 
+        Self::from_parts(key, hash(key), key_stream)
+    }
+
+    /// Cipher with the standard layout, from already derived key material.
+    pub(super) fn from_parts(
+        key: &[u8],
+        hash: f64,
+        key_stream: Box<[u8; RC4_STREAM_CACHE_SIZE]>,
+    ) -> Self {
         Self {
-            hash: hash(key),
+            hash,
             key: key.into(),
-            key_stream,
+            key_stream: Arc::from(key_stream),
+            first_segment_size: FIRST_SEGMENT_SIZE,
+            segment_size: OTHER_SEGMENT_SIZE,
+            segment_key_fn: None,
+            first_segment_table: None,
+        }
+    }
+
+    pub(super) fn with_decrypt_mode(mut self, decrypt_mode: DecryptMode) -> Self {
+        self.first_segment_table = match decrypt_mode {
+            DecryptMode::WholeFile => {
+                let mut table = vec![0u8; self.first_segment_size];
+                self.process_first_segment_live(&mut table, 0);
+                Some(table.into())
+            }
+            DecryptMode::HeaderOnly => None,
+        };
+        self
+    }
+
+    pub fn decrypt_mode(&self) -> DecryptMode {
+        match self.first_segment_table {
+            Some(_) => DecryptMode::WholeFile,
+            None => DecryptMode::HeaderOnly,
+        }
+    }
+
+    /// Same as [`QMC2RC4::new`], as a trait object.
+    ///
+    /// Handy for storing ciphers of different kinds together, e.g. in a plugin registry.
+    pub fn new_boxed(key: &[u8]) -> Box<dyn QmcCipher> {
+        Box::new(Self::new(key))
+    }
+
+    pub fn builder(key: &[u8]) -> QMC2RC4Builder {
+        QMC2RC4Builder::new(key)
+    }
+
+    /// Same as [`QMC2RC4::new`], but rejects an empty key instead of panicking later.
+    pub fn try_new(key: &[u8]) -> Result<Self, QmcCryptoError> {
+        if key.is_empty() {
+            Err(QmcCryptoError::QMCV2RC4KeyEmpty)?;
         }
+        Ok(Self::new(key))
+    }
+
+    /// Decrypt the base64 encoded `ekey` and create the cipher from the result.
+    pub fn from_ekey<T: AsRef<[u8]>>(ekey_str: T) -> Result<Self, QmcCryptoError> {
+        let key = ekey::decrypt(ekey_str).map_err(QmcCryptoError::EKeyDecodeError)?;
+        Self::try_new(&key)
+    }
+
+    /// Create a copy of this cipher that uses `segment_size` for the "other" segments.
+    ///
+    /// The RC4 key stream is shared with `self` instead of being derived again. This
+    /// is sound as the key stream is the RC4 output for the key alone; the segment
+    /// size only changes which part of that stream each segment reads (segment id
+    /// and skip). The cached key stream covers segments of up to 0x1400 bytes.
+    ///
+    /// # Panics
+    ///
+    /// If `segment_size` is not in the range `0x80..=0x1400`, or is smaller than
+    /// the first segment size.
+    pub fn with_segment_size(&self, segment_size: usize) -> Self {
+        assert!(
+            (FIRST_SEGMENT_SIZE..=OTHER_SEGMENT_SIZE).contains(&segment_size),
+            "segment size {segment_size:#x} out of range"
+        );
+        assert!(
+            self.first_segment_size <= segment_size,
+            "segment size {segment_size:#x} smaller than first segment size {:#x}",
+            self.first_segment_size
+        );
+
+        Self {
+            hash: self.hash,
+            key: self.key.clone(),
+            key_stream: Arc::clone(&self.key_stream),
+            first_segment_size: self.first_segment_size,
+            segment_size,
+            segment_key_fn: self.segment_key_fn.clone(),
+            first_segment_table: self.first_segment_table.clone(),
+        }
+    }
+
+    /// Stable, non-reversible fingerprint of the key, safe to log.
+    ///
+    /// This is the first 8 bytes of the MD5 digest of the key.
+    pub fn key_fingerprint(&self) -> [u8; 8] {
+        let digest = umc_utils::md5(&self.key);
+        let mut fingerprint = [0u8; 8];
+        fingerprint.copy_from_slice(&digest[..8]);
+        fingerprint
+    }
+
+    /// Check if both ciphers decrypt identically: same key and segment configuration.
+    ///
+    /// The key stream and hash are derived from the key, so they are never compared;
+    /// the segment configuration is checked before the key bytes. `==` does the same.
+    pub fn equivalent_to(&self, other: &Self) -> bool {
+        let same_segment_key_fn = match (&self.segment_key_fn, &other.segment_key_fn) {
+            (None, None) => true,
+            (Some(a), Some(b)) => Arc::ptr_eq(a, b),
+            _ => false,
+        };
+
+        self.first_segment_size == other.first_segment_size
+            && self.segment_size == other.segment_size
+            && same_segment_key_fn
+            && self.key == other.key
+    }
+
+    /// `(key, hash, key_stream)`, see [`QMC2RC4::save`].
+    pub(super) fn parts(&self) -> (&[u8], f64, &[u8; RC4_STREAM_CACHE_SIZE]) {
+        (&self.key, self.hash, &self.key_stream)
+    }
+
+    pub fn first_segment_size(&self) -> usize {
+        self.first_segment_size
+    }
+
+    pub fn segment_size(&self) -> usize {
+        self.segment_size
+    }
+
+    fn layout(&self, offset: usize, len: usize) -> Vec<SegmentInfo> {
+        segment_layout_with_size(offset, len, self.first_segment_size, self.segment_size)
     }
 
     fn process_first_segment(&self, data: &mut [u8], offset: usize) {
+        let table = self.first_segment_table.as_deref();
+        match table.and_then(|table| table.get(offset..offset + data.len())) {
+            Some(keys) => {
+                for (datum, &key) in data.iter_mut().zip(keys) {
+                    *datum ^= key;
+                }
+            }
+            None => self.process_first_segment_live(data, offset),
+        }
+    }
+
+    fn process_first_segment_live(&self, data: &mut [u8], offset: usize) {
+        match &self.segment_key_fn {
+            None => self.process_first_segment_with(&DefaultSegmentKey, data, offset),
+            Some(segment_key_fn) => {
+                self.process_first_segment_with(segment_key_fn.as_ref(), data, offset)
+            }
+        }
+    }
+
+    fn process_first_segment_with<F>(&self, segment_key_fn: &F, data: &mut [u8], offset: usize)
+    where
+        F: SegmentKeyFn + ?Sized,
+    {
         let n = self.key.len();
 
         for (datum, offset) in data.iter_mut().zip(offset..) {
-            let idx = get_segment_key(offset as u64, self.key[offset % n], self.hash);
+            let idx = segment_key_fn.segment_key(offset as u64, self.key[offset % n], self.hash);
             let idx = idx % (n as u64);
             *datum ^= self.key[idx as usize];
         }
     }
 
-    fn process_other_segment(&self, data: &mut [u8], offset: usize) {
-        let n = self.key.len();
-
-        let id = offset / OTHER_SEGMENT_SIZE;
-        let block_offset = offset % OTHER_SEGMENT_SIZE;
+    /// Key stream skip for the other segment with the given `id`.
+    pub(crate) fn segment_skip(&self, id: usize) -> usize {
+        let seed = self.key[id % self.key.len()];
+        let skip = match &self.segment_key_fn {
+            None => DefaultSegmentKey.segment_key(id as u64, seed, self.hash),
+            Some(segment_key_fn) => segment_key_fn.segment_key(id as u64, seed, self.hash),
+        };
+        skip as usize & MAX_SEGMENT_SKIP
+    }
 
-        let seed = self.key[id % n];
-        let skip = get_segment_key(id as u64, seed, self.hash);
-        let skip = (skip & 0x1FF) as usize;
+    fn process_other_segment(&self, data: &mut [u8], offset: usize) {
+        let id = offset / self.segment_size;
+        let block_offset = offset % self.segment_size;
+        let skip = self.segment_skip(id);
 
-        debug_assert!(data.len() <= OTHER_SEGMENT_SIZE - block_offset);
+        debug_assert!(data.len() <= self.segment_size - block_offset);
         let key_stream = self.key_stream.iter().skip(skip + block_offset);
         for (datum, &key) in data.iter_mut().zip(key_stream) {
             *datum ^= key;
         }
     }
 
+    /// Fast path of [`QMC2RC4::decrypt`] for a buffer that starts at offset 0.
+    fn decrypt_from_start(&self, data: &mut [u8]) {
+        let first_segment_size = self.first_segment_size;
+        if data.len() <= first_segment_size {
+            self.process_first_segment(data, 0);
+            return;
+        }
+        let (block, rest) = data.split_at_mut(first_segment_size);
+        self.process_first_segment(block, 0);
+
+        // Remainder of the first "other" segment.
+        if rest.len() <= self.segment_size - first_segment_size {
+            self.process_other_segment(rest, first_segment_size);
+            return;
+        }
+        let (block, rest) = rest.split_at_mut(self.segment_size - first_segment_size);
+        self.process_other_segment(block, first_segment_size);
+
+        let mut offset = self.segment_size;
+        let mut blocks = rest.chunks_exact_mut(self.segment_size);
+        for block in &mut blocks {
+            self.process_other_segment(block, offset);
+            offset += self.segment_size;
+        }
+        self.process_other_segment(blocks.into_remainder(), offset);
+    }
+
+    /// Decrypt `data`, located at `offset` of the file.
+    ///
+    /// `offset + data.len()` must not overflow `usize` (checked in debug builds);
+    /// use [`QMC2RC4::try_decrypt`] when the offset is not trusted.
+    ///
+    /// This only forwards to the non-generic [`QmcCipher::decrypt`], so the
+    /// decryption code itself is not duplicated for each `T`.
+    #[inline]
     pub fn decrypt<T>(&self, data: &mut T, offset: usize)
     where
         T: AsMut<[u8]> + ?Sized,
     {
+        QmcCipher::decrypt(self, data.as_mut(), offset)
+    }
+
+    /// Same as [`QMC2RC4::decrypt`], but reports [`QmcCryptoError::OffsetOverflow`]
+    /// instead of wrapping around when `offset + data.len()` overflows `usize`.
+    pub fn try_decrypt(&self, data: &mut [u8], offset: usize) -> Result<(), QmcCryptoError> {
+        if offset.checked_add(data.len()).is_none() {
+            Err(QmcCryptoError::OffsetOverflow {
+                offset,
+                len: data.len(),
+            })?;
+        }
+
+        self.decrypt(data, offset);
+        Ok(())
+    }
+
+    /// Same as [`QMC2RC4::decrypt`], taking the offset as any unsigned integer
+    /// (e.g. a `u64` file position or a `u32` range header).
+    ///
+    /// # Panics
+    ///
+    /// If `offset` does not fit in `usize` (only possible on 32-bit targets).
+    pub fn decrypt_at(&self, data: &mut [u8], offset: impl Into<u64>) {
+        let offset = offset.into();
+        let offset = usize::try_from(offset)
+            .unwrap_or_else(|_| panic!("offset {offset:#x} does not fit in usize"));
+        self.decrypt(data, offset);
+    }
+
+    /// Decrypt `src`, located at `offset` of the file, into `dst`.
+    ///
+    /// `src` and `dst` can't overlap, the borrow checker makes sure of it; to move
+    /// data within one buffer while decrypting it, see [`QMC2RC4::decrypt_within`].
+    ///
+    /// # Panics
+    ///
+    /// If `src` and `dst` have different lengths.
+    pub fn decrypt_into(&self, src: &[u8], dst: &mut [u8], offset: usize) {
+        assert_eq!(src.len(), dst.len(), "src and dst lengths differ");
+        dst.copy_from_slice(src);
+        self.decrypt(dst, offset);
+    }
+
+    /// Decrypt `data`, located at `offset` of the file, into a new shared buffer.
+    ///
+    /// `data` is left untouched, so it can stay shared too. The result only depends
+    /// on the key, `data` and `offset`: to serve it to several callers, cache it by
+    /// (key fingerprint, blob id, offset), see [`QMC2RC4::key_fingerprint`].
+    pub fn decrypt_shared(&self, data: Arc<[u8]>, offset: usize) -> Arc<[u8]> {
+        let mut buffer = data.to_vec();
+        self.decrypt(&mut buffer, offset);
+        buffer.into()
+    }
+
+    /// Move `buffer[src]` (located at `offset` of the file) to `buffer[dest..]`, as with
+    /// [`slice::copy_within`], and decrypt it there.
+    ///
+    /// The source and destination ranges may overlap.
+    ///
+    /// # Panics
+    ///
+    /// If either range is out of the bounds of `buffer`.
+    pub fn decrypt_within(&self, buffer: &mut [u8], src: Range<usize>, dest: usize, offset: usize) {
+        let len = src.len();
+        buffer.copy_within(src, dest);
+        self.decrypt(&mut buffer[dest..dest + len], offset);
+    }
+
+    /// Decrypt `data`, located at `*offset` of the file, then advance `*offset`
+    /// past it, ready for the next chunk.
+    pub fn decrypt_advancing(&self, data: &mut [u8], offset: &mut usize) {
+        self.decrypt(data, *offset);
+        *offset += data.len();
+    }
+
+    fn decrypt_generic(&self, buffer: &mut [u8], offset: usize) {
         let mut offset = offset;
-        let mut buffer = data.as_mut();
-        if offset < FIRST_SEGMENT_SIZE {
-            let n = min(FIRST_SEGMENT_SIZE - offset, buffer.len());
+        let mut buffer = buffer;
+        if offset < self.first_segment_size {
+            let n = min(self.first_segment_size - offset, buffer.len());
             let (block, rest) = buffer.split_at_mut(n);
             buffer = rest;
             self.process_first_segment(block, offset);
             offset += n;
         }
 
-        match offset % OTHER_SEGMENT_SIZE {
+        match offset % self.segment_size {
             0 => {} // we are already in the boundary, nothing to do.
             excess => {
-                let n = min(OTHER_SEGMENT_SIZE - excess, buffer.len());
+                let n = min(self.segment_size - excess, buffer.len());
                 let (block, rest) = buffer.split_at_mut(n);
                 buffer = rest;
                 self.process_other_segment(block, offset);
@@ -80,51 +518,1109 @@ impl QMC2RC4 {
         };
 
         while !buffer.is_empty() {
-            let n = min(OTHER_SEGMENT_SIZE, buffer.len());
+            let n = min(self.segment_size, buffer.len());
             let (block, rest) = buffer.split_at_mut(n);
             buffer = rest;
             self.process_other_segment(block, offset);
             offset += n;
         }
     }
+
+    /// Decrypt `bufs` as if they were one contiguous buffer starting at `start_offset`.
+    pub fn decrypt_vectored(&self, bufs: &mut [IoSliceMut<'_>], start_offset: usize) {
+        let mut offset = start_offset;
+        for buf in bufs.iter_mut() {
+            self.decrypt(&mut buf[..], offset);
+            offset += buf.len();
+        }
+    }
+
+    /// Decrypt a copy of each of `segments`, read as one contiguous stream starting at
+    /// `start_offset`; the read-only counterpart of [`QMC2RC4::decrypt_vectored`].
+    pub fn decrypt_scattered(&self, segments: &[&[u8]], start_offset: usize) -> Vec<Vec<u8>> {
+        let mut offset = start_offset;
+        segments
+            .iter()
+            .map(|segment| {
+                let mut buffer = segment.to_vec();
+                self.decrypt(&mut buffer, offset);
+                offset += buffer.len();
+                buffer
+            })
+            .collect()
+    }
+
+    /// Decrypt a copy of each of the `ranges` of the whole encrypted file `data`.
+    ///
+    /// Ranges may overlap and come in any order. Only the requested bytes are decrypted.
+    ///
+    /// # Panics
+    ///
+    /// If a range is out of bounds of `data`.
+    pub fn decrypt_ranges(&self, data: &[u8], ranges: &[Range<usize>]) -> Vec<Vec<u8>> {
+        ranges
+            .iter()
+            .map(|range| {
+                let mut window = data[range.clone()].to_vec();
+                self.decrypt(&mut window, range.start);
+                window
+            })
+            .collect()
+    }
+
+    /// Lazily decrypt `data`, located at `offset` of the file, one byte at a time.
+    ///
+    /// Meant for composing with other iterators; [`QMC2RC4::decrypt`] is much faster.
+    pub fn decrypt_iter<'a>(
+        &'a self,
+        data: &'a [u8],
+        offset: usize,
+    ) -> impl Iterator<Item = u8> + 'a {
+        data.iter().zip(offset..).map(move |(&datum, offset)| {
+            let mut byte = [datum];
+            self.decrypt(&mut byte, offset);
+            byte[0]
+        })
+    }
+
+    /// Decrypt `data`, calling `on_segment(bytes_done, total_bytes)` after each segment.
+    pub fn decrypt_with_progress<F>(&self, data: &mut [u8], offset: usize, mut on_segment: F)
+    where
+        F: FnMut(usize, usize),
+    {
+        let total = data.len();
+        for segment in self.layout(offset, total) {
+            let range = segment.range.start - offset..segment.range.end - offset;
+            let bytes_done = range.end;
+            self.decrypt(&mut data[range], segment.range.start);
+            on_segment(bytes_done, total);
+        }
+    }
+
+    /// Decrypt `data` in place, and return the peak of each `window` bytes of the
+    /// decrypted data, e.g. to draw a rough waveform without decoding the audio.
+    ///
+    /// The bytes are taken as signed 8-bit samples, a peak is the largest magnitude
+    /// (`0..=128`) in its window. This is only a coarse visual aid: for compressed
+    /// formats, it doesn't follow the actual audio.
+    ///
+    /// # Panics
+    ///
+    /// If `window` is 0.
+    pub fn decrypt_with_peaks(&self, data: &mut [u8], offset: usize, window: usize) -> Vec<u8> {
+        assert_ne!(window, 0, "window should not be 0");
+        self.decrypt(data, offset);
+        data.chunks(window)
+            .map(|chunk| {
+                chunk
+                    .iter()
+                    .map(|&b| (b as i8).unsigned_abs())
+                    .max()
+                    .unwrap_or(0)
+            })
+            .collect()
+    }
+
+    /// Decrypt `head`, the start of the file, up to the end of the first segment and
+    /// the one segment after it; where metadata (ID3, FLAC tags) usually lives.
+    ///
+    /// Same as `decrypt(head, 0)`, meant for reading tags without decrypting the whole
+    /// file. `head` should not be longer than
+    /// [`first_segment_size`](Self::first_segment_size) +
+    /// [`segment_size`](Self::segment_size), checked by a debug assertion.
+    pub fn decrypt_first_segment_only(&self, head: &mut [u8]) {
+        debug_assert!(
+            head.len() <= self.first_segment_size + self.segment_size,
+            "head is larger than the first two segments"
+        );
+        self.decrypt(head, 0);
+    }
+
+    /// Cheap check that this key is probably right for a file.
+    ///
+    /// Decrypts up to [`PROBE_SIZE`] bytes of `file_head` (the start of the file)
+    /// and checks the result for a known audio magic.
+    pub fn probe_key(&self, file_head: &[u8]) -> bool {
+        let mut buffer = file_head[..min(file_head.len(), PROBE_SIZE)].to_vec();
+        self.decrypt(&mut buffer, 0);
+        is_known_audio(&buffer)
+    }
+
+    /// Decrypt the whole file `data` in place, if this key looks right for it.
+    ///
+    /// The first [`PROBE_SIZE`] bytes are decrypted and checked as in
+    /// [`QMC2RC4::probe_key`]. On failure, they are encrypted back, so `data` is
+    /// left as it was, without having to keep a copy around.
+    pub fn try_decrypt_or_restore(&self, data: &mut [u8]) -> Result<(), QmcCryptoError> {
+        let (head, rest) = data.split_at_mut(min(data.len(), PROBE_SIZE));
+        self.decrypt(head, 0);
+        if !is_known_audio(head) {
+            // The cipher is symmetric.
+            self.decrypt(head, 0);
+            Err(QmcCryptoError::UnknownAudio)?;
+        }
+
+        self.decrypt(rest, head.len());
+        Ok(())
+    }
+
+    /// Decrypt `data` with its segments processed in parallel on the global rayon pool.
+    ///
+    /// Produces the same output as [`QMC2RC4::decrypt`].
+    #[cfg(feature = "rayon")]
+    pub fn decrypt_par(&self, data: &mut [u8], offset: usize) {
+        use rayon::prelude::*;
+
+        let mut blocks = Vec::new();
+        let mut rest = data;
+        for segment in self.layout(offset, rest.len()) {
+            let (block, tail) = rest.split_at_mut(segment.range.len());
+            blocks.push((block, segment.range.start));
+            rest = tail;
+        }
+
+        blocks
+            .into_par_iter()
+            .for_each(|(block, offset)| self.decrypt(block, offset));
+    }
+
+    /// Same as [`QMC2RC4::decrypt_par`], but runs inside the given `pool`
+    /// instead of the global one.
+    #[cfg(feature = "rayon")]
+    pub fn decrypt_par_in(&self, data: &mut [u8], offset: usize, pool: &rayon::ThreadPool) {
+        pool.install(|| self.decrypt_par(data, offset));
+    }
+
+    /// Key stream XOR-ed into the first `segments` segments of the file (of
+    /// [`QMC2RC4::segment_size`] bytes each), the first segment keys included.
+    ///
+    /// Only depends on the key, so it can be compared across platforms as a golden
+    /// file.
+    ///
+    /// # Panics
+    ///
+    /// If the key stream length, `segments` times the segment size, overflows `usize`.
+    pub fn effective_keystream(&self, segments: usize) -> Vec<u8> {
+        let len = segments
+            .checked_mul(self.segment_size)
+            .expect("key stream length overflow");
+        let mut key_stream = vec![0u8; len];
+        self.decrypt(&mut key_stream, 0);
+        key_stream
+    }
+
+    /// Describe how `len` bytes starting at `offset` are split into segments,
+    /// including the key stream skip of each other segment.
+    pub fn segment_layout(&self, offset: usize, len: usize) -> Vec<SegmentInfo> {
+        let mut layout = self.layout(offset, len);
+        for segment in layout.iter_mut() {
+            if segment.kind == SegmentKind::Other {
+                segment.skip = Some(self.segment_skip(segment.id));
+            }
+        }
+        layout
+    }
+
+    /// Decrypt `len` bytes from `src` and discard the result.
+    ///
+    /// Only a single segment sized scratch buffer is used, regardless of `len`.
+    /// Fails if `src` could not provide `len` bytes.
+    pub fn validate_decrypt<R: Read>(&self, src: &mut R, len: u64) -> Result<(), QmcCryptoError> {
+        let mut buffer = [0u8; OTHER_SEGMENT_SIZE];
+        let mut offset = 0usize;
+        let mut remaining = len;
+
+        while remaining > 0 {
+            let n = min(remaining, buffer.len() as u64) as usize;
+            let block = &mut buffer[..n];
+            src.read_exact(block).map_err(QmcCryptoError::IOError)?;
+            self.decrypt(block, offset);
+            offset += n;
+            remaining -= n as u64;
+        }
+
+        Ok(())
+    }
+
+    /// Read and decrypt at most `max_bytes` from `src`, whose first byte is located
+    /// at `offset` of the file.
+    ///
+    /// Stops at `max_bytes` or at the end of `src`, whichever comes first; the output
+    /// never grows past `max_bytes`.
+    pub fn decrypt_budgeted<R: Read>(
+        &self,
+        src: &mut R,
+        max_bytes: usize,
+        offset: usize,
+    ) -> Result<Vec<u8>, QmcCryptoError> {
+        let mut buffer = Vec::with_capacity(min(max_bytes, OTHER_SEGMENT_SIZE));
+        src.take(max_bytes as u64)
+            .read_to_end(&mut buffer)
+            .map_err(QmcCryptoError::IOError)?;
+        self.decrypt(&mut buffer, offset);
+        Ok(buffer)
+    }
+}
+
+/// Check if `buffer`, the decrypted start of a file, looks like known audio.
+pub(crate) fn is_known_audio(buffer: &[u8]) -> bool {
+    match um_audio::detect_audio_type(buffer) {
+        Ok(AudioType::Unknown) => false,
+        Ok(_) => true,
+        // A tag header was recognised, but the tag is larger than the probe. Any
+        // buffer shorter than a magic needs more header too, whatever it holds.
+        Err(AudioError::NeedMoreHeader(_)) => {
+            buffer.len() >= 10 && (buffer.starts_with(b"ID3") || buffer.starts_with(b"APETAGEX"))
+        }
+    }
+}
+
+impl QmcCipher for QMC2RC4 {
+    fn decrypt(&self, data: &mut [u8], offset: usize) {
+        debug_assert!(
+            offset.checked_add(data.len()).is_some(),
+            "offset overflow: {:#x} bytes at offset {offset:#x}",
+            data.len()
+        );
+
+        match offset {
+            0 => self.decrypt_from_start(data),
+            _ => self.decrypt_generic(data, offset),
+        }
+    }
 }
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use super::*;
+    use crate::self_test::{header_test_key, TEST_QMC2_HEADER};
+    use crate::test::generate_key;
+    use crate::v2_rc4::segment_key::get_segment_key;
+    use crate::QMCv2Cipher;
+    use std::io::Cursor;
 
     #[test]
     fn test_qmc2_header() {
-        let mut data = [
-            0x39, 0x5a, 0x4f, 0x75, 0x38, 0x71, 0x37, 0x6b, 0x36, 0x51, 0x53, 0x6d, 0x7a, 0x66,
-            0x53, 0x4b, 0x66, 0x50, 0x69, 0x34, 0x67, 0x6c, 0x33, 0x7a, 0x55, 0x62, 0x35, 0x5a,
-            0x32, 0x75, 0x4f, 0x68, 0x44, 0x52, 0x6d, 0x65, 0x75, 0x6e, 0x39, 0x52, 0x30, 0x7a,
-            0x68, 0x62, 0x73, 0x59, 0x39, 0x48, 0x55, 0x57, 0x73, 0x32, 0x5a, 0x70, 0x64, 0x50,
-            0x4e, 0x52, 0x6a, 0x63, 0x4d, 0x39, 0x37, 0x76, 0x72, 0x47, 0x64, 0x4d, 0x62, 0x6d,
-            0x58, 0x68, 0x75, 0x47, 0x37, 0x56, 0x69, 0x6b, 0x4a, 0x79, 0x66, 0x63, 0x70, 0x39,
-            0x59, 0x34, 0x43, 0x6b, 0x45, 0x32, 0x5a, 0x31, 0x38, 0x77, 0x70, 0x43, 0x51, 0x79,
-            0x6a, 0x62, 0x32, 0x33, 0x65, 0x58, 0x4a, 0x4d, 0x33, 0x4e, 0x70, 0x62, 0x62, 0x67,
-            0x4c, 0x54, 0x78, 0x64, 0x64, 0x77, 0x6e, 0x72, 0x37, 0x41, 0x54, 0x39, 0x42, 0x52,
-            0x47, 0x32, 0x1a, 0xe4, 0x1b, 0x71, 0x68, 0x29, 0xb3, 0x6e, 0xad, 0xc5, 0x28, 0x12,
-            0xd6, 0xa4, 0x4b, 0x06, 0x7a, 0xdc, 0x90, 0x15, 0x99, 0xd6, 0xbf, 0x72, 0xa2, 0x30,
-            0x37, 0x6b, 0x5c, 0xd6, 0x2f, 0x35, 0x14, 0x8a, 0xd6, 0xfb, 0x9f, 0xee, 0x7d, 0x2d,
-            0xb7, 0x37, 0xf2, 0x0b, 0x6e, 0x00, 0xfb, 0xa0, 0x3c, 0x40, 0xf3, 0x36, 0xb2, 0x76,
-            0x20, 0x0f, 0x9e, 0xa5, 0xa3, 0x15, 0x60, 0x23, 0x15, 0x29, 0xa1, 0x91, 0xbf, 0xfb,
-            0x12, 0x95, 0xaa, 0x8d, 0x92, 0xc6, 0x0b, 0x8d, 0x49, 0x99, 0xa5, 0xe0, 0x05, 0xcf,
-            0xb6, 0xac, 0x07, 0x54, 0x58, 0x28, 0xf9, 0x96, 0xd1, 0x9a, 0xfe, 0x0b, 0x3c, 0xfb,
-            0x0b, 0x25, 0x7a, 0x43, 0x5a, 0x33, 0xc3, 0x7a, 0xfc, 0x33, 0xa3, 0xc2, 0x65, 0x48,
-            0x29, 0x8d, 0x2c, 0x8f, 0x4e, 0x88, 0xfd, 0x44, 0xfd, 0xd5, 0xca, 0xb9, 0x8d, 0x62,
-            0x4a, 0x48, 0x20, 0x1du8,
-        ];
-        let key = (b'a'..=b'z')
-            .chain(b'A'..=b'Z')
-            .chain(b'0'..=b'9')
-            .cycle()
-            .take(512)
-            .collect::<Vec<u8>>();
+        let mut data = TEST_QMC2_HEADER;
+        let key = header_test_key();
 
         let cipher = QMC2RC4::new(&key);
         cipher.decrypt(&mut data, 0);
         assert_eq!(data, [0u8; 256]);
     }
+
+    #[test]
+    fn test_effective_keystream() {
+        let cipher = QMC2RC4::new(&header_test_key());
+        let key_stream = cipher.effective_keystream(4);
+        assert_eq!(key_stream.len(), 4 * OTHER_SEGMENT_SIZE);
+        assert_eq!(key_stream[..256], TEST_QMC2_HEADER);
+
+        for (id, segment) in key_stream.chunks(OTHER_SEGMENT_SIZE).enumerate().skip(1) {
+            let skip = cipher.segment_skip(id);
+            assert_eq!(segment, &cipher.key_stream[skip..skip + OTHER_SEGMENT_SIZE]);
+        }
+
+        // Golden value: catches any platform dependent change to the key stream.
+        assert_eq!(
+            umc_utils::md5(&key_stream),
+            [
+                0x06, 0x23, 0xf8, 0xd7, 0x4d, 0xa9, 0x9c, 0x27, 0x6c, 0x5f, 0x99, 0x3c, 0xc1, 0xa6,
+                0x17, 0x3a
+            ]
+        );
+    }
+
+    #[test]
+    fn test_qmc2_header_boxed() {
+        let mut data = TEST_QMC2_HEADER;
+        let cipher = QMC2RC4::new_boxed(&header_test_key());
+
+        let (head, tail) = data.split_at_mut(0x50);
+        cipher.decrypt(head, 0);
+        cipher.decrypt(tail, 0x50);
+        assert_eq!(data, [0u8; 256]);
+    }
+
+    #[test]
+    fn test_validate_decrypt() {
+        let cipher = QMC2RC4::new(&generate_key(512));
+        let data = vec![0xA5u8; OTHER_SEGMENT_SIZE * 3 + 123];
+
+        let mut src = Cursor::new(&data);
+        let result = cipher.validate_decrypt(&mut src, data.len() as u64);
+        assert!(result.is_ok());
+        assert_eq!(src.position(), data.len() as u64);
+
+        let mut src = Cursor::new(&data);
+        let result = cipher.validate_decrypt(&mut src, data.len() as u64 + 1);
+        assert!(matches!(result, Err(QmcCryptoError::IOError(_))));
+    }
+
+    #[test]
+    fn test_decrypt_budgeted() {
+        let cipher = QMC2RC4::new(&generate_key(512));
+        let data = (0..0x3000).map(|i| (i * 3) as u8).collect::<Vec<_>>();
+        let mut expected = data.clone();
+        cipher.decrypt(&mut expected, 0);
+
+        let mut src = Cursor::new(&data);
+        let preview = cipher.decrypt_budgeted(&mut src, 0x1500, 0).unwrap();
+        assert_eq!(preview, expected[..0x1500]);
+        assert_eq!(src.position(), 0x1500);
+
+        let rest = cipher.decrypt_budgeted(&mut src, 0x10000, 0x1500).unwrap();
+        assert_eq!(rest, expected[0x1500..]);
+    }
+
+    #[test]
+    fn test_from_ekey() {
+        let ekey = include_str!("fixtures/ekey_rc4_512.txt");
+        let cipher = QMC2RC4::from_ekey(ekey).expect("should create cipher from ekey");
+        assert_eq!(cipher, QMC2RC4::new(&header_test_key()));
+
+        let mut data = TEST_QMC2_HEADER;
+        cipher.decrypt(&mut data, 0);
+        assert_eq!(data, [0u8; 256]);
+    }
+
+    #[test]
+    fn test_try_new_empty_key() {
+        assert!(matches!(
+            QMC2RC4::try_new(&[]),
+            Err(QmcCryptoError::QMCV2RC4KeyEmpty)
+        ));
+        assert!(QMC2RC4::from_ekey("").is_err());
+        assert!(matches!(
+            QMC2RC4::from_ekey("not base64!"),
+            Err(QmcCryptoError::EKeyDecodeError(_))
+        ));
+    }
+
+    #[test]
+    fn test_try_from_key_slice() -> Result<(), QmcCryptoError> {
+        let key = header_test_key();
+        let cipher: QMC2RC4 = key.as_slice().try_into()?;
+        let mut data = TEST_QMC2_HEADER;
+        cipher.decrypt(&mut data, 0);
+        assert_eq!(data, [0u8; 256]);
+
+        let empty: &[u8] = &[];
+        assert!(matches!(
+            QMC2RC4::try_from(empty),
+            Err(QmcCryptoError::QMCV2RC4KeyEmpty)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_decrypt_at_first_segment_boundary() {
+        let data = (0..0x3000).map(|i| (i * 7) as u8).collect::<Vec<_>>();
+
+        for key_len in [301, 512, 1000, 2048] {
+            let cipher = QMC2RC4::new(&generate_key(key_len));
+            let mut expected = data.clone();
+            cipher.decrypt(&mut expected, 0);
+
+            let mut actual = data[FIRST_SEGMENT_SIZE..].to_vec();
+            cipher.decrypt(&mut actual, FIRST_SEGMENT_SIZE);
+            assert_eq!(actual, &expected[FIRST_SEGMENT_SIZE..], "key_len={key_len}");
+
+            let mut actual = data[FIRST_SEGMENT_SIZE..FIRST_SEGMENT_SIZE + 0x10].to_vec();
+            cipher.decrypt(&mut actual, FIRST_SEGMENT_SIZE);
+            let expected = &expected[FIRST_SEGMENT_SIZE..FIRST_SEGMENT_SIZE + 0x10];
+            assert_eq!(actual, expected, "key_len={key_len}");
+        }
+    }
+
+    #[test]
+    fn test_decrypt_with_progress() {
+        let cipher = QMC2RC4::new(&generate_key(512));
+        let data = (0..0x2810).map(|i| i as u8).collect::<Vec<_>>();
+
+        let mut expected = data.clone();
+        cipher.decrypt(&mut expected, 0);
+
+        let mut actual = data.clone();
+        let mut progress = vec![];
+        cipher.decrypt_with_progress(&mut actual, 0, |done, total| progress.push((done, total)));
+
+        assert_eq!(actual, expected);
+        assert_eq!(
+            progress,
+            [
+                (0x80, 0x2810),
+                (0x1400, 0x2810),
+                (0x2800, 0x2810),
+                (0x2810, 0x2810)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_decrypt_from_start_matches_generic_path() {
+        let cipher = QMC2RC4::new(&generate_key(512));
+        let data = (0..OTHER_SEGMENT_SIZE * 10 + 0x123)
+            .map(|i| (i * 7) as u8)
+            .collect::<Vec<_>>();
+
+        let lengths = [
+            0,
+            1,
+            FIRST_SEGMENT_SIZE,
+            FIRST_SEGMENT_SIZE + 1,
+            OTHER_SEGMENT_SIZE,
+            OTHER_SEGMENT_SIZE + 1,
+            OTHER_SEGMENT_SIZE * 3,
+            data.len(),
+        ];
+        for len in lengths {
+            let mut fast = data[..len].to_vec();
+            cipher.decrypt_from_start(&mut fast);
+            let mut generic = data[..len].to_vec();
+            cipher.decrypt_generic(&mut generic, 0);
+            assert_eq!(fast, generic, "len = {len:#x}");
+        }
+    }
+
+    #[test]
+    fn test_probe_key() {
+        // The header vector decrypts to zeros, so prefix it with a FLAC stream header.
+        let cipher = QMC2RC4::new(&header_test_key());
+        let mut file_head = b"fLaC\x00\x00\x00\x22".to_vec();
+        file_head.extend_from_slice(&[0u8; 0x100]);
+        cipher.decrypt(&mut file_head, 0);
+
+        assert!(cipher.probe_key(&file_head));
+        assert!(!QMC2RC4::new(&generate_key(512)).probe_key(&file_head));
+
+        // Same for the header vector: patch it so that it decrypts to "fLaC" + zeros.
+        let mut header = TEST_QMC2_HEADER;
+        header[..4].copy_from_slice(&file_head[..4]);
+        assert!(cipher.probe_key(&header));
+        assert!(!QMC2RC4::new(&generate_key(512)).probe_key(&header));
+    }
+
+    #[test]
+    fn test_try_decrypt_or_restore() {
+        let cipher = QMC2RC4::new(&header_test_key());
+        let mut plaintext = b"fLaC\x00\x00\x00\x22".to_vec();
+        plaintext.extend((0..0x3000).map(|i| i as u8));
+        let mut encrypted = plaintext.clone();
+        cipher.decrypt(&mut encrypted, 0);
+
+        let mut data = encrypted.clone();
+        let result = QMC2RC4::new(&generate_key(512)).try_decrypt_or_restore(&mut data);
+        assert!(matches!(result, Err(QmcCryptoError::UnknownAudio)));
+        assert_eq!(data, encrypted);
+
+        cipher.try_decrypt_or_restore(&mut data).unwrap();
+        assert_eq!(data, plaintext);
+    }
+
+    #[test]
+    #[should_panic(expected = "key stream length overflow")]
+    fn test_effective_keystream_overflow() {
+        QMC2RC4::new(&generate_key(512)).effective_keystream(usize::MAX / 2);
+    }
+
+    #[test]
+    fn test_debug_hides_key() {
+        let key = b"0123456789abcdef-secret-key".repeat(8);
+        let debug = format!("{:?}", QMC2RC4::new(&key));
+        let fingerprint = crate::key_fingerprint_hex(&key);
+        assert!(debug.contains(&format!("key_fingerprint: \"{fingerprint}\"")));
+        assert!(debug.contains(&format!("key_len: {}", key.len())));
+        let raw_key = format!("{:?}", &key[..4]);
+        assert!(!debug.contains(raw_key.trim_end_matches(']')), "{debug}");
+    }
+
+    #[test]
+    fn test_probe_key_short_head() {
+        // Too short for any magic: not a hit, whatever the key.
+        let cipher = QMC2RC4::new(&header_test_key());
+        let mut encrypted = b"fLaC\x00\x00\x00\x22".to_vec();
+        cipher.decrypt(&mut encrypted, 0);
+
+        let wrong_cipher = QMC2RC4::new(&generate_key(512));
+        assert!(!wrong_cipher.probe_key(&encrypted));
+        let mut data = encrypted.clone();
+        let result = wrong_cipher.try_decrypt_or_restore(&mut data);
+        assert!(matches!(result, Err(QmcCryptoError::UnknownAudio)));
+        assert_eq!(data, encrypted);
+
+        // A tag larger than the head is still a hit.
+        let mut file_head = b"ID3\x04\x00\x00\x00\x01\x00\x00".to_vec();
+        file_head.extend_from_slice(&[0u8; 0x20]);
+        cipher.decrypt(&mut file_head, 0);
+        assert!(cipher.probe_key(&file_head));
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn test_decrypt_par_in_matches_serial() {
+        let cipher = QMC2RC4::new(&generate_key(512));
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(2)
+            .build()
+            .expect("failed to build thread pool");
+        let data = (0..OTHER_SEGMENT_SIZE * 8 + 0x321)
+            .map(|i| (i * 5) as u8)
+            .collect::<Vec<_>>();
+
+        for offset in [0, 0x7f, 0x80, 0x1400, 0x2345] {
+            let mut expected = data.clone();
+            cipher.decrypt(&mut expected, offset);
+
+            let mut actual = data.clone();
+            cipher.decrypt_par_in(&mut actual, offset, &pool);
+            assert_eq!(actual, expected, "offset = {offset:#x}");
+
+            let mut actual = data.clone();
+            cipher.decrypt_par(&mut actual, offset);
+            assert_eq!(actual, expected, "offset = {offset:#x}");
+        }
+    }
+
+    #[test]
+    fn test_with_segment_size() {
+        let cipher = QMC2RC4::new(&generate_key(512));
+        let resized = cipher.with_segment_size(0x800);
+        assert_eq!(resized.segment_size(), 0x800);
+        assert!(Arc::ptr_eq(&cipher.key_stream, &resized.key_stream));
+
+        let data = (0..0x3456).map(|i| (i * 11) as u8).collect::<Vec<_>>();
+        let mut expected = data.clone();
+        resized.decrypt(&mut expected, 0);
+        let mut default = data.clone();
+        cipher.decrypt(&mut default, 0);
+        assert_eq!(expected[..0x800], default[..0x800]);
+        assert_ne!(expected[0x800..], default[0x800..]);
+
+        let mut chunked = data.clone();
+        let mut offset = 0;
+        for chunk in chunked.chunks_mut(0x3ff) {
+            resized.decrypt(chunk, offset);
+            offset += chunk.len();
+        }
+        assert_eq!(chunked, expected);
+
+        let mut generic = data.clone();
+        resized.decrypt_generic(&mut generic, 0);
+        assert_eq!(generic, expected);
+    }
+
+    #[test]
+    fn test_full_byte_range_keys() {
+        let keys = [
+            // Every byte value, including 0x00 and 0x80..=0xFF.
+            (0..=255u8).cycle().take(512).collect::<Vec<_>>(),
+            // Mostly null bytes, in the positions used as segment seeds.
+            (0..1000usize)
+                .map(|i| if i % 3 == 0 { 0 } else { 0x80 | i as u8 })
+                .collect(),
+            // High bytes only.
+            (0..400usize).map(|i| 0xFF - (i % 0x80) as u8).collect(),
+        ];
+        let data = (0..OTHER_SEGMENT_SIZE * 4 + 0x55)
+            .map(|i| (i * 13) as u8)
+            .collect::<Vec<_>>();
+
+        for key in keys {
+            let cipher = QMC2RC4::new(&key);
+
+            let mut encrypted = data.clone();
+            cipher.decrypt(&mut encrypted, 0);
+            assert_ne!(encrypted, data);
+
+            let mut chunked = data.clone();
+            for (i, chunk) in chunked.chunks_mut(0x333).enumerate() {
+                cipher.decrypt(chunk, i * 0x333);
+            }
+            assert_eq!(chunked, encrypted);
+
+            let mut round_trip = encrypted;
+            cipher.decrypt(&mut round_trip, 0);
+            assert_eq!(round_trip, data);
+        }
+    }
+
+    #[test]
+    fn test_decrypt_at_integer_offsets() {
+        let cipher = QMC2RC4::new(&generate_key(512));
+        let data = (0..0x2000).map(|i| i as u8).collect::<Vec<_>>();
+
+        let mut expected = data.clone();
+        cipher.decrypt(&mut expected, 0x1234);
+
+        let mut actual = data.clone();
+        cipher.decrypt_at(&mut actual, 0x1234u32);
+        assert_eq!(actual, expected);
+
+        let mut actual = data.clone();
+        cipher.decrypt_at(&mut actual, 0x1234u64);
+        assert_eq!(actual, expected);
+
+        let mut actual = data.clone();
+        cipher.decrypt_at(&mut actual, 0x12u8);
+        let mut expected = data;
+        cipher.decrypt(&mut expected, 0x12);
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn test_custom_segment_key_fn() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let key = generate_key(512);
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+        let cipher = QMC2RC4::builder(&key)
+            .segment_key_fn(move |_id: u64, _seed: u8, _hash: f64| {
+                counter.fetch_add(1, Ordering::Relaxed);
+                0
+            })
+            .build();
+
+        let mut data = vec![0u8; 0x1500];
+        cipher.decrypt(&mut data, 0);
+        // One call per first segment byte, and one per other segment (2 segments).
+        assert_eq!(calls.load(Ordering::Relaxed), FIRST_SEGMENT_SIZE + 2);
+
+        // Segment key 0: every first segment byte uses key[0], and there is no skip.
+        assert!(data[..FIRST_SEGMENT_SIZE].iter().all(|&b| b == key[0]));
+        assert_eq!(
+            data[FIRST_SEGMENT_SIZE..0x1400],
+            cipher.key_stream[FIRST_SEGMENT_SIZE..0x1400]
+        );
+        assert_eq!(data[0x1400..], cipher.key_stream[..0x100]);
+
+        assert_ne!(cipher, QMC2RC4::new(&key));
+        assert_eq!(QMC2RC4::builder(&key).build(), QMC2RC4::new(&key));
+    }
+
+    #[test]
+    fn test_equivalent_to() {
+        let a = QMC2RC4::new(&generate_key(512));
+        let b = QMC2RC4::new(&generate_key(512));
+        let other_key = QMC2RC4::new(&generate_key(768));
+        let other_segment_size = QMC2RC4::builder(&generate_key(512))
+            .segment_size(0x1000)
+            .build();
+
+        for (x, y) in [(&a, &b), (&a, &other_key), (&a, &other_segment_size)] {
+            assert_eq!(x.equivalent_to(y), x == y);
+        }
+        assert!(a.equivalent_to(&b));
+        assert!(!a.equivalent_to(&other_key));
+        assert!(!a.equivalent_to(&other_segment_size));
+    }
+
+    #[test]
+    fn test_decrypt_into_and_within() {
+        let cipher = QMC2RC4::new(&generate_key(512));
+        let data = (0..0x2000).map(|i| (i * 11) as u8).collect::<Vec<_>>();
+        let mut expected = data.clone();
+        cipher.decrypt(&mut expected, 0);
+
+        let mut dst = vec![0u8; data.len()];
+        cipher.decrypt_into(&data, &mut dst, 0);
+        assert_eq!(dst, expected);
+
+        // Shift the data by 0x10 bytes, the ranges overlap.
+        let mut buffer = vec![0u8; 0x10];
+        buffer.extend_from_slice(&data);
+        cipher.decrypt_within(&mut buffer, 0x10..0x10 + data.len(), 0, 0);
+        assert_eq!(&buffer[..data.len()], expected);
+    }
+
+    #[test]
+    #[should_panic(expected = "src and dst lengths differ")]
+    fn test_decrypt_into_length_mismatch() {
+        let cipher = QMC2RC4::new(&generate_key(512));
+        cipher.decrypt_into(&[0u8; 0x10], &mut [0u8; 0x20], 0);
+    }
+
+    #[test]
+    fn test_decrypt_modes_match() {
+        let data = (0..0x200).map(|i| (i * 13) as u8).collect::<Vec<_>>();
+        for first_segment_size in [FIRST_SEGMENT_SIZE, 0x100] {
+            let build = |decrypt_mode| {
+                QMC2RC4::builder(&generate_key(512))
+                    .first_segment_size(first_segment_size)
+                    .decrypt_mode(decrypt_mode)
+                    .build()
+            };
+            let table = build(DecryptMode::WholeFile);
+            let live = build(DecryptMode::HeaderOnly);
+            assert_eq!(table.decrypt_mode(), DecryptMode::WholeFile);
+            assert_eq!(live.decrypt_mode(), DecryptMode::HeaderOnly);
+
+            for offset in [0, 1, 0x7f] {
+                let mut expected = data.clone();
+                let mut actual = data.clone();
+                table.decrypt(&mut expected, offset);
+                live.decrypt(&mut actual, offset);
+                assert_eq!(actual, expected, "offset={offset:#x}");
+            }
+        }
+        assert_eq!(
+            QMC2RC4::new(&generate_key(512)).decrypt_mode(),
+            DecryptMode::WholeFile
+        );
+    }
+
+    #[test]
+    fn test_decrypt_shared() {
+        let cipher = QMC2RC4::new(&generate_key(512));
+        let data = (0..0x3000).map(|i| (i * 3) as u8).collect::<Vec<_>>();
+        let shared: Arc<[u8]> = data.clone().into();
+
+        let decrypted = cipher.decrypt_shared(shared.clone(), 0x10);
+        let mut expected = data.clone();
+        cipher.decrypt(&mut expected, 0x10);
+        assert_eq!(*decrypted, *expected);
+        assert_eq!(*shared, *data);
+        assert!(!Arc::ptr_eq(&decrypted, &shared));
+    }
+
+    #[test]
+    fn test_decrypt_advancing() {
+        let cipher = QMC2RC4::new(&generate_key(512));
+        let data = (0..0x3000).map(|i| (i * 5) as u8).collect::<Vec<_>>();
+        let mut expected = data.clone();
+        cipher.decrypt(&mut expected, 0);
+
+        let mut actual = data.clone();
+        let (first, rest) = actual.split_at_mut(0x7f);
+        let (second, third) = rest.split_at_mut(0x1500);
+        let mut offset = 0;
+        cipher.decrypt_advancing(first, &mut offset);
+        cipher.decrypt_advancing(second, &mut offset);
+        cipher.decrypt_advancing(third, &mut offset);
+        assert_eq!(offset, data.len());
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn test_decrypt_with_peaks() {
+        let cipher = QMC2RC4::new(&generate_key(512));
+        let data = (0..0x3000 + 5).map(|i| (i * 7) as u8).collect::<Vec<_>>();
+        let mut expected = data.clone();
+        cipher.decrypt(&mut expected, 0x10);
+
+        let mut actual = data.clone();
+        let peaks = cipher.decrypt_with_peaks(&mut actual, 0x10, 0x100);
+        assert_eq!(actual, expected);
+        assert_eq!(peaks.len(), actual.len().div_ceil(0x100));
+        assert!(peaks.iter().all(|&peak| peak <= 128));
+
+        let window_peak = |chunk: &[u8]| chunk.iter().map(|&b| (b as i8).unsigned_abs()).max();
+        assert_eq!(peaks[0], window_peak(&expected[..0x100]).unwrap());
+        assert_eq!(
+            *peaks.last().unwrap(),
+            window_peak(&expected[0x3000..]).unwrap()
+        );
+
+        let mut again = data.clone();
+        assert_eq!(cipher.decrypt_with_peaks(&mut again, 0x10, 0x100), peaks);
+        assert!(cipher.decrypt_with_peaks(&mut [], 0, 0x100).is_empty());
+    }
+
+    #[test]
+    fn test_decrypt_first_segment_only() {
+        let cipher = QMC2RC4::new(&generate_key(512));
+        let data = (0..0x3000).map(|i| (i * 7) as u8).collect::<Vec<_>>();
+        let mut expected = data.clone();
+        cipher.decrypt(&mut expected, 0);
+
+        let head_len = FIRST_SEGMENT_SIZE + OTHER_SEGMENT_SIZE;
+        let mut head = data[..head_len].to_vec();
+        cipher.decrypt_first_segment_only(&mut head);
+        assert_eq!(head, expected[..head_len]);
+    }
+
+    #[test]
+    fn test_decrypt_vectored() {
+        let cipher = QMC2RC4::new(&generate_key(512));
+        let data = (0..0x3000).map(|i| (i * 3) as u8).collect::<Vec<_>>();
+
+        let mut expected = data.clone();
+        cipher.decrypt(&mut expected, 0x40);
+
+        let mut a = data[..0x30].to_vec();
+        let mut b = data[0x30..0x1500].to_vec();
+        let mut c = data[0x1500..].to_vec();
+        let mut bufs = [
+            IoSliceMut::new(&mut a),
+            IoSliceMut::new(&mut b),
+            IoSliceMut::new(&mut c),
+        ];
+        cipher.decrypt_vectored(&mut bufs, 0x40);
+
+        assert_eq!([a, b, c].concat(), expected);
+    }
+
+    #[test]
+    fn test_decrypt_scattered() {
+        let cipher = QMC2RC4::new(&generate_key(512));
+        let data = (0..0x3000).map(|i| (i * 5) as u8).collect::<Vec<_>>();
+
+        let mut expected = data.clone();
+        cipher.decrypt(&mut expected, 0x40);
+
+        let segments = [&data[..0x30], &data[0x30..0x1500], &data[0x1500..]];
+        let decrypted = cipher.decrypt_scattered(&segments, 0x40);
+
+        assert_eq!(decrypted.len(), 3);
+        assert_eq!(decrypted[1].len(), 0x1500 - 0x30);
+        assert_eq!(decrypted.concat(), expected);
+    }
+
+    #[test]
+    fn test_try_decrypt_offset_overflow() {
+        let cipher = QMC2RC4::new(&generate_key(512));
+        let mut data = [0u8; 0x20];
+
+        let result = cipher.try_decrypt(&mut data, usize::MAX - 0x10);
+        assert!(matches!(
+            result,
+            Err(QmcCryptoError::OffsetOverflow { len: 0x20, .. })
+        ));
+        assert_eq!(data, [0u8; 0x20], "data should be left untouched");
+
+        // Ending exactly at `usize::MAX` is fine.
+        let mut expected = data;
+        cipher.decrypt(&mut expected, usize::MAX - 0x20);
+        cipher
+            .try_decrypt(&mut data, usize::MAX - 0x20)
+            .expect("should not overflow");
+        assert_eq!(data, expected);
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    #[should_panic(expected = "offset overflow")]
+    fn test_decrypt_offset_overflow_debug_assert() {
+        let cipher = QMC2RC4::new(&generate_key(512));
+        cipher.decrypt(&mut [0u8; 0x20], usize::MAX - 0x10);
+    }
+
+    #[test]
+    fn test_key_fingerprint() {
+        let key = generate_key(512);
+        let fingerprint = QMC2RC4::new(&key).key_fingerprint();
+
+        assert_eq!(QMC2RC4::new(&key).key_fingerprint(), fingerprint);
+        assert_eq!(
+            QMC2RC4::new(&key)
+                .with_segment_size(0x800)
+                .key_fingerprint(),
+            fingerprint
+        );
+        assert_ne!(
+            QMC2RC4::new(&generate_key(513)).key_fingerprint(),
+            fingerprint
+        );
+        assert_ne!(
+            QMC2RC4::new(&header_test_key()).key_fingerprint(),
+            fingerprint
+        );
+    }
+
+    #[test]
+    fn test_degenerate_keys() {
+        let data = (0..0x5000).map(|i| (i * 7) as u8).collect::<Vec<_>>();
+
+        // All-zero keys skip every byte of the hash, all-0xFF keys wrap it early.
+        for (key, expected_hash) in [([0x00u8; 512], 1.0), ([0xFFu8; 512], 4228250625.0)] {
+            let cipher = QMC2RC4::new(&key);
+            assert_eq!(cipher.hash, expected_hash);
+
+            let mut first = data.clone();
+            cipher.decrypt(&mut first, 0);
+            let mut second = data.clone();
+            QMC2RC4::new(&key).decrypt(&mut second, 0);
+            assert_eq!(first, second);
+
+            cipher.decrypt(&mut first, 0);
+            assert_eq!(first, data);
+        }
+    }
+
+    #[test]
+    fn test_large_keys() {
+        let data = (0..0x8000).map(|i| (i * 7) as u8).collect::<Vec<_>>();
+
+        for key_len in [1024, 2048] {
+            let key = generate_key(key_len);
+            let cipher = QMC2RC4::new(&key);
+            assert_eq!(
+                QMCv2Cipher::new(&key).unwrap(),
+                QMCv2Cipher::RC4(cipher.clone())
+            );
+
+            let mut expected = data.clone();
+            cipher.decrypt(&mut expected, 0);
+
+            let mut chunked = data.clone();
+            let mut offset = 0;
+            for chunk in chunked.chunks_mut(0x7FF) {
+                cipher.decrypt(chunk, offset);
+                offset += chunk.len();
+            }
+            assert_eq!(chunked, expected, "key_len={key_len}");
+
+            cipher.decrypt(&mut chunked, 0);
+            assert_eq!(chunked, data, "key_len={key_len}");
+        }
+    }
+
+    #[test]
+    fn test_decrypt_ranges() {
+        let cipher = QMC2RC4::new(&generate_key(512));
+        let data = (0..0x8000).map(|i| (i * 7) as u8).collect::<Vec<_>>();
+        let mut expected = data.clone();
+        cipher.decrypt(&mut expected, 0);
+
+        let ranges = [
+            0x7000..0x7100,
+            0..0x10,
+            0x7F..0x81,
+            0x13FF..0x2801,
+            0x20..0x20,
+        ];
+        let windows = cipher.decrypt_ranges(&data, &ranges);
+        assert_eq!(windows.len(), ranges.len());
+        for (window, range) in windows.iter().zip(ranges) {
+            assert_eq!(window, &expected[range.clone()], "range={range:?}");
+        }
+    }
+
+    #[test]
+    fn test_custom_first_segment_size() {
+        let key = generate_key(512);
+        let data = (0..0x5000).map(|i| (i * 7) as u8).collect::<Vec<_>>();
+
+        let default = QMC2RC4::builder(&key).build();
+        assert_eq!(default, QMC2RC4::new(&key));
+        assert_eq!(default.first_segment_size(), FIRST_SEGMENT_SIZE);
+
+        for first_segment_size in [0x40, 0x100, 0x1400] {
+            let cipher = QMC2RC4::builder(&key)
+                .first_segment_size(first_segment_size)
+                .build();
+            assert_ne!(cipher, default);
+
+            let mut expected = data.clone();
+            cipher.decrypt(&mut expected, 0);
+
+            // Per-byte keys up to the first segment size, then the key stream.
+            let mut generic = data.clone();
+            cipher.decrypt_generic(&mut generic, 0);
+            assert_eq!(generic, expected);
+            let mut first = data[..first_segment_size].to_vec();
+            default.process_first_segment(&mut first, 0);
+            assert_eq!(first, &expected[..first_segment_size]);
+
+            for chunk_size in [1, 0x7F, 0x3FF, 0x1401] {
+                let mut chunked = data.clone();
+                let mut offset = 0;
+                for chunk in chunked.chunks_mut(chunk_size) {
+                    cipher.decrypt(chunk, offset);
+                    offset += chunk.len();
+                }
+                assert_eq!(
+                    chunked, expected,
+                    "first_segment_size={first_segment_size:#x}, chunk_size={chunk_size:#x}"
+                );
+            }
+
+            let layout = cipher.segment_layout(0, data.len());
+            assert_eq!(layout[0].range, 0..first_segment_size);
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_first_segment_larger_than_segment() {
+        QMC2RC4::builder(&generate_key(512))
+            .first_segment_size(0x200)
+            .segment_size(0x100)
+            .build();
+    }
+
+    #[test]
+    fn test_decrypt_iter() {
+        let cipher = QMC2RC4::new(&generate_key(512));
+        let data = (0..0x3000).map(|i| (i * 7) as u8).collect::<Vec<_>>();
+        let mut expected = data.clone();
+        cipher.decrypt(&mut expected, 0);
+
+        let actual = cipher.decrypt_iter(&data, 0).collect::<Vec<_>>();
+        assert_eq!(actual, expected);
+
+        let offset = 0x7E;
+        let actual = cipher
+            .decrypt_iter(&data[offset..], offset)
+            .collect::<Vec<_>>();
+        assert_eq!(actual, &expected[offset..]);
+    }
+
+    #[test]
+    fn test_first_segment_sparse_key() {
+        // Every other key byte is zero. The first segment must match a direct
+        // computation of the selected key bytes, whether or not the XOR with a zero
+        // key byte is skipped.
+        let mut key = generate_key(512);
+        key.iter_mut().skip(1).step_by(2).for_each(|b| *b = 0);
+        let n = key.len() as u64;
+        let hash = QMC2RC4::new(&key).hash;
+
+        let data = (0..FIRST_SEGMENT_SIZE)
+            .map(|i| (i * 7 + 1) as u8)
+            .collect::<Vec<_>>();
+        let selected_keys = (0..FIRST_SEGMENT_SIZE)
+            .map(|offset| {
+                let seed = key[offset % key.len()];
+                key[(get_segment_key(offset as u64, seed, hash) % n) as usize]
+            })
+            .collect::<Vec<_>>();
+        assert!(selected_keys.contains(&0), "no zero key byte selected");
+        assert!(
+            selected_keys.iter().any(|&k| k != 0),
+            "only zero key bytes selected"
+        );
+        let expected = data
+            .iter()
+            .zip(&selected_keys)
+            .map(|(&datum, &key)| datum ^ key)
+            .collect::<Vec<_>>();
+
+        // With the first segment table, and computing each key byte on the fly.
+        for cipher in [QMC2RC4::new(&key), QMC2RC4::new_header_only(&key)] {
+            let mut actual = data.clone();
+            cipher.decrypt(&mut actual, 0);
+            assert_eq!(actual, expected);
+        }
+    }
+
+    #[cfg(feature = "tracing")]
+    #[tracing_test::traced_test]
+    #[test]
+    fn test_new_emits_trace_event() {
+        let key = header_test_key();
+        let cipher = QMC2RC4::new(&key);
+
+        let fingerprint = cipher
+            .key_fingerprint()
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect::<String>();
+        assert!(logs_contain("created QMC2RC4 cipher"));
+        assert!(logs_contain(&format!("key_len={}", key.len())));
+        assert!(logs_contain(&format!("fingerprint={fingerprint}")));
+        // The key itself is never logged.
+        assert!(!logs_contain(std::str::from_utf8(&key[..16]).unwrap()));
+    }
 }
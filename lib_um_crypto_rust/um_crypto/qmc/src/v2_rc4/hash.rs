@@ -22,3 +22,18 @@ fn test_hash() {
     let actual = hash(b"hello world");
     assert_eq!(expected, actual);
 }
+
+#[test]
+fn test_hash_ignores_null_bytes() {
+    let expected = hash(b"hello world");
+    let actual = hash(b"\0hello\0\0 world\0");
+    assert_eq!(expected, actual);
+}
+
+#[test]
+fn test_hash_high_bytes() {
+    // 0x80 * 0xFF * 0xFE * 0xFD = 0x7D057D00, next multiply by 0xFC wraps around.
+    let expected = (0x80u32 * 0xFF * 0xFE * 0xFD) as f64;
+    let actual = hash([0x80, 0xFF, 0xFE, 0xFD, 0xFC]);
+    assert_eq!(expected, actual);
+}
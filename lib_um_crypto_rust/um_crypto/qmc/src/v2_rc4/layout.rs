@@ -0,0 +1,111 @@
+use crate::v2_rc4::cipher::{FIRST_SEGMENT_SIZE, OTHER_SEGMENT_SIZE};
+use std::cmp::min;
+use std::ops::Range;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum SegmentKind {
+    /// The first 0x80 bytes, where each byte has its own key.
+    First,
+    /// Segments of 0x1400 bytes, using the RC4 key stream.
+    Other,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct SegmentInfo {
+    pub kind: SegmentKind,
+    /// Segment id, `offset / 0x1400` for other segments.
+    pub id: usize,
+    /// Byte range (offset in file) covered by this segment.
+    pub range: Range<usize>,
+    /// Key stream skip, only known for other segments when a key is available.
+    pub skip: Option<usize>,
+}
+
+/// Describe how `len` bytes starting at `offset` are split into segments.
+///
+/// This mirrors the decryption loop, but does not require a key.
+/// Use [`crate::v2_rc4::cipher::QMC2RC4::segment_layout`] to get the skip values.
+pub fn segment_layout(offset: usize, len: usize) -> Vec<SegmentInfo> {
+    segment_layout_with_size(offset, len, FIRST_SEGMENT_SIZE, OTHER_SEGMENT_SIZE)
+}
+
+/// Same as [`segment_layout`], with custom segment sizes.
+pub(crate) fn segment_layout_with_size(
+    offset: usize,
+    len: usize,
+    first_segment_size: usize,
+    segment_size: usize,
+) -> Vec<SegmentInfo> {
+    let mut result = vec![];
+    let mut offset = offset;
+    let end = offset + len;
+
+    if offset < first_segment_size && offset < end {
+        let n = min(first_segment_size - offset, end - offset);
+        result.push(SegmentInfo {
+            kind: SegmentKind::First,
+            id: 0,
+            range: offset..offset + n,
+            skip: None,
+        });
+        offset += n;
+    }
+
+    while offset < end {
+        let id = offset / segment_size;
+        let n = min(segment_size - offset % segment_size, end - offset);
+        result.push(SegmentInfo {
+            kind: SegmentKind::Other,
+            id,
+            range: offset..offset + n,
+            skip: None,
+        });
+        offset += n;
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::generate_key;
+    use crate::v2_rc4::cipher::QMC2RC4;
+
+    #[test]
+    fn test_segment_layout() {
+        let layout = segment_layout(0x70, 0x2900);
+        let ranges = layout
+            .iter()
+            .map(|s| (s.kind, s.id, s.range.clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            ranges,
+            [
+                (SegmentKind::First, 0, 0x70..0x80),
+                (SegmentKind::Other, 0, 0x80..0x1400),
+                (SegmentKind::Other, 1, 0x1400..0x2800),
+                (SegmentKind::Other, 2, 0x2800..0x2970),
+            ]
+        );
+        assert!(layout.iter().all(|s| s.skip.is_none()));
+    }
+
+    #[test]
+    fn test_segment_layout_empty() {
+        assert_eq!(segment_layout(0x10, 0), []);
+        assert_eq!(segment_layout(0x2000, 0), []);
+    }
+
+    #[test]
+    fn test_segment_layout_with_key() {
+        let cipher = QMC2RC4::new(&generate_key(512));
+        let layout = cipher.segment_layout(0x1400, 0x1401);
+        assert_eq!(layout.len(), 2);
+        assert_eq!(layout[0].range, 0x1400..0x2800);
+        assert_eq!(layout[0].skip, Some(cipher.segment_skip(1)));
+        assert_eq!(layout[1].range, 0x2800..0x2801);
+        assert_eq!(layout[1].skip, Some(cipher.segment_skip(2)));
+        assert!(layout.iter().all(|s| s.skip.unwrap() <= 0x1FF));
+    }
+}
@@ -1,4 +1,9 @@
 pub mod cipher;
 pub mod hash;
+pub mod layout;
+pub mod persist;
 pub mod rc4;
+pub mod reader;
+pub mod ring;
 pub mod segment_key;
+pub mod verify;
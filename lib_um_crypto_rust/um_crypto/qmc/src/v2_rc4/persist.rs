@@ -0,0 +1,128 @@
+//! Save the derived key material of a [`QMC2RC4`] cipher, to skip deriving it again
+//! after a restart.
+//!
+//! Layout, little endian:
+//!
+//! ```text
+//! offset    value
+//!      0    magic("QMC2KS") u16(version)
+//!      8    u32(key_len) byte[key_len] key
+//!     ??    f64(hash)
+//!     ??    byte[RC4_STREAM_CACHE_SIZE] key_stream
+//!     ??    byte[16] md5(everything above)
+//! ```
+
+use crate::v2_rc4::cipher::{DecryptMode, QMC2RC4, RC4_STREAM_CACHE_SIZE};
+use crate::QmcCryptoError;
+use std::io::{Read, Write};
+
+const MAGIC: &[u8; 6] = b"QMC2KS";
+const VERSION: u16 = 1;
+
+impl QMC2RC4 {
+    /// Write the key, its hash and its key stream to `w`, see [`QMC2RC4::load`].
+    ///
+    /// Only the key material is saved: custom segment sizes and segment key
+    /// functions are not, a loaded cipher uses the standard layout.
+    pub fn save(&self, w: &mut impl Write) -> Result<(), QmcCryptoError> {
+        let (key, hash, key_stream) = self.parts();
+
+        let mut buffer = Vec::with_capacity(12 + key.len() + 8 + key_stream.len() + 16);
+        buffer.extend_from_slice(MAGIC);
+        buffer.extend_from_slice(&VERSION.to_le_bytes());
+        buffer.extend_from_slice(&(key.len() as u32).to_le_bytes());
+        buffer.extend_from_slice(key);
+        buffer.extend_from_slice(&hash.to_le_bytes());
+        buffer.extend_from_slice(key_stream);
+        let checksum = umc_utils::md5(&buffer);
+        buffer.extend_from_slice(&checksum);
+
+        w.write_all(&buffer).map_err(QmcCryptoError::IOError)
+    }
+
+    /// Read a cipher written by [`QMC2RC4::save`], after checking its version and
+    /// checksum.
+    pub fn load(r: &mut impl Read) -> Result<Self, QmcCryptoError> {
+        let mut header = [0u8; 12];
+        r.read_exact(&mut header).map_err(QmcCryptoError::IOError)?;
+        if &header[..6] != MAGIC {
+            return Err(QmcCryptoError::KeyStreamCacheInvalid("bad magic"));
+        }
+        if u16::from_le_bytes([header[6], header[7]]) != VERSION {
+            return Err(QmcCryptoError::KeyStreamCacheInvalid("unsupported version"));
+        }
+        let key_len = u32::from_le_bytes(header[8..12].try_into().unwrap()) as usize;
+
+        // Read through `take`, so a bogus key length can't make us allocate.
+        let body_len = key_len as u64 + 8 + RC4_STREAM_CACHE_SIZE as u64 + 16;
+        let mut buffer = header.to_vec();
+        r.take(body_len)
+            .read_to_end(&mut buffer)
+            .map_err(QmcCryptoError::IOError)?;
+        if buffer.len() as u64 != header.len() as u64 + body_len {
+            return Err(QmcCryptoError::KeyStreamCacheInvalid("truncated"));
+        }
+
+        let (data, checksum) = buffer.split_at(buffer.len() - 16);
+        if umc_utils::md5(data) != checksum {
+            return Err(QmcCryptoError::KeyStreamCacheInvalid("checksum mismatch"));
+        }
+        if key_len == 0 {
+            return Err(QmcCryptoError::QMCV2RC4KeyEmpty);
+        }
+
+        let (key, rest) = data[header.len()..].split_at(key_len);
+        let (hash, key_stream) = rest.split_at(8);
+        let hash = f64::from_le_bytes(hash.try_into().unwrap());
+        let key_stream = Box::new(<[u8; RC4_STREAM_CACHE_SIZE]>::try_from(key_stream).unwrap());
+        Ok(Self::from_parts(key, hash, key_stream).with_decrypt_mode(DecryptMode::WholeFile))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::generate_key;
+
+    #[test]
+    fn test_save_load_round_trip() {
+        let cipher = QMC2RC4::new(&generate_key(512));
+        let mut saved = vec![];
+        cipher.save(&mut saved).unwrap();
+
+        let loaded = QMC2RC4::load(&mut saved.as_slice()).unwrap();
+        assert_eq!(loaded, cipher);
+
+        let data = (0..0x3000).map(|i| (i * 3) as u8).collect::<Vec<_>>();
+        let mut expected = data.clone();
+        cipher.decrypt(&mut expected, 0);
+        let mut actual = data.clone();
+        loaded.decrypt(&mut actual, 0);
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn test_load_rejects_corrupt_cache() {
+        let mut saved = vec![];
+        QMC2RC4::new(&generate_key(512)).save(&mut saved).unwrap();
+
+        let mut corrupt = saved.clone();
+        corrupt[0x100] ^= 1;
+        assert!(matches!(
+            QMC2RC4::load(&mut corrupt.as_slice()),
+            Err(QmcCryptoError::KeyStreamCacheInvalid("checksum mismatch"))
+        ));
+
+        let mut newer = saved.clone();
+        newer[6] = 2;
+        assert!(matches!(
+            QMC2RC4::load(&mut newer.as_slice()),
+            Err(QmcCryptoError::KeyStreamCacheInvalid("unsupported version"))
+        ));
+
+        assert!(matches!(
+            QMC2RC4::load(&mut &saved[..saved.len() - 1]),
+            Err(QmcCryptoError::KeyStreamCacheInvalid("truncated"))
+        ));
+    }
+}
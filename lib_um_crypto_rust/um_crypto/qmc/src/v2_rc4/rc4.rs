@@ -1,5 +1,8 @@
 use std::ops::Rem;
 
+/// QMC variant of RC4: the state is a permutation of `0..key.len()` instead of
+/// `0..256`, and `i`, `j` and the output index are all taken modulo the key length.
+/// It only matches standard RC4 for 256 byte keys.
 #[derive(Debug, Clone)]
 pub struct RC4 {
     state: Box<[u8]>,
@@ -7,6 +10,8 @@ pub struct RC4 {
     j: usize,
 }
 
+/// The state has one entry per key byte, so keys of any length are supported.
+/// As in the reference implementation, entries wrap around for keys longer than 256 bytes.
 fn init_state(key: &[u8]) -> Box<[u8]> {
     let n = key.len();
     let mut state: Box<[u8]> = (0..n).map(|i| i as u8).collect();
@@ -29,6 +34,14 @@ impl RC4 {
         }
     }
 
+    /// Copy of the current permutation (one entry per key byte, see `init_state`).
+    ///
+    /// Right after [`RC4::new`], this is the state once the key is scheduled; only
+    /// meant to debug key stream mismatches against another implementation.
+    pub fn snapshot_state(&self) -> Box<[u8]> {
+        self.state.clone()
+    }
+
     pub fn generate(&mut self) -> u8 {
         let n = self.state.len();
         self.i = self.i.wrapping_add(1).rem(n);
@@ -63,3 +76,70 @@ fn test_rc4() {
     assert_ne!(rc4.state, rc4_copy.state);
     assert_eq!(&data, b"\x68\x75\x6b\x64\x64\x24\x7f\x60\x7c\x7d\x60")
 }
+
+#[test]
+fn test_rc4_long_key() {
+    let key = (0..2048).map(|i| (i * 13) as u8).collect::<Vec<_>>();
+    let rc4 = RC4::new(&key);
+    assert_eq!(rc4.state.len(), key.len());
+
+    let mut data = [0u8; 0x1000];
+    RC4::new(&key).derive(&mut data[..]);
+    let mut again = [0u8; 0x1000];
+    RC4::new(&key).derive(&mut again[..]);
+    assert_eq!(data, again);
+    assert!(data.iter().any(|&b| b != 0));
+}
+
+#[test]
+fn test_rc4_snapshot_state() {
+    let rc4 = RC4::new(b"this is a test key");
+    assert_eq!(
+        &*rc4.snapshot_state(),
+        [15, 5, 4, 17, 3, 2, 10, 0, 12, 16, 8, 7, 13, 6, 11, 1, 9, 14]
+    );
+}
+
+/// RFC 6229 test vectors (offset 0 and 16 of the key stream).
+///
+/// The keys are repeated to 256 bytes, the only length where [`RC4`] is standard RC4;
+/// standard RC4 repeats short keys the same way while scheduling them.
+#[test]
+fn test_rc4_rfc6229_vectors() {
+    let vectors: [(&[u8], [u8; 32]); 3] = [
+        (
+            &[0x01, 0x02, 0x03, 0x04, 0x05],
+            [
+                0xb2, 0x39, 0x63, 0x05, 0xf0, 0x3d, 0xc0, 0x27, 0xcc, 0xc3, 0x52, 0x4a, 0x0a, 0x11,
+                0x18, 0xa8, 0x69, 0x82, 0x94, 0x4f, 0x18, 0xfc, 0x82, 0xd5, 0x89, 0xc4, 0x03, 0xa4,
+                0x7a, 0x0d, 0x09, 0x19,
+            ],
+        ),
+        (
+            &[0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08],
+            [
+                0x97, 0xab, 0x8a, 0x1b, 0xf0, 0xaf, 0xb9, 0x61, 0x32, 0xf2, 0xf6, 0x72, 0x58, 0xda,
+                0x15, 0xa8, 0x82, 0x63, 0xef, 0xdb, 0x45, 0xc4, 0xa1, 0x86, 0x84, 0xef, 0x87, 0xe6,
+                0xb1, 0x9e, 0x5b, 0x09,
+            ],
+        ),
+        (
+            &[
+                0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a, 0x0b, 0x0c, 0x0d, 0x0e,
+                0x0f, 0x10,
+            ],
+            [
+                0x9a, 0xc7, 0xcc, 0x9a, 0x60, 0x9d, 0x1e, 0xf7, 0xb2, 0x93, 0x28, 0x99, 0xcd, 0xe4,
+                0x1b, 0x97, 0x52, 0x48, 0xc4, 0x95, 0x90, 0x14, 0x12, 0x6a, 0x6e, 0x8a, 0x84, 0xf1,
+                0x1d, 0x1a, 0x9e, 0x1c,
+            ],
+        ),
+    ];
+
+    for (key, expected) in vectors {
+        let key = key.iter().copied().cycle().take(256).collect::<Vec<_>>();
+        let mut key_stream = [0u8; 32];
+        RC4::new(&key).derive(&mut key_stream[..]);
+        assert_eq!(key_stream, expected, "key {:02x?}", &key[..8]);
+    }
+}
@@ -0,0 +1,254 @@
+use crate::v2_rc4::cipher::QMC2RC4;
+use std::io::{self, Read};
+
+/// Decrypts data on the fly as it is read from `inner`.
+pub struct QMC2RC4Reader<R> {
+    inner: R,
+    cipher: QMC2RC4,
+    offset: usize,
+}
+
+impl<R: Read> QMC2RC4Reader<R> {
+    pub fn new(inner: R, cipher: QMC2RC4) -> Self {
+        Self::new_at(inner, cipher, 0)
+    }
+
+    /// Same as [`QMC2RC4Reader::new`], for a reader positioned at `offset` of the file.
+    pub fn new_at(inner: R, cipher: QMC2RC4, offset: usize) -> Self {
+        Self {
+            inner,
+            cipher,
+            offset,
+        }
+    }
+
+    /// File offset of the next byte to be read.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: Read> Read for QMC2RC4Reader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        // The inner reader may return fewer bytes than requested (e.g. sockets);
+        // only decrypt what was actually read.
+        let n = self.inner.read(buf)?;
+        self.cipher.decrypt(&mut buf[..n], self.offset);
+        self.offset += n;
+        Ok(n)
+    }
+}
+
+/// Like [`QMC2RC4Reader`], but collects whole segments from `inner` before decrypting.
+///
+/// Useful when `inner` returns small chunks: each segment is then decrypted in a
+/// single call, instead of once per chunk. A partial segment is only emitted at
+/// the end of the stream.
+pub struct SegmentBufferedReader<R> {
+    inner: R,
+    cipher: QMC2RC4,
+    /// File offset of `buffer[0]`.
+    offset: usize,
+    buffer: Box<[u8]>,
+    /// Bytes of `buffer` already returned to the caller.
+    pos: usize,
+    /// Bytes of `buffer` decrypted and ready to be returned.
+    decrypted: usize,
+    /// Bytes of `buffer` read from `inner`.
+    filled: usize,
+}
+
+impl<R: Read> SegmentBufferedReader<R> {
+    pub fn new(inner: R, cipher: QMC2RC4) -> Self {
+        Self::new_at(inner, cipher, 0)
+    }
+
+    /// Same as [`SegmentBufferedReader::new`], for a reader positioned at `offset` of the file.
+    pub fn new_at(inner: R, cipher: QMC2RC4, offset: usize) -> Self {
+        let buffer = vec![0u8; cipher.segment_size()].into_boxed_slice();
+        Self {
+            inner,
+            cipher,
+            offset,
+            buffer,
+            pos: 0,
+            decrypted: 0,
+            filled: 0,
+        }
+    }
+
+    /// File offset of the next byte to be read.
+    pub fn offset(&self) -> usize {
+        self.offset + self.pos
+    }
+
+    /// Returns the inner reader. Data buffered but not yet read is lost.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    /// Read up to the next segment boundary, or the end of `inner`, then decrypt.
+    fn fill_segment(&mut self) -> io::Result<()> {
+        let segment_size = self.buffer.len();
+        let len = segment_size - self.offset % segment_size;
+        while self.filled < len {
+            match self.inner.read(&mut self.buffer[self.filled..len]) {
+                Ok(0) => break,
+                Ok(n) => self.filled += n,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                // Keep what was read so far, the next call picks up from there.
+                Err(err) => return Err(err),
+            }
+        }
+
+        self.cipher
+            .decrypt(&mut self.buffer[..self.filled], self.offset);
+        self.decrypted = self.filled;
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for SegmentBufferedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos == self.decrypted {
+            // Everything decrypted was returned, move on to the next segment.
+            self.offset += self.decrypted;
+            self.filled -= self.decrypted;
+            self.pos = 0;
+            self.decrypted = 0;
+            self.fill_segment()?;
+        }
+
+        let n = buf.len().min(self.decrypted - self.pos);
+        buf[..n].copy_from_slice(&self.buffer[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::generate_key;
+
+    /// Returns 1 to 3 bytes per call.
+    struct TrickleReader<'a> {
+        data: &'a [u8],
+        calls: usize,
+    }
+
+    impl Read for TrickleReader<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.calls += 1;
+            let n = (self.calls % 3 + 1).min(buf.len()).min(self.data.len());
+            buf[..n].copy_from_slice(&self.data[..n]);
+            self.data = &self.data[n..];
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn test_reader_short_reads() {
+        let cipher = QMC2RC4::new(&generate_key(512));
+        let data = (0..0x3000).map(|i| (i * 7) as u8).collect::<Vec<_>>();
+
+        let mut expected = data.clone();
+        cipher.decrypt(&mut expected, 0);
+
+        let inner = TrickleReader {
+            data: &data,
+            calls: 0,
+        };
+        let mut reader = QMC2RC4Reader::new(inner, cipher);
+        let mut actual = vec![];
+        let mut buf = [0xCCu8; 0x100];
+        loop {
+            let n = reader.read(&mut buf).unwrap();
+            if n == 0 {
+                break;
+            }
+            // The untouched tail must be left alone.
+            assert!(buf[n..].iter().all(|&b| b == 0xCC));
+            actual.extend_from_slice(&buf[..n]);
+            buf[..n].fill(0xCC);
+        }
+
+        assert_eq!(reader.offset(), data.len());
+        assert_eq!(actual, expected);
+    }
+
+    /// Returns a pseudo-random number of bytes per call, up to `max_chunk`.
+    struct ChunkedReader<'a> {
+        data: &'a [u8],
+        state: u32,
+        max_chunk: usize,
+    }
+
+    impl Read for ChunkedReader<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.state = self.state.wrapping_mul(1103515245).wrapping_add(12345);
+            let chunk = (self.state >> 16) as usize % self.max_chunk + 1;
+            let n = chunk.min(buf.len()).min(self.data.len());
+            buf[..n].copy_from_slice(&self.data[..n]);
+            self.data = &self.data[n..];
+            Ok(n)
+        }
+    }
+
+    fn read_in_chunks<R: Read>(mut reader: R, max_chunk: usize) -> Vec<u8> {
+        let mut result = vec![];
+        let mut buf = vec![0u8; max_chunk];
+        loop {
+            let n = reader.read(&mut buf).unwrap();
+            if n == 0 {
+                break;
+            }
+            result.extend_from_slice(&buf[..n]);
+        }
+        result
+    }
+
+    #[test]
+    fn test_segment_buffered_reader() {
+        let cipher = QMC2RC4::new(&generate_key(512));
+        let data = (0..0x5123).map(|i| (i * 7) as u8).collect::<Vec<_>>();
+
+        for (seed, max_chunk) in [(1, 7), (2, 0x100), (3, 0x1000), (4, 0x3000)] {
+            let inner = ChunkedReader {
+                data: &data,
+                state: seed,
+                max_chunk,
+            };
+            let expected = read_in_chunks(QMC2RC4Reader::new(inner, cipher.clone()), max_chunk);
+
+            let inner = ChunkedReader {
+                data: &data,
+                state: seed,
+                max_chunk,
+            };
+            let mut reader = SegmentBufferedReader::new(inner, cipher.clone());
+            let actual = read_in_chunks(&mut reader, max_chunk);
+
+            assert_eq!(actual, expected, "max_chunk={max_chunk}");
+            assert_eq!(reader.offset(), data.len());
+        }
+    }
+
+    #[test]
+    fn test_segment_buffered_reader_at_offset() {
+        let cipher = QMC2RC4::new(&generate_key(512));
+        let data = (0..0x3000).map(|i| (i * 7) as u8).collect::<Vec<_>>();
+
+        let mut expected = data.clone();
+        cipher.decrypt(&mut expected, 0);
+
+        let offset = 0x1234;
+        let mut reader = SegmentBufferedReader::new_at(&data[offset..], cipher, offset);
+        let actual = read_in_chunks(&mut reader, 0x10);
+        assert_eq!(actual, &expected[offset..]);
+    }
+}
@@ -0,0 +1,138 @@
+use crate::v2_rc4::cipher::QMC2RC4;
+
+/// Fixed size ring buffer of decrypted audio, e.g. for a playback callback.
+///
+/// Encrypted data is copied straight into the free space of the ring and
+/// decrypted there, without an intermediate buffer.
+pub struct DecryptRing {
+    buffer: Box<[u8]>,
+    /// Index of the oldest byte.
+    head: usize,
+    len: usize,
+}
+
+impl DecryptRing {
+    /// # Panics
+    ///
+    /// If `capacity` is zero.
+    pub fn new(capacity: usize) -> Self {
+        assert!(capacity > 0, "ring capacity must not be zero");
+        Self {
+            buffer: vec![0u8; capacity].into_boxed_slice(),
+            head: 0,
+            len: 0,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// Number of decrypted bytes waiting to be popped.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Free space, in bytes.
+    pub fn available(&self) -> usize {
+        self.capacity() - self.len
+    }
+
+    /// Copy as much of `src` (located at `offset` of the file) as fits, and decrypt it.
+    ///
+    /// returns: Number of bytes taken from `src`.
+    pub fn push_decrypted(&mut self, cipher: &QMC2RC4, src: &[u8], offset: usize) -> usize {
+        let n = src.len().min(self.available());
+        let capacity = self.capacity();
+        let tail = (self.head + self.len) % capacity;
+
+        // The free space may wrap around the end of the buffer.
+        let first = n.min(capacity - tail);
+        let (src_first, src_second) = src[..n].split_at(first);
+        let first_block = &mut self.buffer[tail..tail + first];
+        first_block.copy_from_slice(src_first);
+        cipher.decrypt(first_block, offset);
+
+        let second_block = &mut self.buffer[..src_second.len()];
+        second_block.copy_from_slice(src_second);
+        cipher.decrypt(second_block, offset + first);
+
+        self.len += n;
+        n
+    }
+
+    /// Move up to `out.len()` decrypted bytes to `out`, oldest first.
+    ///
+    /// returns: Number of bytes written to `out`.
+    pub fn pop(&mut self, out: &mut [u8]) -> usize {
+        let n = out.len().min(self.len);
+        let capacity = self.capacity();
+
+        let first = n.min(capacity - self.head);
+        out[..first].copy_from_slice(&self.buffer[self.head..self.head + first]);
+        out[first..n].copy_from_slice(&self.buffer[..n - first]);
+
+        self.head = (self.head + n) % capacity;
+        self.len -= n;
+        n
+    }
+}
+
+impl QMC2RC4 {
+    /// Decrypt `src`, located at `offset` of the file, into `ring`.
+    ///
+    /// See [`DecryptRing::push_decrypted`].
+    pub fn decrypt_into_ring(&self, src: &[u8], ring: &mut DecryptRing, offset: usize) -> usize {
+        ring.push_decrypted(self, src, offset)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::generate_key;
+
+    #[test]
+    fn test_ring_wrap_around() {
+        let cipher = QMC2RC4::new(&generate_key(512));
+        let data = (0..0x3000).map(|i| (i * 7) as u8).collect::<Vec<_>>();
+        let mut expected = data.clone();
+        cipher.decrypt(&mut expected, 0);
+
+        // Pushes of 0x150 bytes into 0x200 bytes, popping 0x100 at a time: the
+        // free space wraps around the end of the ring most of the time.
+        let mut ring = DecryptRing::new(0x200);
+        let mut actual = vec![];
+        let mut offset = 0;
+        let mut frame = [0u8; 0x100];
+        while actual.len() < data.len() {
+            let end = data.len().min(offset + 0x150);
+            offset += cipher.decrypt_into_ring(&data[offset..end], &mut ring, offset);
+
+            let n = ring.pop(&mut frame);
+            actual.extend_from_slice(&frame[..n]);
+        }
+
+        assert_eq!(offset, data.len());
+        assert!(ring.is_empty());
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn test_ring_full() {
+        let cipher = QMC2RC4::new(&generate_key(512));
+        let mut ring = DecryptRing::new(0x10);
+
+        assert_eq!(ring.push_decrypted(&cipher, &[0u8; 0x20], 0), 0x10);
+        assert_eq!(ring.available(), 0);
+        assert_eq!(ring.push_decrypted(&cipher, &[0u8; 0x20], 0x10), 0);
+
+        let mut out = [0u8; 0x20];
+        assert_eq!(ring.pop(&mut out), 0x10);
+        assert_eq!(ring.pop(&mut out), 0);
+    }
+}
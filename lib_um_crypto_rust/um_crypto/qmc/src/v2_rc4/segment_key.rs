@@ -1,10 +1,43 @@
+/// `hash` is expected to come from [`crate::v2_rc4::hash::hash`], i.e. to be a finite
+/// `u32`. Non-finite values, and segment ids large enough to wrap the divisor to zero,
+/// give `0` instead of a saturated cast.
 pub fn get_segment_key(id: u64, seed: u8, hash: f64) -> u64 {
-    match seed {
-        0 => 0,
-        seed => {
-            let result = hash / ((id + 1).wrapping_mul(seed.into()) as f64) * 100.0;
-            result as u64
-        }
+    let divisor = id.wrapping_add(1).wrapping_mul(seed.into());
+    if divisor == 0 || !hash.is_finite() {
+        return 0;
+    }
+
+    let result = hash / (divisor as f64) * 100.0;
+    debug_assert!(result.is_finite(), "segment key is not finite: {result}");
+    result as u64
+}
+
+/// Computes the segment key, which selects the key byte (first segment) or the
+/// key stream skip (other segments).
+///
+/// Used to experiment with scheme variants, see
+/// [`crate::v2_rc4::cipher::QMC2RC4Builder::segment_key_fn`].
+pub trait SegmentKeyFn: Send + Sync {
+    fn segment_key(&self, id: u64, seed: u8, hash: f64) -> u64;
+}
+
+/// The standard QMCv2 segment key, [`get_segment_key`].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DefaultSegmentKey;
+
+impl SegmentKeyFn for DefaultSegmentKey {
+    #[inline]
+    fn segment_key(&self, id: u64, seed: u8, hash: f64) -> u64 {
+        get_segment_key(id, seed, hash)
+    }
+}
+
+impl<F> SegmentKeyFn for F
+where
+    F: Fn(u64, u8, f64) -> u64 + Send + Sync,
+{
+    fn segment_key(&self, id: u64, seed: u8, hash: f64) -> u64 {
+        self(id, seed, hash)
     }
 }
 
@@ -31,4 +64,17 @@ mod tests {
     fn test_segment_key_large_2() {
         assert_eq!(get_segment_key(0, 66, 3908240000.0), 5921575757);
     }
+
+    #[test]
+    fn test_segment_key_non_finite() {
+        assert_eq!(get_segment_key(1, 123, f64::NAN), 0);
+        assert_eq!(get_segment_key(1, 123, f64::INFINITY), 0);
+        assert_eq!(get_segment_key(1, 123, f64::NEG_INFINITY), 0);
+    }
+
+    #[test]
+    fn test_segment_key_wrapping_divisor() {
+        assert_eq!(get_segment_key(u64::MAX, 123, 12345.0), 0);
+        assert_eq!(get_segment_key(u64::MAX / 2, 2, 12345.0), 0);
+    }
 }
@@ -0,0 +1,103 @@
+use crate::v2_rc4::cipher::QMC2RC4;
+use um_audio::AudioType;
+
+impl QMC2RC4 {
+    /// Decrypt a copy of the whole file `data`, and check each segment of
+    /// [`QMC2RC4::segment_size`] bytes for plausibly valid audio frames of `format`.
+    ///
+    /// A segment is flagged (`false`) when frame validation starts failing inside it,
+    /// or it is entirely covered by data that failed validation. See
+    /// [`um_audio::find_corrupt_frames`] for the formats checked; segments of other
+    /// formats are never flagged.
+    pub fn verify_segments(&self, data: &[u8], format: AudioType) -> Vec<bool> {
+        let mut buffer = data.to_vec();
+        self.decrypt(&mut buffer, 0);
+        segment_validity(&buffer, format, self.segment_size())
+    }
+
+    /// Recover the offset in the file of `fragment`, a piece of audio of `format`
+    /// starting at a segment boundary, within the first `max_segments` segments.
+    ///
+    /// The fragment is decrypted at each candidate offset, and the offset giving the
+    /// most valid frame headers (see [`um_audio::count_frame_headers`]) wins.
+    ///
+    /// returns: `None` if no candidate gives any valid audio frame.
+    pub fn probe_offset(
+        &self,
+        fragment: &[u8],
+        format: AudioType,
+        max_segments: usize,
+    ) -> Option<usize> {
+        let mut buffer = vec![0u8; fragment.len()];
+        (0..max_segments)
+            .map(|id| id * self.segment_size())
+            .map(|offset| {
+                self.decrypt_into(fragment, &mut buffer, offset);
+                (um_audio::count_frame_headers(&buffer, format), offset)
+            })
+            .filter(|&(frames, _)| frames > 0)
+            .max_by_key(|&(frames, offset)| (frames, std::cmp::Reverse(offset)))
+            .map(|(_, offset)| offset)
+    }
+}
+
+/// Flag each `segment_size` segment of the decrypted `data`, see [`QMC2RC4::verify_segments`].
+pub(crate) fn segment_validity(data: &[u8], format: AudioType, segment_size: usize) -> Vec<bool> {
+    let mut valid = vec![true; data.len().div_ceil(segment_size)];
+    for corrupt in um_audio::find_corrupt_frames(data, format) {
+        let first = corrupt.start / segment_size;
+        for (i, segment) in valid.iter_mut().enumerate().skip(first) {
+            let segment_end = ((i + 1) * segment_size).min(data.len());
+            if i == first || segment_end <= corrupt.end {
+                *segment = false;
+            }
+            if segment_end >= corrupt.end {
+                break;
+            }
+        }
+    }
+    valid
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::generate_key;
+    use crate::v2_rc4::cipher::OTHER_SEGMENT_SIZE;
+    use um_audio::mp3::tests::make_mp3_frame;
+
+    #[test]
+    fn test_probe_offset() {
+        let cipher = QMC2RC4::new(&generate_key(512));
+        let frame = make_mp3_frame();
+        let mut data = frame.repeat(OTHER_SEGMENT_SIZE * 6 / frame.len() + 1);
+        data.truncate(OTHER_SEGMENT_SIZE * 6);
+        cipher.decrypt(&mut data, 0);
+
+        let offset = OTHER_SEGMENT_SIZE * 3;
+        let fragment = &data[offset..offset + OTHER_SEGMENT_SIZE * 2];
+        assert_eq!(
+            cipher.probe_offset(fragment, AudioType::MP3, 8),
+            Some(offset)
+        );
+        assert_eq!(cipher.probe_offset(fragment, AudioType::MP3, 3), None);
+        assert_eq!(cipher.probe_offset(fragment, AudioType::OGG, 8), None);
+    }
+
+    #[test]
+    fn test_verify_segments_flags_flipped_segment() {
+        let cipher = QMC2RC4::new(&generate_key(512));
+        let frame = make_mp3_frame();
+        let mut data = frame.repeat(OTHER_SEGMENT_SIZE * 5 / frame.len() + 1);
+        data.truncate(OTHER_SEGMENT_SIZE * 5);
+        cipher.decrypt(&mut data, 0);
+        assert_eq!(cipher.verify_segments(&data, AudioType::MP3), vec![true; 5]);
+
+        let flipped = OTHER_SEGMENT_SIZE * 2..OTHER_SEGMENT_SIZE * 3;
+        data[flipped].iter_mut().for_each(|byte| *byte ^= 0xFF);
+        assert_eq!(
+            cipher.verify_segments(&data, AudioType::MP3),
+            vec![true, true, false, true, true]
+        );
+    }
+}
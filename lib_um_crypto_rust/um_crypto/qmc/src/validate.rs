@@ -0,0 +1,217 @@
+//! Sanity checks of decrypted audio, stricter than the magic checks of
+//! [`um_audio::detect_audio_type`].
+
+use crate::QmcCryptoError;
+use byteorder::{ByteOrder, LE};
+use um_audio::metadata::get_header_metadata_size;
+use um_audio::mp3::Mp3FrameHeader;
+
+/// Format of a WAV file, see [`validate_wav_structure`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WavInfo {
+    pub channels: u16,
+    pub sample_rate: u32,
+    pub bits_per_sample: u16,
+    /// Offset of the content of the `data` chunk.
+    pub data_offset: usize,
+    pub data_len: usize,
+}
+
+/// Check the first MPEG audio frame header of `data`, the decrypted file.
+///
+/// Skips the tags at the start of the file, locates the first frame sync (11 bits
+/// set) and decodes its version, layer, bitrate and sample rate; reserved or
+/// invalid combinations are rejected. Noise that happens to start with `0xFF` will
+/// rarely get through.
+pub fn validate_mp3_frame(data: &[u8]) -> Result<(), QmcCryptoError> {
+    let start = match get_header_metadata_size(data, 0) {
+        Ok(start) => start,
+        Err(_) if data.starts_with(b"ID3") || data.starts_with(b"APETAGEX") => {
+            Err(QmcCryptoError::Mp3FrameInvalid("truncated tag"))?
+        }
+        // Too short to hold a tag.
+        Err(_) => 0,
+    };
+    let sync = start
+        + data
+            .get(start..)
+            .unwrap_or_default()
+            .windows(2)
+            .position(|w| w[0] == 0xFF && w[1] & 0xE0 == 0xE0)
+            .ok_or(QmcCryptoError::Mp3FrameInvalid("no frame sync"))?;
+
+    match Mp3FrameHeader::parse(&data[sync..]) {
+        Some(_) => Ok(()),
+        None if data.len() < sync + 4 => {
+            Err(QmcCryptoError::Mp3FrameInvalid("truncated frame header"))
+        }
+        None => Err(QmcCryptoError::Mp3FrameInvalid(
+            "reserved or invalid header fields",
+        )),
+    }
+}
+
+/// Check the structure of `data`, a decrypted WAV file: its RIFF header, a sane
+/// `fmt ` chunk, then a `data` chunk that fits in the file.
+///
+/// The audio itself is not looked at.
+pub fn validate_wav_structure(data: &[u8]) -> Result<WavInfo, QmcCryptoError> {
+    let invalid = QmcCryptoError::WavInvalid;
+    if data.len() < 12 || !data.starts_with(b"RIFF") || &data[8..12] != b"WAVE" {
+        return Err(invalid("no RIFF/WAVE header"));
+    }
+
+    let mut format = None;
+    let mut offset = 12;
+    while offset + 8 <= data.len() {
+        let chunk_id = &data[offset..offset + 4];
+        let chunk_len = LE::read_u32(&data[offset + 4..offset + 8]) as usize;
+        let start = offset + 8;
+        let chunk = data.get(start..start.saturating_add(chunk_len));
+
+        match chunk_id {
+            b"fmt " => {
+                //  offset    value
+                //       0    u16(format) u16(channels) u32(sample_rate)
+                //       8    u32(byte_rate) u16(block_align) u16(bits_per_sample)
+                let fmt = chunk
+                    .filter(|fmt| fmt.len() >= 16)
+                    .ok_or(invalid("truncated fmt chunk"))?;
+                let channels = LE::read_u16(&fmt[2..4]);
+                let sample_rate = LE::read_u32(&fmt[4..8]);
+                let byte_rate = LE::read_u32(&fmt[8..12]);
+                let block_align = LE::read_u16(&fmt[12..14]);
+                let bits_per_sample = LE::read_u16(&fmt[14..16]);
+                if channels == 0 || sample_rate == 0 || bits_per_sample == 0 {
+                    return Err(invalid("empty format field"));
+                }
+                let expected_align = channels as u32 * (bits_per_sample as u32).div_ceil(8);
+                if block_align as u32 != expected_align
+                    || byte_rate as u64 != sample_rate as u64 * expected_align as u64
+                {
+                    return Err(invalid("inconsistent fmt chunk"));
+                }
+                format = Some((channels, sample_rate, bits_per_sample));
+            }
+            b"data" => {
+                let (channels, sample_rate, bits_per_sample) =
+                    format.ok_or(invalid("data chunk before fmt chunk"))?;
+                if chunk.is_none() {
+                    return Err(invalid("data chunk larger than the file"));
+                }
+                return Ok(WavInfo {
+                    channels,
+                    sample_rate,
+                    bits_per_sample,
+                    data_offset: start,
+                    data_len: chunk_len,
+                });
+            }
+            _ => {}
+        }
+
+        // Chunks are padded to an even size.
+        offset = start
+            .saturating_add(chunk_len)
+            .saturating_add(chunk_len & 1);
+    }
+
+    Err(invalid("no data chunk"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use um_audio::mp3::tests::make_mp3_frame;
+
+    #[test]
+    fn test_validate_mp3_frame() {
+        let mut data = vec![0u8; 16];
+        data.extend(make_mp3_frame());
+        validate_mp3_frame(&data).expect("valid frame header");
+    }
+
+    #[test]
+    fn test_validate_mp3_frame_after_cover() {
+        // ID3 tag with an APIC frame holding a JPEG, whose marker looks like a sync.
+        let mut apic = b"\x00image/jpeg\x00\x03\x00".to_vec();
+        apic.extend_from_slice(&[0xFF, 0xD8, 0xFF, 0xE0, 0x00, 0x10]);
+        apic.extend_from_slice(b"JFIF\x00");
+        apic.resize(0x40, 0);
+        let mut tag = b"APIC".to_vec();
+        tag.extend_from_slice(&(apic.len() as u32).to_be_bytes());
+        tag.extend_from_slice(&[0, 0]);
+        tag.extend_from_slice(&apic);
+
+        let mut data = b"ID3\x03\x00\x00\x00\x00\x00".to_vec();
+        data.push(tag.len() as u8);
+        data.extend_from_slice(&tag);
+        data.extend(make_mp3_frame());
+        validate_mp3_frame(&data).expect("valid frame header after the tag");
+
+        let result = validate_mp3_frame(&data[..0x20]);
+        assert!(matches!(result, Err(QmcCryptoError::Mp3FrameInvalid(_))));
+    }
+
+    #[test]
+    fn test_validate_mp3_frame_bogus() {
+        // Layer bits reserved.
+        let result = validate_mp3_frame(&[0xFF, 0xF9, 0x90, 0x64]);
+        assert!(matches!(result, Err(QmcCryptoError::Mp3FrameInvalid(_))));
+        // Bitrate index 15 is invalid.
+        let result = validate_mp3_frame(&[0xFF, 0xFB, 0xF0, 0x64]);
+        assert!(matches!(result, Err(QmcCryptoError::Mp3FrameInvalid(_))));
+        // Sample rate index reserved.
+        let result = validate_mp3_frame(&[0xFF, 0xFB, 0x9C, 0x64]);
+        assert!(matches!(result, Err(QmcCryptoError::Mp3FrameInvalid(_))));
+
+        let result = validate_mp3_frame(&[0x12, 0x34, 0x56, 0x78]);
+        assert!(matches!(result, Err(QmcCryptoError::Mp3FrameInvalid(_))));
+    }
+
+    fn make_wav(block_align: u16) -> Vec<u8> {
+        let mut wav = b"RIFF\x00\x00\x00\x00WAVE".to_vec();
+        wav.extend_from_slice(b"fmt \x10\x00\x00\x00");
+        wav.extend_from_slice(&1u16.to_le_bytes()); // PCM
+        wav.extend_from_slice(&2u16.to_le_bytes());
+        wav.extend_from_slice(&44100u32.to_le_bytes());
+        wav.extend_from_slice(&(44100u32 * 4).to_le_bytes());
+        wav.extend_from_slice(&block_align.to_le_bytes());
+        wav.extend_from_slice(&16u16.to_le_bytes());
+        wav.extend_from_slice(b"LIST\x03\x00\x00\x00abc\x00"); // odd sized, padded
+        wav.extend_from_slice(b"data\x00\x01\x00\x00");
+        wav.resize(wav.len() + 0x100, 0);
+        wav
+    }
+
+    #[test]
+    fn test_validate_wav_structure() {
+        let wav = make_wav(4);
+        assert_eq!(
+            validate_wav_structure(&wav).unwrap(),
+            WavInfo {
+                channels: 2,
+                sample_rate: 44100,
+                bits_per_sample: 16,
+                data_offset: wav.len() - 0x100,
+                data_len: 0x100,
+            }
+        );
+    }
+
+    #[test]
+    fn test_validate_wav_structure_malformed() {
+        let wav = make_wav(4);
+        let result = validate_wav_structure(&wav[..wav.len() - 1]);
+        assert!(matches!(result, Err(QmcCryptoError::WavInvalid(_))));
+
+        let result = validate_wav_structure(&make_wav(3));
+        assert!(matches!(result, Err(QmcCryptoError::WavInvalid(_))));
+
+        let result = validate_wav_structure(b"RIFF\x00\x00\x00\x00AVI LIST");
+        assert!(matches!(result, Err(QmcCryptoError::WavInvalid(_))));
+
+        let result = validate_wav_structure(&wav[..0x20]);
+        assert!(matches!(result, Err(QmcCryptoError::WavInvalid(_))));
+    }
+}
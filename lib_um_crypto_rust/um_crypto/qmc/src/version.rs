@@ -0,0 +1,92 @@
+//! Tell QMC v1 (static key) files from v2 ones, without a key.
+
+use crate::footer::{self, Metadata};
+use crate::v1;
+use crate::v2_rc4::cipher::{is_known_audio, PROBE_SIZE};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QmcVersion {
+    /// Static key, see [`v1::decrypt`].
+    V1,
+    /// Per file key, see [`crate::QMCv2Cipher`].
+    V2,
+    /// Neither looks right, e.g. a v2 file whose key is stored elsewhere.
+    Unknown,
+}
+
+/// Classify the encrypted file `data` from its structure.
+///
+/// * A key in `footer` (see [`footer::parse_footer_partial`]), or any footer at the
+///   end of `data`: v2, v1 files have no trailing key region.
+/// * Otherwise, v1 if decrypting the start of `data` with the static key gives
+///   known audio.
+pub fn detect_qmc_version(data: &[u8], footer: Option<&Metadata>) -> QmcVersion {
+    if footer.is_some_and(|footer| footer.ekey.as_ref().is_some_and(|ekey| !ekey.is_empty())) {
+        return QmcVersion::V2;
+    }
+    if !footer::candidates(data).is_empty() {
+        return QmcVersion::V2;
+    }
+
+    let mut head = data[..data.len().min(PROBE_SIZE)].to_vec();
+    v1::decrypt(&mut head, 0);
+    match is_known_audio(&head) {
+        true => QmcVersion::V1,
+        false => QmcVersion::Unknown,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::footer::tests::append_qtag;
+    use crate::QMCv2Cipher;
+
+    const EKEY: &str = include_str!("v2_rc4/fixtures/ekey_rc4_512.txt");
+
+    fn make_flac() -> Vec<u8> {
+        let mut data = b"fLaC\x00\x00\x00\x22".to_vec();
+        data.extend((0..0x1000).map(|i| i as u8));
+        data
+    }
+
+    #[test]
+    fn test_v2_with_footer() {
+        let mut data = make_flac();
+        QMCv2Cipher::new_from_ekey(EKEY)
+            .unwrap()
+            .decrypt(&mut data, 0);
+        append_qtag(&mut data, EKEY);
+
+        assert_eq!(detect_qmc_version(&data, None), QmcVersion::V2);
+
+        let (footer, _) = footer::parse_footer_partial(&data).unwrap();
+        assert_eq!(detect_qmc_version(&data, Some(&footer)), QmcVersion::V2);
+    }
+
+    #[test]
+    fn test_v1_without_footer() {
+        let mut data = make_flac();
+        v1::decrypt(&mut data, 0);
+        assert_eq!(detect_qmc_version(&data, None), QmcVersion::V1);
+    }
+
+    #[test]
+    fn test_v1_large_id3() {
+        // The ID3 tag (e.g. with a cover) goes past the probed head.
+        let mut data = b"ID3\x04\x00\x00\x00\x00\x10\x00".to_vec();
+        data.extend_from_slice(&[0u8; 0x800]);
+        data.extend(make_flac());
+        assert!(data.len() > PROBE_SIZE);
+        v1::decrypt(&mut data, 0);
+        assert_eq!(detect_qmc_version(&data, None), QmcVersion::V1);
+    }
+
+    #[test]
+    fn test_unknown() {
+        assert_eq!(
+            detect_qmc_version(&[0x55u8; 0x1000], None),
+            QmcVersion::Unknown
+        );
+    }
+}
@@ -0,0 +1,83 @@
+//! Advisory warnings on keys; none of them prevent decryption.
+
+use crate::v1::cipher::V1_KEY_SIZE;
+use crate::v2_map::used_key_bytes;
+use std::fmt::Display;
+
+/// Longest key decrypted with the map cipher, see [`crate::QMCv2Cipher::new`].
+const MAX_MAP_KEY_LEN: usize = 300;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QmcWarning {
+    /// Shorter than the compressed map key (128 bytes): key bytes are reused.
+    ShortKey { len: usize },
+    /// Only `used` of the `len` key bytes end up in the compressed map key, e.g. with
+    /// a power of two length, where few residues are squares.
+    PoorKeyCoverage { len: usize, used: usize },
+    /// The footer declares a `declared` bytes payload, but only `available` bytes are
+    /// left: the key is incomplete. See [`crate::footer::parse_footer_partial`].
+    PartialFooterKey { declared: usize, available: usize },
+    /// The file is cut off inside the footer, after `available` key bytes: the key is
+    /// incomplete. See [`crate::footer::parse_footer_partial`].
+    TruncatedFooterKey { available: usize },
+}
+
+impl Display for QmcWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            QmcWarning::ShortKey { len } => write!(f, "key is only {len} bytes long"),
+            QmcWarning::PoorKeyCoverage { len, used } => {
+                write!(f, "only {used} of the {len} key bytes are used")
+            }
+            QmcWarning::PartialFooterKey {
+                declared,
+                available,
+            } => write!(
+                f,
+                "only {available} of the {declared} footer bytes are left"
+            ),
+            QmcWarning::TruncatedFooterKey { available } => {
+                write!(f, "footer is cut off after {available} key bytes")
+            }
+        }
+    }
+}
+
+/// Flag key lengths that make poor use of the key material.
+///
+/// This is advisory: the official scheme accepts these keys, and so does this crate.
+/// Empty keys are rejected by the ciphers instead, and are not reported here.
+/// Keys used with the RC4 cipher (longer than 300 bytes) are never flagged.
+pub fn warn_on_suspicious_key_length(key: &[u8]) -> Option<QmcWarning> {
+    let len = key.len();
+    match len {
+        0 => None,
+        1..V1_KEY_SIZE => Some(QmcWarning::ShortKey { len }),
+        V1_KEY_SIZE..=MAX_MAP_KEY_LEN => {
+            let used = used_key_bytes(len);
+            (used < len / 2).then_some(QmcWarning::PoorKeyCoverage { len, used })
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::generate_key;
+
+    #[test]
+    fn test_warn_on_suspicious_key_length() {
+        assert_eq!(
+            warn_on_suspicious_key_length(&generate_key(16)),
+            Some(QmcWarning::ShortKey { len: 16 })
+        );
+        assert_eq!(warn_on_suspicious_key_length(&generate_key(512)), None);
+        assert_eq!(warn_on_suspicious_key_length(&generate_key(257)), None);
+        assert!(matches!(
+            warn_on_suspicious_key_length(&generate_key(256)),
+            Some(QmcWarning::PoorKeyCoverage { len: 256, .. })
+        ));
+        assert_eq!(warn_on_suspicious_key_length(&[]), None);
+    }
+}
@@ -0,0 +1,49 @@
+#![cfg(feature = "rayon")]
+
+use std::fs;
+use std::sync::Mutex;
+use umc_qmc::batch::{decrypt_batch, DecryptJob, KeySource};
+use umc_qmc::{ekey, QMCv2Cipher, QmcCryptoError};
+
+const EKEY: &str = include_str!("../src/v2_rc4/fixtures/ekey_rc4_512.txt");
+
+#[test]
+fn test_decrypt_batch() {
+    let dir = tempfile::tempdir().expect("failed to create temp dir");
+    let cipher = QMCv2Cipher::new_from_ekey(EKEY).expect("failed to create cipher");
+
+    let mut flac = b"fLaC\x00\x00\x00\x22".to_vec();
+    flac.extend((0..0x3000).map(|i| i as u8));
+    let mut encrypted = flac.clone();
+    cipher.decrypt(&mut encrypted, 0);
+    let input = dir.path().join("a.mflac");
+    fs::write(&input, &encrypted).unwrap();
+
+    let job = |key, output: &str| DecryptJob {
+        input: input.clone(),
+        key,
+        output: dir.path().join(output),
+    };
+    let jobs = vec![
+        job(KeySource::EKey(EKEY.into()), "ekey.flac"),
+        job(KeySource::Key(ekey::decrypt(EKEY).unwrap()), "key.flac"),
+        job(KeySource::Embedded, "embedded.flac"),
+    ];
+
+    let results = Mutex::new(vec![]);
+    decrypt_batch(jobs, |index, result| {
+        results.lock().unwrap().push((index, result));
+    });
+    let mut results = results.into_inner().unwrap();
+    results.sort_by_key(|(index, _)| *index);
+    assert_eq!(results.len(), 3);
+
+    for (index, name) in [(0, "ekey.flac"), (1, "key.flac")] {
+        let output = results[index].1.as_ref().expect("job should succeed");
+        assert_eq!(output, &dir.path().join(name));
+        assert_eq!(fs::read(output).unwrap(), flac);
+    }
+    // No footer, nor sidecar key.
+    assert_eq!(results[2].0, 2);
+    assert!(matches!(&results[2].1, Err(QmcCryptoError::KeyNotFound(_))));
+}
@@ -0,0 +1,61 @@
+use std::thread;
+use umc_qmc::QMCv2Cipher;
+
+const EKEY: &str = include_str!("../src/v2_rc4/fixtures/ekey_rc4_512.txt");
+
+/// Each thread decrypts its own copy of an overlapping range of `encrypted`, which
+/// has to match the same range of a single threaded decryption.
+fn check_concurrent_ranges(cipher: &QMCv2Cipher) {
+    let plaintext = (0..0x20000).map(|i| (i * 13 + 7) as u8).collect::<Vec<_>>();
+    let mut encrypted = plaintext.clone();
+    cipher.decrypt(&mut encrypted, 0);
+
+    // Ranges overlap, start mid segment and cover the first segments.
+    let ranges = (0..16)
+        .map(|i| {
+            let start = i * 0x1357;
+            let end = (start + 0x4000 + i * 0x0123).min(encrypted.len());
+            start..end
+        })
+        .collect::<Vec<_>>();
+
+    thread::scope(|scope| {
+        let workers = ranges
+            .iter()
+            .map(|range| {
+                let encrypted = &encrypted;
+                scope.spawn(move || {
+                    // Decrypt several times, to interleave with the other threads.
+                    (0..8)
+                        .map(|_| {
+                            let mut buffer = encrypted[range.clone()].to_vec();
+                            cipher.decrypt(&mut buffer, range.start);
+                            buffer
+                        })
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for (range, worker) in ranges.iter().zip(workers) {
+            for buffer in worker.join().expect("worker panicked") {
+                assert_eq!(buffer, plaintext[range.clone()], "range {range:?}");
+            }
+        }
+    });
+}
+
+#[test]
+fn test_concurrent_overlapping_rc4() {
+    let cipher = QMCv2Cipher::new_from_ekey(EKEY).expect("failed to create cipher");
+    assert!(matches!(cipher, QMCv2Cipher::RC4(_)));
+    check_concurrent_ranges(&cipher);
+}
+
+#[test]
+fn test_concurrent_overlapping_map() {
+    let key = (1..=128).map(|i| i as u8).collect::<Vec<_>>();
+    let cipher = QMCv2Cipher::new(&key).expect("failed to create cipher");
+    assert!(matches!(cipher, QMCv2Cipher::MapL(_)));
+    check_concurrent_ranges(&cipher);
+}
@@ -0,0 +1,69 @@
+use um_audio::AudioType;
+use umc_qmc::decoder::QmcDecoder;
+use umc_qmc::fixtures::{write_footer, FooterScheme};
+use umc_qmc::{QMCv2Cipher, QmcCryptoError};
+
+const EKEY: &str = include_str!("../src/v2_rc4/fixtures/ekey_rc4_512.txt");
+
+#[test]
+fn test_decrypt_all_flac() {
+    let mut plaintext = b"fLaC\x80\x00\x00\x22".to_vec();
+    plaintext.extend_from_slice(&[0u8; 0x22]);
+    plaintext.extend((0..0x3000).map(|i| i as u8));
+
+    let mut data = plaintext.clone();
+    QMCv2Cipher::new_from_ekey(EKEY)
+        .expect("failed to create cipher")
+        .decrypt(&mut data, 0);
+    data.extend_from_slice(&write_footer(
+        EKEY,
+        &FooterScheme::AndroidQTag { resource_id: 1 },
+    ));
+
+    let decoder = QmcDecoder::from_vec(data).expect("failed to prepare decoder");
+    let (format, audio) = decoder.decrypt_all().expect("failed to decrypt");
+    assert_eq!(format, AudioType::FLAC);
+    assert_eq!(
+        um_audio::detect_audio_type(&audio).unwrap(),
+        AudioType::FLAC
+    );
+    assert_eq!(audio, plaintext);
+}
+
+#[test]
+fn test_decrypt_all_wav() {
+    let mut plaintext = b"RIFF\x00\x00\x00\x00WAVEfmt \x10\x00\x00\x00".to_vec();
+    plaintext.extend_from_slice(&[0u8; 0x10]);
+    plaintext.extend_from_slice(b"data\x00\x30\x00\x00");
+    plaintext.extend((0..0x3000).map(|i| i as u8));
+
+    let mut data = plaintext.clone();
+    QMCv2Cipher::new_from_ekey(EKEY)
+        .expect("failed to create cipher")
+        .decrypt(&mut data, 0);
+    data.extend_from_slice(&write_footer(
+        EKEY,
+        &FooterScheme::AndroidQTag { resource_id: 1 },
+    ));
+
+    let decoder = QmcDecoder::from_vec(data).expect("failed to prepare decoder");
+    let (format, audio) = decoder.decrypt_all().expect("failed to decrypt");
+    assert_eq!(format, AudioType::WAV);
+    assert_eq!(um_audio::detect_audio_type(&audio).unwrap(), AudioType::WAV);
+    assert_eq!(audio, plaintext);
+}
+
+#[test]
+fn test_decrypt_all_unknown_audio() {
+    let mut data = vec![0x55u8; 0x1000];
+    data.extend_from_slice(&write_footer(
+        EKEY,
+        &FooterScheme::AndroidQTag { resource_id: 1 },
+    ));
+
+    let decoder = QmcDecoder::from_vec(data).expect("failed to prepare decoder");
+    assert!(matches!(
+        decoder.decrypt_all(),
+        Err(QmcCryptoError::UnknownAudio)
+    ));
+}
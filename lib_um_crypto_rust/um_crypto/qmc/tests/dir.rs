@@ -0,0 +1,275 @@
+use std::collections::HashMap;
+use std::fs;
+use umc_qmc::decoder::QmcDecoder;
+use umc_qmc::dir::{
+    decrypt_dir_with_keymap, decrypt_dir_with_keymap_in, decrypt_file_to_file, read_sidecar_key,
+    DecryptPool,
+};
+use umc_qmc::fixtures::{write_footer, FooterScheme};
+use umc_qmc::{QMCv2Cipher, QmcCryptoError};
+
+const EKEY: &str = include_str!("../src/v2_rc4/fixtures/ekey_rc4_512.txt");
+
+fn make_plaintext() -> Vec<u8> {
+    let mut data = b"fLaC\x00\x00\x00\x22".to_vec();
+    data.extend((0..0x3000).map(|i| i as u8));
+    data
+}
+
+#[test]
+fn test_decrypt_dir_with_keymap() {
+    let input_dir = tempfile::tempdir().expect("failed to create temp dir");
+    let output_dir = tempfile::tempdir().expect("failed to create temp dir");
+
+    let plaintext = make_plaintext();
+    let mut encrypted = plaintext.clone();
+    QMCv2Cipher::new_from_ekey(EKEY)
+        .expect("failed to create cipher")
+        .decrypt(&mut encrypted, 0);
+
+    fs::write(input_dir.path().join("song.mflac"), &encrypted).unwrap();
+    fs::write(input_dir.path().join("unknown.mflac"), &encrypted).unwrap();
+
+    let keymap = HashMap::from([("song.mflac".to_string(), EKEY.to_string())]);
+    let results = decrypt_dir_with_keymap(input_dir.path(), &keymap, output_dir.path());
+    assert_eq!(results.len(), 2);
+
+    let (input, result) = &results[0];
+    assert_eq!(input, &input_dir.path().join("song.mflac"));
+    let output = result.as_ref().expect("song.mflac should decrypt");
+    assert_eq!(output, &output_dir.path().join("song.flac"));
+    assert_eq!(fs::read(output).unwrap(), plaintext);
+
+    let (input, result) = &results[1];
+    assert_eq!(input, &input_dir.path().join("unknown.mflac"));
+    assert!(matches!(result, Err(QmcCryptoError::KeyNotFound(name)) if name == "unknown.mflac"));
+}
+
+#[test]
+fn test_decrypt_dir_names_ogg_flac() {
+    let input_dir = tempfile::tempdir().expect("failed to create temp dir");
+    let output_dir = tempfile::tempdir().expect("failed to create temp dir");
+
+    // OGG page with a FLAC-in-OGG identification header.
+    let mut plaintext = b"OggS\x00\x02".to_vec();
+    plaintext.resize(26, 0);
+    plaintext.extend_from_slice(&[1, 51]);
+    plaintext.extend_from_slice(b"\x7fFLAC\x01\x00\x00\x01fLaC\x00\x00\x00\x22");
+    plaintext.resize(0x3000, 0);
+    let mut encrypted = plaintext.clone();
+    QMCv2Cipher::new_from_ekey(EKEY)
+        .expect("failed to create cipher")
+        .decrypt(&mut encrypted, 0);
+    fs::write(input_dir.path().join("song.mgg"), &encrypted).unwrap();
+
+    let keymap = HashMap::from([("song.mgg".to_string(), EKEY.to_string())]);
+    let results = decrypt_dir_with_keymap(input_dir.path(), &keymap, output_dir.path());
+    let output = results[0].1.as_ref().expect("should decrypt");
+    assert_eq!(output, &output_dir.path().join("song.oga"));
+    assert_eq!(fs::read(output).unwrap(), plaintext);
+}
+
+#[test]
+fn test_decrypt_dir_keeps_dotted_stem() {
+    let input_dir = tempfile::tempdir().expect("failed to create temp dir");
+    let output_dir = tempfile::tempdir().expect("failed to create temp dir");
+
+    let plaintext = make_plaintext();
+    let mut encrypted = plaintext.clone();
+    QMCv2Cipher::new_from_ekey(EKEY)
+        .expect("failed to create cipher")
+        .decrypt(&mut encrypted, 0);
+    fs::write(input_dir.path().join("Mr. Brightside.mflac"), &encrypted).unwrap();
+    fs::write(input_dir.path().join("Mr. Jones.mflac"), &encrypted).unwrap();
+
+    let keymap = HashMap::from([
+        ("Mr. Brightside.mflac".to_string(), EKEY.to_string()),
+        ("Mr. Jones.mflac".to_string(), EKEY.to_string()),
+    ]);
+    let results = decrypt_dir_with_keymap(input_dir.path(), &keymap, output_dir.path());
+    let outputs = results
+        .into_iter()
+        .map(|(_, result)| result.expect("should decrypt"))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        outputs,
+        [
+            output_dir.path().join("Mr. Brightside.flac"),
+            output_dir.path().join("Mr. Jones.flac"),
+        ]
+    );
+}
+
+#[test]
+fn test_decrypt_file_to_file_empty_key() {
+    let dir = tempfile::tempdir().expect("failed to create temp dir");
+    let src = dir.path().join("song.mflac");
+    fs::write(&src, make_plaintext()).unwrap();
+
+    let err = decrypt_file_to_file(&src, &dir.path().join("song.flac"), &[]).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+    assert!(matches!(
+        err.into_inner()
+            .unwrap()
+            .downcast::<QmcCryptoError>()
+            .map(|err| *err),
+        Ok(QmcCryptoError::QMCV2MapKeyEmpty)
+    ));
+}
+
+#[test]
+fn test_read_sidecar_key() {
+    let dir = tempfile::tempdir().expect("failed to create temp dir");
+    let audio_path = dir.path().join("song.mflac");
+    fs::write(&audio_path, b"").unwrap();
+    fs::write(dir.path().join("song.mflac.key"), format!("{EKEY}\n")).unwrap();
+
+    let key = read_sidecar_key(&audio_path).expect("failed to read sidecar key");
+    assert_eq!(key, umc_qmc::ekey::decrypt(EKEY).unwrap());
+
+    let missing = read_sidecar_key(&dir.path().join("other.mflac"));
+    assert!(matches!(missing, Err(QmcCryptoError::IOError(_))));
+}
+
+#[test]
+fn test_decrypt_dir_with_sidecar_key() {
+    let input_dir = tempfile::tempdir().expect("failed to create temp dir");
+    let output_dir = tempfile::tempdir().expect("failed to create temp dir");
+
+    let plaintext = make_plaintext();
+    let mut encrypted = plaintext.clone();
+    QMCv2Cipher::new_from_ekey(EKEY)
+        .expect("failed to create cipher")
+        .decrypt(&mut encrypted, 0);
+
+    fs::write(input_dir.path().join("song.mflac"), &encrypted).unwrap();
+    fs::write(input_dir.path().join("song.mflac.key"), EKEY).unwrap();
+
+    let results = decrypt_dir_with_keymap(input_dir.path(), &HashMap::new(), output_dir.path());
+    assert_eq!(results.len(), 1);
+
+    let output = results[0].1.as_ref().expect("song.mflac should decrypt");
+    assert_eq!(fs::read(output).unwrap(), plaintext);
+}
+
+#[test]
+fn test_decoder_open_with_sidecar_key() {
+    let dir = tempfile::tempdir().expect("failed to create temp dir");
+    let plaintext = make_plaintext();
+    let mut encrypted = plaintext.clone();
+    QMCv2Cipher::new_from_ekey(EKEY)
+        .expect("failed to create cipher")
+        .decrypt(&mut encrypted, 0);
+
+    let audio_path = dir.path().join("song.mflac");
+    fs::write(&audio_path, &encrypted).unwrap();
+    assert!(matches!(
+        QmcDecoder::open(&audio_path),
+        Err(QmcCryptoError::KeyNotFound(_))
+    ));
+
+    fs::write(dir.path().join("song.mflac.key"), EKEY).unwrap();
+    let decoder = QmcDecoder::open(&audio_path).expect("should open with sidecar key");
+    assert!(!decoder.is_passthrough());
+    assert_eq!(decoder.into_vec(), plaintext);
+
+    // Running it again over the output is a no-op.
+    let output_path = dir.path().join("song.flac");
+    fs::write(&output_path, &plaintext).unwrap();
+    let decoder = QmcDecoder::open(&output_path).expect("should open plain flac");
+    assert!(decoder.is_passthrough());
+    assert_eq!(decoder.into_vec(), plaintext);
+}
+
+#[test]
+fn test_decrypt_pool_reuses_buffers() {
+    let dir = tempfile::tempdir().expect("failed to create temp dir");
+    let cipher = QMCv2Cipher::new_from_ekey(EKEY).expect("failed to create cipher");
+
+    let long = make_plaintext();
+    let short = b"ID3\x03\x00\x00\x00\x00\x00\x00short".to_vec();
+    for (name, plaintext) in [("long.mflac", &long), ("short.mflac", &short)] {
+        let mut encrypted = plaintext.clone();
+        cipher.decrypt(&mut encrypted, 0);
+        fs::write(dir.path().join(name), &encrypted).unwrap();
+    }
+
+    let mut pool = DecryptPool::new();
+    let first = pool
+        .decrypt_file(&dir.path().join("long.mflac"), &cipher)
+        .unwrap();
+    assert_eq!(first, long);
+    let first_ptr = first.as_ptr();
+    pool.put(first);
+    assert_eq!(pool.available(), 1);
+
+    // Reuses the allocation of the first, longer output; nothing of it is left over.
+    let second = pool
+        .decrypt_file(&dir.path().join("short.mflac"), &cipher)
+        .unwrap();
+    assert_eq!(second, short);
+    assert_eq!(second.as_ptr(), first_ptr);
+    assert_eq!(pool.available(), 0);
+
+    let missing = pool.decrypt_file(&dir.path().join("missing.mflac"), &cipher);
+    assert!(matches!(missing, Err(QmcCryptoError::IOError(_))));
+    assert_eq!(pool.available(), 1);
+}
+
+#[test]
+fn test_decrypt_dir_with_shared_pool() {
+    let input_dir = tempfile::tempdir().expect("failed to create temp dir");
+    let output_dir = tempfile::tempdir().expect("failed to create temp dir");
+
+    let plaintext = make_plaintext();
+    let mut encrypted = plaintext.clone();
+    QMCv2Cipher::new_from_ekey(EKEY)
+        .expect("failed to create cipher")
+        .decrypt(&mut encrypted, 0);
+    fs::write(input_dir.path().join("a.mflac"), &encrypted).unwrap();
+    fs::write(input_dir.path().join("b.mflac"), &encrypted).unwrap();
+
+    let keymap = HashMap::from([
+        ("a.mflac".to_string(), EKEY.to_string()),
+        ("b.mflac".to_string(), EKEY.to_string()),
+    ]);
+    let mut pool = DecryptPool::new();
+    for _ in 0..2 {
+        let results =
+            decrypt_dir_with_keymap_in(input_dir.path(), &keymap, output_dir.path(), &mut pool);
+        for (_, result) in results {
+            assert_eq!(fs::read(result.unwrap()).unwrap(), plaintext);
+        }
+        assert_eq!(pool.available(), 1);
+    }
+}
+
+#[test]
+fn test_decrypt_file_to_file() {
+    let dir = tempfile::tempdir().expect("failed to create temp dir");
+    let key = umc_qmc::ekey::decrypt(EKEY).unwrap();
+    let cipher = QMCv2Cipher::new(&key).unwrap();
+
+    // Spans a few stream chunks, and ends partway through one.
+    let mut plaintext = b"fLaC\x00\x00\x00\x22".to_vec();
+    plaintext.extend((0..0x280123).map(|i| (i * 7) as u8));
+    let mut encrypted = plaintext.clone();
+    cipher.decrypt(&mut encrypted, 0);
+
+    encrypted.extend_from_slice(&write_footer(
+        EKEY,
+        &FooterScheme::AndroidQTag { resource_id: 123 },
+    ));
+
+    let src = dir.path().join("song.mflac");
+    let dst = dir.path().join("song.flac");
+    fs::write(&src, &encrypted).unwrap();
+
+    let written = decrypt_file_to_file(&src, &dst, &key).expect("failed to decrypt");
+    assert_eq!(written, plaintext.len() as u64);
+
+    let mut expected = encrypted[..plaintext.len()].to_vec();
+    cipher.decrypt(&mut expected, 0);
+    assert_eq!(fs::read(&dst).unwrap(), expected);
+    assert_eq!(expected, plaintext);
+}
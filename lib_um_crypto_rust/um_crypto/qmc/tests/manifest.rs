@@ -0,0 +1,64 @@
+#![cfg(feature = "manifest")]
+
+use std::fs;
+use umc_qmc::manifest::decrypt_manifest;
+use umc_qmc::{QMCv2Cipher, QmcCryptoError};
+
+const EKEY: &str = include_str!("../src/v2_rc4/fixtures/ekey_rc4_512.txt");
+
+#[test]
+fn test_decrypt_manifest() {
+    let input_dir = tempfile::tempdir().expect("failed to create temp dir");
+    let output_dir = tempfile::tempdir().expect("failed to create temp dir");
+    let cipher = QMCv2Cipher::new_from_ekey(EKEY).expect("failed to create cipher");
+
+    let mut flac = b"fLaC\x00\x00\x00\x22".to_vec();
+    flac.extend((0..0x3000).map(|i| i as u8));
+    let mut ogg = b"OggS\x00\x02".to_vec();
+    ogg.extend((0..0x2000).map(|i| (i * 3) as u8));
+    for (name, plaintext) in [("a.mflac", &flac), ("b.mgg", &ogg)] {
+        let mut encrypted = plaintext.clone();
+        cipher.decrypt(&mut encrypted, 0);
+        fs::write(input_dir.path().join(name), &encrypted).unwrap();
+    }
+
+    let manifest = format!(
+        r#"[
+            {{"filename": "a.mflac", "ekey": "{EKEY}", "title": "First Song"}},
+            {{"filename": "b.mgg", "ekey": "{EKEY}"}},
+            {{"filename": "missing.mflac", "ekey": "{EKEY}", "title": "Missing"}},
+            {{"filename": "a.mflac", "ekey": "not an ekey", "title": "Bad key"}}
+        ]"#
+    );
+    let results = decrypt_manifest(&manifest, input_dir.path(), output_dir.path());
+    assert_eq!(results.len(), 4);
+
+    let (name, result) = &results[0];
+    assert_eq!(name, "a.mflac");
+    let output = result.as_ref().expect("a.mflac should decrypt");
+    assert_eq!(output, &output_dir.path().join("First Song.flac"));
+    assert_eq!(fs::read(output).unwrap(), flac);
+
+    let (name, result) = &results[1];
+    assert_eq!(name, "b.mgg");
+    let output = result.as_ref().expect("b.mgg should decrypt");
+    assert_eq!(output, &output_dir.path().join("b.ogg"));
+    assert_eq!(fs::read(output).unwrap(), ogg);
+
+    assert!(matches!(&results[2].1, Err(QmcCryptoError::IOError(_))));
+    assert!(matches!(
+        &results[3].1,
+        Err(QmcCryptoError::EKeyDecodeError(_))
+    ));
+}
+
+#[test]
+fn test_decrypt_manifest_invalid_json() {
+    let dir = tempfile::tempdir().expect("failed to create temp dir");
+    let results = decrypt_manifest("not json", dir.path(), dir.path());
+    assert_eq!(results.len(), 1);
+    assert!(matches!(
+        &results[0].1,
+        Err(QmcCryptoError::ManifestInvalidJson(_))
+    ));
+}
@@ -0,0 +1,57 @@
+//! `QMC2RC4Reader::read` should not allocate: it decrypts the caller's buffer in place.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::io::{Cursor, Read};
+use umc_qmc::v2_rc4::cipher::QMC2RC4;
+use umc_qmc::v2_rc4::reader::QMC2RC4Reader;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+struct CountingAllocator;
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.with(|count| count.set(count.get() + 1));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+fn allocations() -> usize {
+    ALLOCATIONS.with(|count| count.get())
+}
+
+#[test]
+fn test_reader_does_not_allocate_per_read() {
+    let key = (1..=512).map(|i| i as u8).collect::<Vec<_>>();
+    let data = vec![0x5Au8; 0x10000];
+
+    let mut expected = data.clone();
+    QMC2RC4::new(&key).decrypt(&mut expected, 0);
+
+    let mut reader = QMC2RC4Reader::new(Cursor::new(data), QMC2RC4::new(&key));
+    let mut output = Vec::with_capacity(expected.len());
+    let mut buf = [0u8; 0x333];
+
+    let before = allocations();
+    loop {
+        let n = reader.read(&mut buf).unwrap();
+        if n == 0 {
+            break;
+        }
+        output.extend_from_slice(&buf[..n]);
+    }
+    let after = allocations();
+
+    assert_eq!(after - before, 0, "read() should not allocate");
+    assert_eq!(output, expected);
+}
@@ -103,7 +103,7 @@ impl QrcDes {
     }
 
     pub fn transform_bytes(&self, data: &mut [u8]) -> Result<(), QrcError> {
-        if data.len() % 8 != 0 {
+        if !data.len().is_multiple_of(8) {
             Err(QrcError::QRCDesInputSizeError)?;
         }
 
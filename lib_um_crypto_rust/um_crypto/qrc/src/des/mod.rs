@@ -1,4 +1,5 @@
 mod constants;
+#[allow(clippy::module_inception)]
 mod des;
 mod utils;
 
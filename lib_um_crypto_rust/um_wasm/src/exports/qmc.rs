@@ -1,8 +1,9 @@
 use crate::errors::map_js_error;
+use js_sys::{Function, Uint8Array};
 use umc_qmc::footer::{Data as FooterData, FooterParseError};
 use umc_qmc::QMCv2Cipher;
 use wasm_bindgen::prelude::wasm_bindgen;
-use wasm_bindgen::JsError;
+use wasm_bindgen::{JsError, JsValue};
 
 /// QMC1 (qmcflac) decipher, decrypt buffer at given offset.
 #[wasm_bindgen(js_name=decryptQMC1)]
@@ -27,6 +28,31 @@ impl JsQMC2 {
     pub fn decrypt(&self, buffer: &mut [u8], offset: usize) {
         self.0.decrypt(buffer, offset)
     }
+
+    /// Decrypt buffer at given offset, calling `callback` with each decrypted chunk
+    ///   (a `Uint8Array` of up to `chunk_size` bytes), in order.
+    /// Each chunk is a new copy, owned by the callback.
+    #[wasm_bindgen(js_name=decryptStreaming)]
+    pub fn decrypt_streaming(
+        &self,
+        buffer: &[u8],
+        offset: usize,
+        chunk_size: usize,
+        callback: &Function,
+    ) -> Result<(), JsValue> {
+        if chunk_size == 0 {
+            return Err(JsError::new("chunk_size should not be 0").into());
+        }
+
+        let mut chunk = vec![0u8; chunk_size.min(buffer.len())];
+        for (i, src) in buffer.chunks(chunk_size).enumerate() {
+            let chunk = &mut chunk[..src.len()];
+            chunk.copy_from_slice(src);
+            self.0.decrypt(chunk, offset + i * chunk_size);
+            callback.call1(&JsValue::NULL, &Uint8Array::from(&chunk[..]))?;
+        }
+        Ok(())
+    }
 }
 
 /// QMC Footer.
@@ -11,3 +11,29 @@ wasm_bindgen_test_configure!(run_in_browser);
 fn pass() {
     assert_eq!(1 + 1, 2);
 }
+
+#[wasm_bindgen_test]
+fn qmc2_decrypt_streaming() {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use um_wasm::exports::qmc::JsQMC2;
+    use wasm_bindgen::prelude::*;
+
+    const EKEY: &str = include_str!("../../um_crypto/qmc/src/v2_rc4/fixtures/ekey_rc4_512.txt");
+    let cipher = JsQMC2::new(EKEY).unwrap();
+    let data = (0..0x3000).map(|i| (i * 3) as u8).collect::<Vec<_>>();
+    let mut expected = data.clone();
+    cipher.decrypt(&mut expected, 0x10);
+
+    let actual = Rc::new(RefCell::new(vec![]));
+    let callback = {
+        let actual = actual.clone();
+        Closure::<dyn FnMut(js_sys::Uint8Array)>::new(move |chunk: js_sys::Uint8Array| {
+            actual.borrow_mut().extend(chunk.to_vec());
+        })
+    };
+    cipher
+        .decrypt_streaming(&data, 0x10, 0x1000, callback.as_ref().unchecked_ref())
+        .unwrap();
+    assert_eq!(*actual.borrow(), expected);
+}